@@ -0,0 +1,144 @@
+//! Splitting the UART stream into text and records, and rendering records.
+
+use crate::binlog_wire::{cobs_decode, render, unescape, Record, MAX_FRAME};
+use crate::elf::Image;
+
+/// A call site's `Entry`, as read from the ELF image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub level: u32,
+    pub format: String,
+    pub target: String,
+}
+
+impl Entry {
+    /// Read the entry at `addr`. The layout matches `binlog::Entry` on the
+    /// ESP32: five little-endian 32-bit words, the strings' addresses being
+    /// in the strings section.
+    pub fn read(image: &Image, addr: u32) -> Option<Entry> {
+        let addr = addr as u64;
+        let word = |i: u64| image.read_u32_le(addr + 4 * i);
+        let string = |ptr: u32, len: u32| {
+            let bytes = image.read_string(ptr as u64, len as u64)?;
+            Some(String::from_utf8_lossy(bytes).into_owned())
+        };
+        Some(Entry {
+            level: word(0)?,
+            format: string(word(1)?, word(2)?)?,
+            target: string(word(3)?, word(4)?)?,
+        })
+    }
+}
+
+/// What the stream contained.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Output {
+    /// Bytes that aren't part of a record, such as ESP-IDF's text logs.
+    Text(Vec<u8>),
+    /// A rendered record, formatted like ESP-IDF's log lines.
+    Line(String),
+}
+
+fn level_letter(level: u32) -> char {
+    match level {
+        1 => 'E',
+        2 => 'W',
+        3 => 'I',
+        4 => 'D',
+        _ => 'V',
+    }
+}
+
+/// Render a record with its entry.
+pub fn render_record(entry: &Entry, record: &Record) -> String {
+    let mut line = format!(
+        "{} ({}) {}: {}",
+        level_letter(entry.level),
+        record.timestamp,
+        entry.target,
+        render(&entry.format, &record.args)
+    );
+    if record.truncated {
+        line.push_str(" [truncated]");
+    }
+    line
+}
+
+fn text(bytes: Vec<u8>) -> Option<Output> {
+    if bytes.is_empty() {
+        None
+    } else {
+        Some(Output::Text(bytes))
+    }
+}
+
+/// Incremental decoder for the UART stream.
+pub struct StreamDecoder<'a> {
+    image: &'a Image,
+    pending: Vec<u8>,
+    /// Whether `pending` follows an opening delimiter, rather than being
+    /// text.
+    in_frame: bool,
+}
+
+impl<'a> StreamDecoder<'a> {
+    pub fn new(image: &'a Image) -> Self {
+        StreamDecoder {
+            image,
+            pending: Vec::new(),
+            in_frame: false,
+        }
+    }
+
+    /// Feed more bytes, returning everything completed so far. Text is
+    /// returned a line at a time.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Output> {
+        let mut out = Vec::new();
+        for &b in bytes {
+            if b == 0 {
+                let chunk = std::mem::take(&mut self.pending);
+                if !self.in_frame {
+                    out.extend(text(chunk));
+                    self.in_frame = true;
+                } else if !chunk.is_empty() {
+                    let output = self.decode_chunk(chunk);
+                    // A chunk that isn't a record was text with a stray
+                    // `0x00` in it, so this delimiter opens the next frame.
+                    self.in_frame = matches!(output, Output::Text(_));
+                    out.push(output);
+                }
+                continue;
+            }
+
+            self.pending.push(b);
+            if self.in_frame && self.pending.len() > MAX_FRAME {
+                // The closing delimiter was lost; go back to text.
+                self.in_frame = false;
+            }
+            if !self.in_frame && b == b'\n' {
+                out.extend(text(std::mem::take(&mut self.pending)));
+            }
+        }
+        out
+    }
+
+    /// Return whatever is buffered as text, e.g. at the end of the input.
+    pub fn finish(&mut self) -> Option<Output> {
+        self.in_frame = false;
+        text(std::mem::take(&mut self.pending))
+    }
+
+    fn decode_chunk(&self, chunk: Vec<u8>) -> Output {
+        let line = unescape(&chunk)
+            .and_then(|encoded| cobs_decode(&encoded))
+            .and_then(|payload| Record::parse(&payload))
+            .and_then(|record| {
+                let entry = Entry::read(self.image, record.entry)?;
+                Some(render_record(&entry, &record))
+            });
+        match line {
+            Some(line) => Output::Line(line),
+            None => Output::Text(chunk),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Just enough ELF parsing to read the firmware's loaded sections, and the
+//! `binlog!` strings that aren't loaded, by address.
+
+use std::fmt;
+
+#[derive(Debug)]
+pub struct Error(&'static str);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid ELF file: {}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+const SHT_PROGBITS: u32 = 1;
+const SHF_ALLOC: u64 = 0x2;
+
+/// The output section `main/binlog.ld` puts the strings in.
+pub const STRINGS_SECTION: &[u8] = b".binlog_strings";
+
+/// A section's contents at its load address.
+#[derive(Clone, Debug)]
+pub struct Section {
+    pub addr: u64,
+    pub data: Vec<u8>,
+}
+
+impl Section {
+    fn read(&self, addr: u64, len: u64) -> Option<&[u8]> {
+        let start = addr.checked_sub(self.addr)?;
+        let end = start.checked_add(len)?;
+        self.data.get(start as usize..end as usize)
+    }
+}
+
+/// The loaded contents of an executable.
+#[derive(Clone, Debug, Default)]
+pub struct Image {
+    pub sections: Vec<Section>,
+    /// The `binlog!` strings, which have addresses of their own.
+    pub strings: Option<Section>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: u64, len: u64) -> Result<&'a [u8], Error> {
+        let start = offset as usize;
+        let end = start
+            .checked_add(len as usize)
+            .ok_or(Error("offset overflow"))?;
+        self.data.get(start..end).ok_or(Error("truncated"))
+    }
+
+    fn u16(&self, offset: u64) -> Result<u16, Error> {
+        let b = self.bytes(offset, 2)?;
+        let b = [b[0], b[1]];
+        Ok(if self.little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&self, offset: u64) -> Result<u32, Error> {
+        let b = self.bytes(offset, 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Ok(if self.little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    fn u64(&self, offset: u64) -> Result<u64, Error> {
+        let b = self.bytes(offset, 8)?;
+        let mut a = [0; 8];
+        a.copy_from_slice(b);
+        Ok(if self.little_endian {
+            u64::from_le_bytes(a)
+        } else {
+            u64::from_be_bytes(a)
+        })
+    }
+}
+
+impl Image {
+    /// Collect the allocated `PROGBITS` sections of an ELF file, and the
+    /// strings section.
+    pub fn parse(data: &[u8]) -> Result<Image, Error> {
+        if data.get(..4) != Some(b"\x7fELF") {
+            return Err(Error("bad magic"));
+        }
+        let is_64 = match data.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(Error("bad class")),
+        };
+        let little_endian = match data.get(5) {
+            Some(1) => true,
+            Some(2) => false,
+            _ => return Err(Error("bad data encoding")),
+        };
+        let r = Reader {
+            data,
+            little_endian,
+        };
+
+        let (shoff, shentsize, shnum, shstrndx) = if is_64 {
+            (r.u64(0x28)?, r.u16(0x3a)?, r.u16(0x3c)?, r.u16(0x3e)?)
+        } else {
+            (
+                r.u32(0x20)? as u64,
+                r.u16(0x2e)?,
+                r.u16(0x30)?,
+                r.u16(0x32)?,
+            )
+        };
+        let header = |i: u64| -> Result<(u32, u32, u64, u64, u64, u64), Error> {
+            let sh = shoff + i * shentsize as u64;
+            Ok(if is_64 {
+                (
+                    r.u32(sh)?,
+                    r.u32(sh + 0x04)?,
+                    r.u64(sh + 0x08)?,
+                    r.u64(sh + 0x10)?,
+                    r.u64(sh + 0x18)?,
+                    r.u64(sh + 0x20)?,
+                )
+            } else {
+                (
+                    r.u32(sh)?,
+                    r.u32(sh + 0x04)?,
+                    r.u32(sh + 0x08)? as u64,
+                    r.u32(sh + 0x0c)? as u64,
+                    r.u32(sh + 0x10)? as u64,
+                    r.u32(sh + 0x14)? as u64,
+                )
+            })
+        };
+        // Only needed for the strings section, so a missing table isn't an
+        // error.
+        let names = header(shstrndx as u64)
+            .and_then(|(_, _, _, _, offset, size)| r.bytes(offset, size))
+            .unwrap_or(&[]);
+
+        let mut image = Image::default();
+        for i in 0..shnum as u64 {
+            let (name, ty, flags, addr, offset, size) = header(i)?;
+            if ty != SHT_PROGBITS || size == 0 {
+                continue;
+            }
+            let section = Section {
+                addr,
+                data: r.bytes(offset, size)?.to_vec(),
+            };
+            if flags & SHF_ALLOC != 0 {
+                image.sections.push(section);
+            } else if names
+                .get(name as usize..)
+                .and_then(|n| n.split(|&b| b == 0).next())
+                == Some(STRINGS_SECTION)
+            {
+                image.strings = Some(section);
+            }
+        }
+        Ok(image)
+    }
+
+    /// Read `len` bytes at load address `addr`, if a single section holds
+    /// them all.
+    pub fn read(&self, addr: u64, len: u64) -> Option<&[u8]> {
+        self.sections.iter().find_map(|s| s.read(addr, len))
+    }
+
+    /// Read `len` bytes of the strings section at address `addr`.
+    pub fn read_string(&self, addr: u64, len: u64) -> Option<&[u8]> {
+        self.strings.as_ref()?.read(addr, len)
+    }
+
+    pub fn read_u32_le(&self, addr: u64) -> Option<u32> {
+        let b = self.read(addr, 4)?;
+        Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
@@ -0,0 +1,8 @@
+//! Decoder for the firmware's compact binary log.
+
+extern crate alloc;
+
+#[path = "../../main/src/binlog_wire.rs"]
+pub mod binlog_wire;
+pub mod decode;
+pub mod elf;
@@ -0,0 +1,56 @@
+//! Decode the firmware's compact binary log.
+//!
+//! Usage: `binlog-decoder <firmware.elf> [input]`
+//!
+//! `input` is a capture file or a serial device, and defaults to stdin.
+//! Plain text in the stream, such as ESP-IDF's own logs, is passed through.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+use binlog_decoder::decode::{Output, StreamDecoder};
+use binlog_decoder::elf::Image;
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let elf_path = args
+        .next()
+        .ok_or("usage: binlog-decoder <firmware.elf> [input]")?;
+    let image = Image::parse(&std::fs::read(elf_path)?)?;
+
+    let mut input: Box<dyn Read> = match args.next() {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::stdin()),
+    };
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut decoder = StreamDecoder::new(&image);
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = input.read(&mut buf)?;
+        let outputs = if n == 0 {
+            decoder.finish().into_iter().collect()
+        } else {
+            decoder.feed(&buf[..n])
+        };
+        for output in outputs {
+            match output {
+                Output::Text(text) => stdout.write_all(&text)?,
+                Output::Line(line) => writeln!(stdout, "{}", line)?,
+            }
+        }
+        stdout.flush()?;
+        if n == 0 {
+            return Ok(());
+        }
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("binlog-decoder: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,262 @@
+use binlog_decoder::binlog_wire::{
+    cobs_decode, cobs_encode, Arg, Format, PayloadWriter, Record, ESCAPE, MAX_FRAME, MAX_PAYLOAD,
+};
+use binlog_decoder::decode::{Output, StreamDecoder};
+use binlog_decoder::elf::{Image, Section};
+
+const ENTRY: u32 = 0x3f40_0000;
+const STRINGS: u32 = 0x10;
+
+/// An image holding one `Entry` at `ENTRY`, laid out like the ESP32's.
+fn image(level: u32, format: &str, target: &str) -> Image {
+    let mut strings = Vec::new();
+    strings.extend_from_slice(format.as_bytes());
+    strings.extend_from_slice(target.as_bytes());
+
+    let mut entry = Vec::new();
+    for word in &[
+        level,
+        STRINGS,
+        format.len() as u32,
+        STRINGS + format.len() as u32,
+        target.len() as u32,
+    ] {
+        entry.extend_from_slice(&word.to_le_bytes());
+    }
+
+    Image {
+        sections: vec![Section {
+            addr: ENTRY as u64,
+            data: entry,
+        }],
+        strings: Some(Section {
+            addr: STRINGS as u64,
+            data: strings,
+        }),
+    }
+}
+
+fn frame(w: &PayloadWriter) -> Vec<u8> {
+    let mut out = [0u8; MAX_FRAME];
+    let n = w.frame(&mut out);
+    out[..n].to_vec()
+}
+
+#[test]
+fn cobs_round_trips() {
+    let cases: Vec<Vec<u8>> = vec![
+        vec![],
+        vec![0],
+        vec![0, 0],
+        vec![1, 0, 2],
+        (1..=253).collect(),
+        (1..=254).collect(),
+        (0..=255).cycle().take(600).collect(),
+    ];
+    for data in cases {
+        let mut out = vec![0u8; data.len() + data.len() / 254 + 1];
+        let n = cobs_encode(&data, &mut out);
+        assert!(!out[..n].contains(&0));
+        assert_eq!(cobs_decode(&out[..n]).unwrap(), data);
+    }
+}
+
+#[test]
+fn record_round_trips() {
+    let mut w = PayloadWriter::new(ENTRY, 123_456);
+    42u8.encode(&mut w);
+    (-7i32).encode(&mut w);
+    u64::MAX.encode(&mut w);
+    i64::MIN.encode(&mut w);
+    1.5f32.encode(&mut w);
+    true.encode(&mut w);
+    "hi".encode(&mut w);
+    [0u8, 1, 2][..].encode(&mut w);
+
+    let record = Record::parse(w.payload()).unwrap();
+    assert_eq!(
+        record,
+        Record {
+            entry: ENTRY,
+            timestamp: 123_456,
+            args: vec![
+                Arg::Unsigned(42),
+                Arg::Signed(-7),
+                Arg::Unsigned(u64::MAX),
+                Arg::Signed(i64::MIN),
+                Arg::F32(1.5),
+                Arg::Bool(true),
+                Arg::Str("hi".into()),
+                Arg::Bytes(vec![0, 1, 2]),
+            ],
+            truncated: false,
+        }
+    );
+}
+
+#[test]
+fn oversized_arguments_are_dropped() {
+    let mut w = PayloadWriter::new(ENTRY, 0);
+    1u8.encode(&mut w);
+    "x".repeat(MAX_PAYLOAD).as_str().encode(&mut w);
+    2u8.encode(&mut w);
+    assert!(w.is_truncated());
+
+    let record = Record::parse(w.payload()).unwrap();
+    assert!(record.truncated);
+    assert_eq!(record.args, vec![Arg::Unsigned(1)]);
+}
+
+#[test]
+fn stream_decodes_records_between_text() {
+    let image = image(3, "rssi {} on {:#x}: {:?}", "firmware::sniffer");
+    let mut w = PayloadWriter::new(ENTRY, 1500);
+    (-60i8).encode(&mut w);
+    0xabu32.encode(&mut w);
+    "ok".encode(&mut w);
+
+    let mut stream = b"I (10) boot: hello\n".to_vec();
+    stream.extend(frame(&w));
+    stream.extend_from_slice(b"tail");
+
+    // Feed one byte at a time to exercise resynchronization.
+    let mut decoder = StreamDecoder::new(&image);
+    let mut outputs = Vec::new();
+    for b in &stream {
+        outputs.extend(decoder.feed(&[*b]));
+    }
+    outputs.extend(decoder.finish());
+
+    assert_eq!(
+        outputs,
+        vec![
+            Output::Text(b"I (10) boot: hello\n".to_vec()),
+            Output::Line("I (1500) firmware::sniffer: rssi -60 on 0xab: \"ok\"".into()),
+            Output::Text(b"tail".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn text_is_returned_a_line_at_a_time() {
+    let image = image(3, "x", "t");
+    let mut decoder = StreamDecoder::new(&image);
+    assert_eq!(
+        decoder.feed(b"I (10) wifi: start\nI (11) wifi: conn"),
+        vec![Output::Text(b"I (10) wifi: start\n".to_vec())]
+    );
+    assert_eq!(
+        decoder.feed(b"ected\n"),
+        vec![Output::Text(b"I (11) wifi: connected\n".to_vec())]
+    );
+    assert_eq!(decoder.finish(), None);
+}
+
+#[test]
+fn lost_closing_delimiter() {
+    let image = image(3, "n {}", "t");
+    let mut w = PayloadWriter::new(ENTRY, 7);
+    1u8.encode(&mut w);
+    let frame = frame(&w);
+
+    // A frame cut short by a reset, followed by the boot log.
+    let mut stream = frame[..frame.len() - 1].to_vec();
+    stream.extend_from_slice(&[b'.'; MAX_FRAME]);
+    stream.extend_from_slice(b"\nrst\n");
+    stream.extend(&frame);
+
+    let outputs = StreamDecoder::new(&image).feed(&stream);
+    assert_eq!(outputs.len(), 3);
+    assert!(matches!(&outputs[0], Output::Text(t) if t.ends_with(b".\n")));
+    assert_eq!(outputs[1], Output::Text(b"rst\n".to_vec()));
+    assert_eq!(outputs[2], Output::Line("I (7) t: n 1".into()));
+}
+
+#[test]
+fn line_feeds_survive_crlf_translation() {
+    let entry = ENTRY + 0x0a;
+    let mut image = image(3, "{} {}", "t");
+    image.sections[0].addr += 0x0a;
+    let mut w = PayloadWriter::new(entry, 0x0a0a);
+    0x0a0au32.encode(&mut w);
+    [0x0a, ESCAPE, 0x0d][..].encode(&mut w);
+    let frame = frame(&w);
+    assert!(!frame.contains(&b'\n'));
+
+    // What the console's VFS does to the frame.
+    let mut stream = Vec::new();
+    for &b in &frame {
+        if b == b'\n' {
+            stream.push(b'\r');
+        }
+        stream.push(b);
+    }
+    let outputs = StreamDecoder::new(&image).feed(&stream);
+    assert_eq!(
+        outputs,
+        vec![Output::Line("I (2570) t: 2570 [10, 125, 13]".into())]
+    );
+}
+
+#[test]
+fn unknown_entries_pass_through_as_text() {
+    let image = image(1, "boom", "t");
+    let w = PayloadWriter::new(ENTRY + 4, 0);
+    let mut decoder = StreamDecoder::new(&image);
+    let outputs = decoder.feed(&frame(&w));
+    assert_eq!(outputs.len(), 1);
+    assert!(matches!(outputs[0], Output::Text(_)));
+}
+
+#[test]
+fn truncated_records_are_marked() {
+    let image = image(2, "{} {}", "t");
+    let mut w = PayloadWriter::new(ENTRY, 5);
+    "y".repeat(MAX_PAYLOAD).as_str().encode(&mut w);
+    let outputs = StreamDecoder::new(&image).feed(&frame(&w));
+    assert_eq!(
+        outputs,
+        vec![Output::Line("W (5) t: {?} {?} [truncated]".into())]
+    );
+}
+
+/// A 32-bit section header.
+fn section_header(name: u32, ty: u32, flags: u32, addr: u32, offset: u32, size: u32) -> [u8; 40] {
+    let mut sh = [0u8; 40];
+    for (i, word) in [name, ty, flags, addr, offset, size].iter().enumerate() {
+        sh[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    sh
+}
+
+#[test]
+fn parses_elf32_sections() {
+    // A little-endian ELF32 header, then the contents of a loaded section,
+    // the strings section and the section names.
+    let mut elf = vec![0u8; 0x34];
+    elf[..6].copy_from_slice(b"\x7fELF\x01\x01");
+    let data_off = elf.len() as u32;
+    elf.extend_from_slice(b"abcd");
+    let strings_off = elf.len() as u32;
+    elf.extend_from_slice(b"fmt");
+    let names_off = elf.len() as u32;
+    let names = b"\0.binlog_strings\0.shstrtab\0";
+    elf.extend_from_slice(names);
+
+    let shoff = elf.len() as u32;
+    elf[0x20..0x24].copy_from_slice(&shoff.to_le_bytes());
+    elf[0x2e..0x30].copy_from_slice(&40u16.to_le_bytes());
+    elf[0x30..0x32].copy_from_slice(&4u16.to_le_bytes());
+    elf[0x32..0x34].copy_from_slice(&3u16.to_le_bytes());
+    elf.extend_from_slice(&[0; 40]);
+    elf.extend_from_slice(&section_header(0, 1, 2, 0x3f40_0000, data_off, 4));
+    elf.extend_from_slice(&section_header(1, 1, 0, 0, strings_off, 3));
+    elf.extend_from_slice(&section_header(17, 3, 0, 0, names_off, names.len() as u32));
+
+    let image = Image::parse(&elf).unwrap();
+    assert_eq!(image.read(0x3f40_0001, 2), Some(&b"bc"[..]));
+    assert_eq!(image.read(0x3f40_0003, 2), None);
+    assert_eq!(image.read(0, 3), None);
+    assert_eq!(image.read_string(1, 2), Some(&b"mt"[..]));
+    assert!(Image::parse(b"not an elf").is_err());
+}
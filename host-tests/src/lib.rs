@@ -0,0 +1,31 @@
+//! The firmware's modules that don't depend on `esp-idf`, built for the
+//! host so they can be tested.
+
+extern crate alloc;
+
+// The firmware spells these `max_value()` throughout.
+#[allow(clippy::legacy_numeric_constants)]
+#[path = "../../main/src/alloc_tracker.rs"]
+pub mod alloc_tracker;
+#[path = "../../main/src/button_gesture.rs"]
+pub mod button_gesture;
+#[path = "../../main/src/dns.rs"]
+pub mod dns;
+#[allow(clippy::legacy_numeric_constants)]
+#[path = "../../main/src/http.rs"]
+pub mod http;
+#[path = "../../main/src/ieee80211.rs"]
+pub mod ieee80211;
+// The firmware's toolchain predates `div_ceil`.
+#[allow(clippy::manual_div_ceil)]
+#[path = "../../main/src/modbus_rtu.rs"]
+pub mod modbus_rtu;
+#[path = "../../main/src/net.rs"]
+pub mod net;
+#[path = "../../main/src/pcap.rs"]
+pub mod pcap;
+#[allow(clippy::legacy_numeric_constants, clippy::new_ret_no_self)]
+#[path = "../../main/src/persistent_config.rs"]
+pub mod persistent_config;
+#[path = "../../main/src/repl.rs"]
+pub mod repl;
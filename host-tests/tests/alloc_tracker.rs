@@ -0,0 +1,199 @@
+//! The tracker's bookkeeping, on top of the system allocator. Each test
+//! uses its own tracker, and threads stand in for tasks.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use host_tests::alloc_tracker::{
+    size_class, size_class_limit, Platform, TrackingAllocator, MAX_TRACKED, SIZE_CLASSES,
+};
+
+struct Host;
+
+static LOCK: Mutex<()> = Mutex::new(());
+static NEXT_TASK: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    static TASK: Cell<usize> = const { Cell::new(0) };
+}
+
+impl Platform for Host {
+    fn current_task() -> usize {
+        TASK.with(|task| {
+            if task.get() == 0 {
+                task.set(NEXT_TASK.fetch_add(1, Ordering::Relaxed));
+            }
+            task.get()
+        })
+    }
+
+    fn locked<R>(f: impl FnOnce() -> R) -> R {
+        let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        f()
+    }
+}
+
+type Tracker = TrackingAllocator<System, Host>;
+
+fn alloc(tracker: &Tracker, size: usize) -> *mut u8 {
+    let ptr = unsafe { tracker.alloc(Layout::from_size_align(size, 4).unwrap()) };
+    assert!(!ptr.is_null());
+    ptr
+}
+
+fn free(tracker: &Tracker, ptr: *mut u8, size: usize) {
+    unsafe { tracker.dealloc(ptr, Layout::from_size_align(size, 4).unwrap()) };
+}
+
+#[test]
+fn size_classes() {
+    assert_eq!(size_class(0), 0);
+    assert_eq!(size_class(16), 0);
+    assert_eq!(size_class(17), 1);
+    assert_eq!(size_class(100), 3);
+    assert_eq!(size_class(16 << 10), 10);
+    assert_eq!(size_class(usize::MAX), SIZE_CLASSES - 1);
+    assert_eq!(size_class_limit(3), Some(128));
+    assert_eq!(size_class_limit(SIZE_CLASSES - 1), None);
+}
+
+#[test]
+fn counters() {
+    let tracker = Tracker::new(System);
+    let a = alloc(&tracker, 10);
+    let b = alloc(&tracker, 100);
+    free(&tracker, a, 10);
+
+    let stats = tracker.stats();
+    assert_eq!(stats.total.allocs, 2);
+    assert_eq!(stats.total.frees, 1);
+    assert_eq!(stats.total.live_allocs(), 1);
+    assert_eq!(stats.total.live_bytes, 100);
+    assert_eq!(stats.total.peak_bytes, 110);
+    assert_eq!(stats.size_classes[0].live_allocs(), 0);
+    assert_eq!(stats.size_classes[3].live_bytes, 100);
+    assert_eq!(stats.untracked, 0);
+
+    let tasks = tracker.tasks();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].task, Host::current_task());
+    assert_eq!(tasks[0].counters, stats.total);
+    free(&tracker, b, 100);
+}
+
+#[test]
+fn frees_are_charged_to_the_allocating_task() {
+    let tracker = Tracker::new(System);
+    let ptr = alloc(&tracker, 64) as usize;
+    std::thread::scope(|scope| {
+        scope.spawn(|| free(&tracker, ptr as *mut u8, 64));
+    });
+
+    // The freeing thread isn't charged, so it never gets a slot.
+    let tasks = tracker.tasks();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].task, Host::current_task());
+    assert_eq!(tasks[0].counters.frees, 1);
+    assert_eq!(tasks[0].counters.live_bytes, 0);
+}
+
+#[test]
+fn tags() {
+    let tracker = Tracker::new(System);
+    let a = alloc(&tracker, 8);
+    let b = tracker.tagged("wifi", || alloc(&tracker, 8));
+    assert_eq!(tracker.set_tag("http"), "");
+    let c = alloc(&tracker, 8);
+
+    let tags: Vec<_> = tracker.outstanding().iter().map(|a| a.tag).collect();
+    assert_eq!(tags, ["", "wifi", "http"]);
+    assert_eq!(tracker.tasks()[0].tag, "http");
+    for ptr in [a, b, c].iter() {
+        free(&tracker, *ptr, 8);
+    }
+    assert!(tracker.outstanding().is_empty());
+}
+
+#[test]
+fn realloc_keeps_its_place() {
+    let tracker = Tracker::new(System);
+    let layout = Layout::from_size_align(16, 4).unwrap();
+    let a = alloc(&tracker, 16);
+    let b = alloc(&tracker, 16);
+    let a = unsafe { tracker.realloc(a, layout, 1000) };
+
+    let outstanding = tracker.outstanding();
+    assert_eq!(outstanding.len(), 2);
+    assert_eq!(
+        (outstanding[0].ptr, outstanding[0].size),
+        (a as usize, 1000)
+    );
+    assert_eq!(outstanding[1].ptr, b as usize);
+    let stats = tracker.stats();
+    assert_eq!(stats.total.live_bytes, 1016);
+    assert_eq!(stats.size_classes[0].live_allocs(), 1);
+    free(&tracker, a, 1000);
+    free(&tracker, b, 16);
+}
+
+#[test]
+fn leaks_between_checkpoints() {
+    let tracker = Tracker::new(System);
+    let before_start = alloc(&tracker, 8);
+    let from = tracker.checkpoint();
+    let leaked = alloc(&tracker, 32);
+    let freed = alloc(&tracker, 16);
+    free(&tracker, freed, 16);
+    free(&tracker, before_start, 8);
+    let to = tracker.checkpoint();
+    let after_end = alloc(&tracker, 8);
+
+    let report = tracker.leaks(&from, &to);
+    assert_eq!(report.allocs_delta, 0);
+    assert_eq!(report.bytes_delta, 24);
+    assert_eq!(report.outstanding.len(), 1);
+    assert_eq!(report.outstanding[0].ptr, leaked as usize);
+    assert!(tracker.leaks(&to, &to).outstanding.is_empty());
+    free(&tracker, leaked, 32);
+    free(&tracker, after_end, 8);
+}
+
+#[test]
+fn sequence_numbers_wrap() {
+    let tracker = Tracker::new(System);
+    tracker.set_next_seq(u32::MAX - 1);
+    let old = alloc(&tracker, 8);
+    let from = tracker.checkpoint();
+    // Numbered `u32::MAX`, 0 and 1.
+    let ptrs: Vec<_> = (0..3).map(|_| alloc(&tracker, 8)).collect();
+    let to = tracker.checkpoint();
+
+    let outstanding: Vec<_> = tracker.leaks(&from, &to).outstanding;
+    let seqs: Vec<_> = outstanding.iter().map(|a| a.seq).collect();
+    assert_eq!(seqs, [u32::MAX, 0, 1]);
+    let all: Vec<_> = tracker.outstanding().iter().map(|a| a.seq).collect();
+    assert_eq!(all, [u32::MAX - 1, u32::MAX, 0, 1]);
+
+    free(&tracker, old, 8);
+    for ptr in ptrs {
+        free(&tracker, ptr, 8);
+    }
+}
+
+#[test]
+fn untracked_allocations() {
+    let tracker = Tracker::new(System);
+    let ptrs: Vec<_> = (0..MAX_TRACKED + 2).map(|_| alloc(&tracker, 4)).collect();
+    assert_eq!(tracker.outstanding().len(), MAX_TRACKED);
+    assert_eq!(tracker.stats().untracked, 2);
+
+    for ptr in ptrs.iter().rev() {
+        free(&tracker, *ptr, 4);
+    }
+    let stats = tracker.stats();
+    assert_eq!(stats.untracked, 0);
+    assert_eq!(stats.total.live_allocs(), 0);
+    assert!(tracker.outstanding().is_empty());
+}
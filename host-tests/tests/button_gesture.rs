@@ -0,0 +1,185 @@
+//! Scripted button presses, with the default timings: 20 ms debounce,
+//! 300 ms double click and 800 ms long press.
+
+use host_tests::button_gesture::{ButtonEvent, Recognizer, Timings};
+
+use ButtonEvent::*;
+
+/// Feed `edges`, as (time, pressed), then run until `end`. Returns each
+/// event with the time it was sent.
+fn run(timings: Timings, edges: &[(u64, bool)], end: u64) -> Vec<(u64, ButtonEvent)> {
+    let mut recognizer = Recognizer::new(timings);
+    let mut events = Vec::new();
+    let mut edges = edges.iter().peekable();
+    loop {
+        let deadline = recognizer.next_deadline();
+        // Step to whichever comes first: an edge, a deadline or the end.
+        match edges.peek() {
+            Some(&&(at, pressed)) if deadline.filter(|&d| d <= at).is_none() => {
+                recognizer.edge(at, pressed, &mut |e| events.push((at, e)));
+                edges.next();
+            }
+            _ => match deadline {
+                Some(d) if d <= end => {
+                    recognizer.update(d, &mut |e| events.push((d, e)));
+                }
+                _ => break,
+            },
+        }
+    }
+    events
+}
+
+fn press(at: u64, len: u64) -> [(u64, bool); 2] {
+    [(at, true), (at + len, false)]
+}
+
+#[test]
+fn click() {
+    let events = run(Timings::default(), &press(100, 100), 2000);
+    assert_eq!(events, [(120, Pressed), (220, Released), (520, Click)]);
+}
+
+#[test]
+fn click_without_double_click() {
+    let timings = Timings {
+        double_click_ms: 0,
+        ..Timings::default()
+    };
+    let events = run(timings, &press(100, 100), 2000);
+    assert_eq!(events, [(120, Pressed), (220, Released), (220, Click)]);
+}
+
+#[test]
+fn bounces_are_ignored() {
+    let edges = [
+        (100, true),
+        (102, false),
+        (105, true),
+        (111, false),
+        (112, true),
+        // Held from 112.
+        (300, false),
+        (303, true),
+        (304, false),
+        // A glitch shorter than the debounce time while released.
+        (1000, true),
+        (1010, false),
+    ];
+    let events = run(Timings::default(), &edges, 2000);
+    assert_eq!(events, [(132, Pressed), (324, Released), (624, Click)]);
+}
+
+#[test]
+fn double_click() {
+    let mut edges = press(100, 80).to_vec();
+    edges.extend_from_slice(&press(350, 80));
+    let events = run(Timings::default(), &edges, 2000);
+    assert_eq!(
+        events,
+        [
+            (120, Pressed),
+            (200, Released),
+            (370, Pressed),
+            (450, Released),
+            (450, DoubleClick)
+        ]
+    );
+}
+
+#[test]
+fn slow_second_click() {
+    let mut edges = press(100, 80).to_vec();
+    edges.extend_from_slice(&press(600, 80));
+    let events = run(Timings::default(), &edges, 2000);
+    assert_eq!(
+        events,
+        [
+            (120, Pressed),
+            (200, Released),
+            (500, Click),
+            (620, Pressed),
+            (700, Released),
+            (1000, Click)
+        ]
+    );
+}
+
+#[test]
+fn long_press() {
+    let events = run(Timings::default(), &press(100, 1500), 3000);
+    assert_eq!(events, [(120, Pressed), (920, LongPress), (1620, Released)]);
+}
+
+#[test]
+fn long_press_repeats() {
+    let timings = Timings {
+        repeat_interval_ms: 200,
+        ..Timings::default()
+    };
+    let events = run(timings, &press(100, 1500), 3000);
+    assert_eq!(
+        events,
+        [
+            (120, Pressed),
+            (920, LongPress),
+            (1120, Repeat),
+            (1320, Repeat),
+            (1520, Repeat),
+            (1620, Released)
+        ]
+    );
+}
+
+#[test]
+fn click_then_long_press() {
+    let mut edges = press(100, 80).to_vec();
+    edges.extend_from_slice(&press(300, 1000));
+    let events = run(Timings::default(), &edges, 3000);
+    assert_eq!(
+        events,
+        [
+            (120, Pressed),
+            (200, Released),
+            (320, Pressed),
+            (1120, Click),
+            (1120, LongPress),
+            (1320, Released)
+        ]
+    );
+}
+
+#[test]
+fn long_press_disabled() {
+    let timings = Timings {
+        long_press_ms: 0,
+        ..Timings::default()
+    };
+    let events = run(timings, &press(100, 1500), 3000);
+    assert_eq!(events, [(120, Pressed), (1620, Released), (1920, Click)]);
+}
+
+#[test]
+fn state_and_deadlines() {
+    let mut recognizer = Recognizer::new(Timings::default());
+    let mut events = Vec::new();
+    assert_eq!(recognizer.next_deadline(), None);
+
+    recognizer.edge(100, true, &mut |e| events.push(e));
+    assert!(!recognizer.is_pressed());
+    assert_eq!(recognizer.next_deadline(), Some(120));
+
+    // Nothing happens until the deadline.
+    recognizer.update(119, &mut |e| events.push(e));
+    assert!(events.is_empty());
+    recognizer.update(120, &mut |e| events.push(e));
+    assert!(recognizer.is_pressed());
+    assert_eq!(events, [Pressed]);
+    assert_eq!(recognizer.next_deadline(), Some(920));
+
+    // Updating late catches up on everything that was due, in order.
+    recognizer.edge(2000, false, &mut |e| events.push(e));
+    recognizer.update(5000, &mut |e| events.push(e));
+    assert_eq!(events, [Pressed, LongPress, Released]);
+    assert_eq!(recognizer.next_deadline(), None);
+}
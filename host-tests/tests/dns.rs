@@ -0,0 +1,120 @@
+//! Queries as `dig` sends them, checked against RFC 1035.
+
+use host_tests::dns::{build_response, Error, Query, MAX_MESSAGE_LEN};
+use host_tests::net::Ipv4Addr;
+
+/// `dig @192.168.4.1 connectivitycheck.gstatic.com A`, without EDNS.
+#[rustfmt::skip]
+const QUERY_A: &[u8] = &[
+    // ID, flags (RD), one question.
+    0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    17, b'c', b'o', b'n', b'n', b'e', b'c', b't', b'i', b'v', b'i', b't', b'y',
+        b'c', b'h', b'e', b'c', b'k',
+    7, b'g', b's', b't', b'a', b't', b'i', b'c',
+    3, b'c', b'o', b'm',
+    0,
+    // QTYPE A, QCLASS IN.
+    0x00, 0x01, 0x00, 0x01,
+];
+
+/// `dig @192.168.4.1 example.com AAAA`.
+#[rustfmt::skip]
+const QUERY_AAAA: &[u8] = &[
+    0xbe, 0xef, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    7, b'e', b'x', b'a', b'm', b'p', b'l', b'e',
+    3, b'c', b'o', b'm',
+    0,
+    0x00, 0x1c, 0x00, 0x01,
+];
+
+const AP: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+
+fn respond(query: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut out = [0; MAX_MESSAGE_LEN];
+    let len = build_response(query, AP, &mut out)?;
+    Ok(out[..len].to_vec())
+}
+
+#[test]
+fn parse_query() {
+    let query = Query::parse(QUERY_A).unwrap();
+    assert_eq!(query.id, 0x1234);
+    assert_eq!(query.name, "connectivitycheck.gstatic.com");
+    assert!(query.wants_ipv4());
+
+    let query = Query::parse(QUERY_AAAA).unwrap();
+    assert_eq!(query.name, "example.com");
+    assert!(!query.wants_ipv4());
+}
+
+#[test]
+fn answer_a() {
+    let mut expected = QUERY_A.to_vec();
+    // QR, AA and RD; one question and one answer.
+    expected[2..8].copy_from_slice(&[0x85, 0x00, 0x00, 0x01, 0x00, 0x01]);
+    #[rustfmt::skip]
+    expected.extend_from_slice(&[
+        // A pointer to the name at offset 12.
+        0xc0, 0x0c,
+        0x00, 0x01, 0x00, 0x01,
+        // TTL 60 s.
+        0x00, 0x00, 0x00, 0x3c,
+        0x00, 0x04, 192, 168, 4, 1,
+    ]);
+    assert_eq!(respond(QUERY_A).unwrap(), expected);
+}
+
+#[test]
+fn empty_answer_for_other_types() {
+    let mut expected = QUERY_AAAA.to_vec();
+    expected[2..8].copy_from_slice(&[0x85, 0x00, 0x00, 0x01, 0x00, 0x00]);
+    assert_eq!(respond(QUERY_AAAA).unwrap(), expected);
+}
+
+#[test]
+fn other_opcodes_not_implemented() {
+    // An inverse query (opcode 1).
+    let mut query = QUERY_A.to_vec();
+    query[2] = 0x09;
+    let mut expected = query.clone();
+    expected[2..8].copy_from_slice(&[0x8d, 0x04, 0x00, 0x01, 0x00, 0x00]);
+    assert_eq!(respond(&query).unwrap(), expected);
+}
+
+#[test]
+fn additional_records_are_dropped() {
+    // An EDNS OPT record after the question.
+    let mut query = QUERY_A.to_vec();
+    query[11] = 1;
+    query.extend_from_slice(&[0, 0x00, 0x29, 0x10, 0x00, 0, 0, 0, 0, 0x00, 0x00]);
+    assert_eq!(respond(&query).unwrap().len(), QUERY_A.len() + 16);
+}
+
+#[test]
+fn errors() {
+    assert_eq!(respond(&QUERY_A[..11]), Err(Error::Truncated));
+    assert_eq!(
+        respond(&QUERY_A[..QUERY_A.len() - 1]),
+        Err(Error::Truncated)
+    );
+    assert_eq!(respond(&QUERY_A[..20]), Err(Error::Truncated));
+
+    let mut response = QUERY_A.to_vec();
+    response[2] |= 0x80;
+    assert_eq!(respond(&response), Err(Error::NotAQuery));
+
+    let mut two = QUERY_A.to_vec();
+    two[5] = 2;
+    assert_eq!(respond(&two), Err(Error::UnsupportedQuestionCount(2)));
+
+    // A compression pointer in place of the name.
+    let mut compressed = QUERY_A[..12].to_vec();
+    compressed.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01]);
+    assert_eq!(respond(&compressed), Err(Error::BadName));
+
+    let mut out = [0; 40];
+    assert_eq!(
+        build_response(QUERY_A, AP, &mut out),
+        Err(Error::BufferTooSmall)
+    );
+}
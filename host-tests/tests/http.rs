@@ -0,0 +1,155 @@
+//! Requests as a phone's browser sends them to the captive portal.
+
+use host_tests::http::{
+    form_fields, form_value, html_escape, url_decode, Method, ParseError, Request, Response,
+};
+
+const GET: &[u8] = b"GET /generate_204?x=1 HTTP/1.1\r\n\
+    Host: connectivitycheck.gstatic.com\r\n\
+    Connection: keep-alive\r\n\r\n";
+
+const POST: &[u8] = b"POST /connect HTTP/1.1\r\n\
+    Host: 192.168.4.1\r\n\
+    content-type: application/x-www-form-urlencoded\r\n\
+    Content-Length: 39\r\n\r\n\
+    ssid=Caf%C3%A9+Wi-Fi&password=p%26ss%3D";
+
+#[test]
+fn get() {
+    let request = Request::parse(GET).unwrap();
+    assert_eq!(request.method, Method::Get);
+    assert_eq!(request.path, "/generate_204");
+    assert_eq!(request.query, Some("x=1"));
+    assert_eq!(
+        request.header("host"),
+        Some("connectivitycheck.gstatic.com")
+    );
+    assert_eq!(request.header("Accept"), None);
+    assert!(request.body.is_empty());
+}
+
+#[test]
+fn post_form() {
+    let request = Request::parse(POST).unwrap();
+    assert_eq!(request.method, Method::Post);
+    assert_eq!(request.path, "/connect");
+    assert_eq!(
+        request.header("Content-Type"),
+        Some("application/x-www-form-urlencoded")
+    );
+
+    let body = core::str::from_utf8(request.body).unwrap();
+    assert_eq!(form_value(body, "ssid").as_deref(), Some("Café Wi-Fi"));
+    assert_eq!(form_value(body, "password").as_deref(), Some("p&ss="));
+    assert_eq!(form_value(body, "user"), None);
+}
+
+#[test]
+fn incomplete() {
+    // The headers haven't ended.
+    for len in [0, 10, GET.len() - 1].iter() {
+        assert_eq!(Request::parse(&GET[..*len]), Err(ParseError::Incomplete));
+    }
+    // The body is shorter than its `Content-Length`.
+    let short = &POST[..POST.len() - 1];
+    assert_eq!(Request::parse(short), Err(ParseError::Incomplete));
+    // Extra bytes after the body belong to the next request.
+    let mut pipelined = POST.to_vec();
+    pipelined.extend_from_slice(GET);
+    assert_eq!(Request::parse(&pipelined).unwrap().body.len(), 39);
+}
+
+#[test]
+fn malformed() {
+    let requests: &[&[u8]] = &[
+        b"\r\n\r\n",
+        b"GET\r\n\r\n",
+        b"GET / SPDY/3\r\n\r\n",
+        b"GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n",
+        b"GET / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+        b"GET /\xff HTTP/1.1\r\n\r\n",
+    ];
+    for request in requests {
+        assert_eq!(Request::parse(request), Err(ParseError::Malformed));
+    }
+}
+
+#[test]
+fn content_length_overflow() {
+    let request = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", usize::MAX);
+    assert_eq!(
+        Request::parse(request.as_bytes()),
+        Err(ParseError::Malformed)
+    );
+}
+
+#[test]
+fn too_large_for_buffer() {
+    // The body would end past the buffer, so it can never arrive.
+    let head = b"POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\n";
+    assert_eq!(Request::parse_within(head, 128), Err(ParseError::Malformed));
+    assert_eq!(
+        Request::parse_within(head, 140),
+        Err(ParseError::Incomplete)
+    );
+
+    // The buffer is full before the headers end.
+    let head = &GET[..32];
+    assert_eq!(Request::parse_within(head, 32), Err(ParseError::Malformed));
+    assert_eq!(Request::parse_within(head, 33), Err(ParseError::Incomplete));
+
+    assert!(Request::parse_within(POST, POST.len()).is_ok());
+}
+
+#[test]
+fn form_decoding() {
+    assert_eq!(url_decode("a+b%20c").as_deref(), Some("a b c"));
+    assert_eq!(url_decode("%e2%82%ac").as_deref(), Some("€"));
+    assert_eq!(url_decode("100%"), None);
+    assert_eq!(url_decode("%4"), None);
+    assert_eq!(url_decode("%+1"), None);
+    assert_eq!(url_decode("%ff"), None);
+
+    let fields: Vec<_> = form_fields("a=1&&b&c=%zz&d=&a=2").collect();
+    let expected = [("a", "1"), ("b", ""), ("d", ""), ("a", "2")];
+    assert_eq!(fields.len(), expected.len());
+    for ((k, v), (ek, ev)) in fields.iter().zip(expected.iter()) {
+        assert_eq!((k.as_str(), v.as_str()), (*ek, *ev));
+    }
+    assert_eq!(form_value("a=1&a=2", "a").as_deref(), Some("1"));
+}
+
+#[test]
+fn escaping() {
+    let mut out = String::from("<p>");
+    html_escape("\"Tom & Jerry's\" <net>", &mut out);
+    assert_eq!(out, "<p>&quot;Tom &amp; Jerry&#39;s&quot; &lt;net&gt;");
+}
+
+#[test]
+fn responses() {
+    let mut out = Vec::new();
+    Response::html("<p>hi</p>").write_to(&mut out);
+    assert_eq!(
+        out,
+        b"HTTP/1.1 200 OK\r\n\
+          Content-Type: text/html; charset=utf-8\r\n\
+          Content-Length: 9\r\n\
+          Connection: close\r\n\
+          Cache-Control: no-store\r\n\r\n\
+          <p>hi</p>"
+            .to_vec()
+    );
+
+    out.clear();
+    Response::redirect("http://192.168.4.1/").write_to(&mut out);
+    assert_eq!(
+        out,
+        b"HTTP/1.1 302 Found\r\n\
+          Location: http://192.168.4.1/\r\n\
+          Content-Length: 0\r\n\
+          Connection: close\r\n\
+          Cache-Control: no-store\r\n\r\n"
+            .to_vec()
+    );
+}
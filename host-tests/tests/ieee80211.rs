@@ -0,0 +1,205 @@
+//! Headers of frames captured from a 2.4 GHz network: an AP at
+//! 00:11:22:33:44:55 and a station at a4:5e:60:12:34:56.
+
+use host_tests::ieee80211::{FrameHeader, FrameSubtype, FrameType, ParseError, SequenceControl};
+use host_tests::net::MacAddress;
+
+const AP: MacAddress = MacAddress([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+const STA: MacAddress = MacAddress([0xa4, 0x5e, 0x60, 0x12, 0x34, 0x56]);
+const MDNS: MacAddress = MacAddress([0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb]);
+
+#[rustfmt::skip]
+const BEACON: &[u8] = &[
+    0x80, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55,
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55,
+    0x10, 0x5b,
+    // Timestamp, beacon interval, capabilities.
+    0x8d, 0x61, 0xa5, 0x1f, 0x00, 0x00, 0x00, 0x00, 0x64, 0x00, 0x31, 0x04,
+    // SSID "test", supported rates, DS parameter set (channel 6).
+    0x00, 0x04, b't', b'e', b's', b't',
+    0x01, 0x04, 0x82, 0x84, 0x8b, 0x96,
+    0x03, 0x01, 0x06,
+];
+
+#[rustfmt::skip]
+const PROBE_REQUEST: &[u8] = &[
+    0x40, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xa4, 0x5e, 0x60, 0x12, 0x34, 0x56,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x20, 0x01,
+    // Wildcard SSID, supported rates.
+    0x00, 0x00,
+    0x01, 0x04, 0x02, 0x04, 0x0b, 0x16,
+];
+
+/// From the station to the AP, retried.
+#[rustfmt::skip]
+const DATA_TO_DS: &[u8] = &[
+    0x08, 0x09, 0x2c, 0x00,
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55,
+    0xa4, 0x5e, 0x60, 0x12, 0x34, 0x56,
+    0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb,
+    0x30, 0x4e,
+    // LLC/SNAP header for IPv4.
+    0xaa, 0xaa, 0x03, 0x00, 0x00, 0x00, 0x08, 0x00,
+];
+
+/// From the AP to the station, protected.
+#[rustfmt::skip]
+const QOS_DATA_FROM_DS: &[u8] = &[
+    0x88, 0x42, 0x30, 0x00,
+    0xa4, 0x5e, 0x60, 0x12, 0x34, 0x56,
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55,
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55,
+    0x81, 0x07,
+    0x06, 0x00,
+    // CCMP header.
+    0x2a, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[rustfmt::skip]
+const WDS_DATA: &[u8] = &[
+    0x08, 0x03, 0x00, 0x00,
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x66,
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55,
+    0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb,
+    0x00, 0x00,
+    0xa4, 0x5e, 0x60, 0x12, 0x34, 0x56,
+];
+
+#[test]
+fn beacon() {
+    let header = FrameHeader::parse(BEACON).unwrap();
+    let fc = header.frame_control;
+    assert_eq!(fc.frame_type(), FrameType::Management);
+    assert_eq!(fc.subtype(), FrameSubtype::Beacon);
+    assert!(!fc.to_ds() && !fc.from_ds() && !fc.retry() && !fc.protected());
+    assert_eq!(header.addr1, MacAddress::BROADCAST);
+    assert_eq!(header.addr2, Some(AP));
+    assert_eq!(header.addr3, Some(AP));
+    assert_eq!(
+        header.sequence_control,
+        Some(SequenceControl {
+            fragment: 0,
+            sequence: 1457
+        })
+    );
+    assert_eq!(header.addr4, None);
+    assert_eq!(header.qos_control, None);
+    assert_eq!(header.len, 24);
+    assert_eq!(header.bssid(), Some(AP));
+    assert_eq!(header.source(), Some(AP));
+    assert!(header.destination().is_broadcast());
+    // The body starts with the timestamp.
+    assert_eq!(&BEACON[header.len..header.len + 2], &[0x8d, 0x61]);
+}
+
+#[test]
+fn probe_request() {
+    let header = FrameHeader::parse(PROBE_REQUEST).unwrap();
+    assert_eq!(header.frame_control.subtype(), FrameSubtype::ProbeRequest);
+    assert_eq!(header.source(), Some(STA));
+    assert_eq!(header.bssid(), Some(MacAddress::BROADCAST));
+    assert_eq!(header.sequence_control.unwrap().sequence, 18);
+    assert_eq!(header.len, 24);
+}
+
+#[test]
+fn data_to_ds() {
+    let header = FrameHeader::parse(DATA_TO_DS).unwrap();
+    let fc = header.frame_control;
+    assert_eq!(fc.frame_type(), FrameType::Data);
+    assert_eq!(fc.subtype(), FrameSubtype::Data);
+    assert!(fc.to_ds() && !fc.from_ds() && fc.retry());
+    assert_eq!(header.duration, 44);
+    assert_eq!(header.bssid(), Some(AP));
+    assert_eq!(header.source(), Some(STA));
+    assert_eq!(header.destination(), MDNS);
+    assert!(header.destination().is_multicast());
+    assert_eq!(header.len, 24);
+}
+
+#[test]
+fn qos_data_from_ds() {
+    let header = FrameHeader::parse(QOS_DATA_FROM_DS).unwrap();
+    let fc = header.frame_control;
+    assert_eq!(fc.subtype(), FrameSubtype::QosData);
+    assert!(!fc.to_ds() && fc.from_ds() && fc.protected());
+    assert_eq!(header.qos_control, Some(6));
+    assert_eq!(
+        header.sequence_control,
+        Some(SequenceControl {
+            fragment: 1,
+            sequence: 0x78
+        })
+    );
+    assert_eq!(header.bssid(), Some(AP));
+    assert_eq!(header.source(), Some(AP));
+    assert_eq!(header.destination(), STA);
+    assert_eq!(header.len, 26);
+}
+
+#[test]
+fn four_address_data() {
+    let header = FrameHeader::parse(WDS_DATA).unwrap();
+    assert_eq!(header.addr4, Some(STA));
+    assert_eq!(header.bssid(), None);
+    assert_eq!(header.source(), Some(STA));
+    assert_eq!(header.destination(), MDNS);
+    assert_eq!(header.len, 30);
+}
+
+#[test]
+fn control_frames() {
+    let ack =
+        FrameHeader::parse(&[0xd4, 0x00, 0x00, 0x00, 0xa4, 0x5e, 0x60, 0x12, 0x34, 0x56]).unwrap();
+    assert_eq!(ack.frame_control.frame_type(), FrameType::Control);
+    assert_eq!(ack.frame_control.subtype(), FrameSubtype::Ack);
+    assert_eq!(ack.addr1, STA);
+    assert_eq!(ack.addr2, None);
+    assert_eq!(ack.source(), None);
+    assert_eq!(ack.len, 10);
+
+    let mut rts = vec![0xb4, 0x00, 0x5e, 0x01];
+    rts.extend_from_slice(&AP.octets());
+    rts.extend_from_slice(&STA.octets());
+    let rts = FrameHeader::parse(&rts).unwrap();
+    assert_eq!(rts.frame_control.subtype(), FrameSubtype::Rts);
+    assert_eq!(rts.duration, 350);
+    assert_eq!(rts.source(), Some(STA));
+    assert_eq!(rts.bssid(), None);
+    assert_eq!(rts.len, 16);
+}
+
+#[test]
+fn unknown_subtype() {
+    let header = FrameHeader::parse(&[
+        0x60, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ])
+    .unwrap();
+    assert_eq!(header.frame_control.subtype(), FrameSubtype::Other(6));
+}
+
+#[test]
+fn errors() {
+    for len in 0..24 {
+        assert_eq!(
+            FrameHeader::parse(&BEACON[..len]),
+            Err(ParseError::Truncated),
+            "length {}",
+            len
+        );
+    }
+    // The QoS control field is missing.
+    assert_eq!(
+        FrameHeader::parse(&QOS_DATA_FROM_DS[..25]),
+        Err(ParseError::Truncated)
+    );
+    assert_eq!(
+        FrameHeader::parse(&[0x81, 0x00, 0x00, 0x00, 0, 0, 0, 0, 0, 0]),
+        Err(ParseError::UnsupportedVersion(1))
+    );
+}
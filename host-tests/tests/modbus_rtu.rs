@@ -0,0 +1,281 @@
+//! Checked against the reference frames for slave 0x11 published by
+//! Simply Modbus.
+
+use host_tests::modbus_rtu::{
+    char_time_us, crc16, frame_gap_us, unpack_bits, unpack_registers, Exception, FrameError,
+    Request, Response, MAX_FRAME,
+};
+
+const UNIT: u8 = 0x11;
+
+fn encode(request: &Request<'_>) -> Vec<u8> {
+    let mut buf = [0; MAX_FRAME];
+    let len = request.encode(UNIT, &mut buf).unwrap();
+    assert_eq!(len, request.frame_len());
+    buf[..len].to_vec()
+}
+
+#[test]
+fn crc() {
+    assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0a]), 0xcdc5);
+    assert_eq!(crc16(&[]), 0xffff);
+}
+
+#[test]
+fn read_coils() {
+    let request = Request::ReadCoils {
+        address: 0x0013,
+        count: 0x0025,
+    };
+    assert_eq!(
+        encode(&request),
+        [0x11, 0x01, 0x00, 0x13, 0x00, 0x25, 0x0e, 0x84]
+    );
+
+    let response = [0x11, 0x01, 0x05, 0xcd, 0x6b, 0xb2, 0x0e, 0x1b, 0x45, 0xe6];
+    assert_eq!(request.response_len(), response.len());
+    let bytes = match request.decode_response(UNIT, &response).unwrap() {
+        Response::Bits(bytes) => bytes,
+        other => panic!("{:?}", other),
+    };
+    let mut coils = [false; 0x25];
+    unpack_bits(bytes, &mut coils);
+    // 0xCD: coils 27..20 are 1100 1101.
+    assert_eq!(
+        coils[..8],
+        [true, false, true, true, false, false, true, true]
+    );
+    // 0x1B, of which only the low five bits are coils 56..52.
+    assert_eq!(coils[32..], [true, true, false, true, true]);
+}
+
+#[test]
+fn read_discrete_inputs() {
+    let request = Request::ReadDiscreteInputs {
+        address: 0x00c4,
+        count: 0x0016,
+    };
+    assert_eq!(
+        encode(&request),
+        [0x11, 0x02, 0x00, 0xc4, 0x00, 0x16, 0xba, 0xa9]
+    );
+
+    let response = [0x11, 0x02, 0x03, 0xac, 0xdb, 0x35, 0x20, 0x18];
+    match request.decode_response(UNIT, &response).unwrap() {
+        Response::Bits(bytes) => assert_eq!(bytes, [0xac, 0xdb, 0x35]),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn read_holding_registers() {
+    let request = Request::ReadHoldingRegisters {
+        address: 0x006b,
+        count: 3,
+    };
+    assert_eq!(
+        encode(&request),
+        [0x11, 0x03, 0x00, 0x6b, 0x00, 0x03, 0x76, 0x87]
+    );
+
+    let response = [
+        0x11, 0x03, 0x06, 0xae, 0x41, 0x56, 0x52, 0x43, 0x40, 0x49, 0xad,
+    ];
+    let bytes = match request.decode_response(UNIT, &response).unwrap() {
+        Response::Registers(bytes) => bytes,
+        other => panic!("{:?}", other),
+    };
+    let mut registers = [0; 3];
+    unpack_registers(bytes, &mut registers);
+    assert_eq!(registers, [0xae41, 0x5652, 0x4340]);
+}
+
+#[test]
+fn read_input_registers() {
+    let request = Request::ReadInputRegisters {
+        address: 0x0008,
+        count: 1,
+    };
+    assert_eq!(
+        encode(&request),
+        [0x11, 0x04, 0x00, 0x08, 0x00, 0x01, 0xb2, 0x98]
+    );
+
+    let response = [0x11, 0x04, 0x02, 0x00, 0x0a, 0xf8, 0xf4];
+    assert_eq!(
+        request.decode_response(UNIT, &response),
+        Ok(Response::Registers(&[0x00, 0x0a]))
+    );
+}
+
+#[test]
+fn write_single_coil() {
+    let request = Request::WriteSingleCoil {
+        address: 0x00ac,
+        value: true,
+    };
+    let frame = encode(&request);
+    assert_eq!(frame, [0x11, 0x05, 0x00, 0xac, 0xff, 0x00, 0x4e, 0x8b]);
+    // The response echoes the request.
+    assert_eq!(request.decode_response(UNIT, &frame), Ok(Response::Written));
+}
+
+#[test]
+fn write_single_register() {
+    let request = Request::WriteSingleRegister {
+        address: 0x0001,
+        value: 0x0003,
+    };
+    let frame = encode(&request);
+    assert_eq!(frame, [0x11, 0x06, 0x00, 0x01, 0x00, 0x03, 0x9a, 0x9b]);
+    assert_eq!(request.decode_response(UNIT, &frame), Ok(Response::Written));
+}
+
+#[test]
+fn write_multiple_coils() {
+    let values = [
+        true, false, true, true, false, false, true, true, true, false,
+    ];
+    let request = Request::WriteMultipleCoils {
+        address: 0x0013,
+        values: &values,
+    };
+    assert_eq!(
+        encode(&request),
+        [0x11, 0x0f, 0x00, 0x13, 0x00, 0x0a, 0x02, 0xcd, 0x01, 0xbf, 0x0b]
+    );
+
+    let response = [0x11, 0x0f, 0x00, 0x13, 0x00, 0x0a, 0x26, 0x99];
+    assert_eq!(
+        request.decode_response(UNIT, &response),
+        Ok(Response::Written)
+    );
+}
+
+#[test]
+fn write_multiple_registers() {
+    let request = Request::WriteMultipleRegisters {
+        address: 0x0001,
+        values: &[0x000a, 0x0102],
+    };
+    assert_eq!(
+        encode(&request),
+        [0x11, 0x10, 0x00, 0x01, 0x00, 0x02, 0x04, 0x00, 0x0a, 0x01, 0x02, 0xc6, 0xf0]
+    );
+
+    let response = [0x11, 0x10, 0x00, 0x01, 0x00, 0x02, 0x12, 0x98];
+    assert_eq!(
+        request.decode_response(UNIT, &response),
+        Ok(Response::Written)
+    );
+}
+
+#[test]
+fn exception() {
+    let request = Request::ReadHoldingRegisters {
+        address: 0xffff,
+        count: 1,
+    };
+    let mut response = vec![UNIT, 0x83, 0x02];
+    let crc = crc16(&response);
+    response.extend_from_slice(&crc.to_le_bytes());
+    assert_eq!(
+        request.decode_response(UNIT, &response),
+        Ok(Response::Exception(Exception::IllegalDataAddress))
+    );
+    assert_eq!(Exception::from_code(0x42), Exception::Other(0x42));
+    assert_eq!(Exception::from_code(0x0b).code(), 0x0b);
+}
+
+#[test]
+fn bad_responses() {
+    let request = Request::ReadInputRegisters {
+        address: 0x0008,
+        count: 1,
+    };
+    let response = [0x11, 0x04, 0x02, 0x00, 0x0a, 0xf8, 0xf4];
+
+    assert_eq!(
+        request.decode_response(UNIT, &response[..4]),
+        Err(FrameError::Truncated)
+    );
+    let mut corrupted = response;
+    corrupted[4] ^= 0x01;
+    assert_eq!(
+        request.decode_response(UNIT, &corrupted),
+        Err(FrameError::BadCrc)
+    );
+    assert_eq!(
+        request.decode_response(0x12, &response),
+        Err(FrameError::Mismatch)
+    );
+    let other = Request::ReadInputRegisters {
+        address: 0x0008,
+        count: 2,
+    };
+    assert_eq!(
+        other.decode_response(UNIT, &response),
+        Err(FrameError::Mismatch)
+    );
+
+    // A write acknowledged with the wrong value.
+    let request = Request::WriteSingleRegister {
+        address: 0x0001,
+        value: 0x0004,
+    };
+    let echo = [0x11, 0x06, 0x00, 0x01, 0x00, 0x03, 0x9a, 0x9b];
+    assert_eq!(
+        request.decode_response(UNIT, &echo),
+        Err(FrameError::Mismatch)
+    );
+}
+
+#[test]
+fn invalid_requests() {
+    let mut buf = [0; MAX_FRAME];
+    for request in &[
+        Request::ReadCoils {
+            address: 0,
+            count: 0,
+        },
+        Request::ReadCoils {
+            address: 0,
+            count: 2001,
+        },
+        Request::ReadHoldingRegisters {
+            address: 0,
+            count: 126,
+        },
+        Request::ReadInputRegisters {
+            address: 0xfffe,
+            count: 3,
+        },
+        Request::WriteMultipleRegisters {
+            address: 0,
+            values: &[0; 124],
+        },
+    ] {
+        assert_eq!(
+            request.encode(UNIT, &mut buf),
+            Err(FrameError::InvalidQuantity)
+        );
+    }
+
+    let request = Request::ReadInputRegisters {
+        address: 0xfffe,
+        count: 2,
+    };
+    assert_eq!(
+        request.encode(UNIT, &mut buf[..7]),
+        Err(FrameError::BufferTooSmall)
+    );
+    assert_eq!(request.encode(UNIT, &mut buf), Ok(8));
+}
+
+#[test]
+fn timings() {
+    assert_eq!(char_time_us(9600), 1146);
+    assert_eq!(frame_gap_us(9600), 4011);
+    assert_eq!(frame_gap_us(19200), 2006);
+    assert_eq!(frame_gap_us(115_200), 1750);
+}
@@ -0,0 +1,131 @@
+//! Checked byte for byte against the pcap and radiotap specifications;
+//! Wireshark reads the same bytes as a 1 Mbps ACK on channel 6.
+
+use host_tests::pcap::{
+    channel_frequency, radiotap_header, PcapWriter, PhyRate, RadioInfo, MAX_RADIOTAP_LEN,
+};
+
+const ACK: &[u8] = &[0xd4, 0x00, 0x00, 0x00, 0xa4, 0x5e, 0x60, 0x12, 0x34, 0x56];
+
+fn radio(rate: Option<PhyRate>) -> RadioInfo {
+    RadioInfo {
+        tsft: 1_500_000,
+        rate,
+        channel: 6,
+        rssi: -40,
+        noise_floor: -95,
+        has_fcs: false,
+    }
+}
+
+#[rustfmt::skip]
+const GLOBAL_HEADER: &[u8] = &[
+    0xd4, 0xc3, 0xb2, 0xa1,
+    0x02, 0x00, 0x04, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+    // 256 bytes of frame plus the largest radiotap header.
+    0x20, 0x01, 0x00, 0x00,
+    // LINKTYPE_IEEE802_11_RADIOTAP.
+    0x7f, 0x00, 0x00, 0x00,
+];
+
+#[rustfmt::skip]
+const LEGACY_RADIOTAP: &[u8] = &[
+    0x00, 0x00, 0x18, 0x00,
+    // TSFT, flags, rate, channel, antenna signal and noise.
+    0x6f, 0x00, 0x00, 0x00,
+    0x60, 0xe3, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00,
+    0x02,
+    0x85, 0x09, 0x80, 0x00,
+    0xd8,
+    0xa1,
+];
+
+#[test]
+fn global_header() {
+    let writer = PcapWriter::new(Vec::new(), 256).unwrap();
+    assert_eq!(writer.into_inner(), GLOBAL_HEADER);
+}
+
+#[test]
+fn record() {
+    let mut writer = PcapWriter::new(Vec::new(), 256).unwrap();
+    writer
+        .write_frame(1_500_000, &radio(Some(PhyRate::Legacy(2))), ACK, ACK.len())
+        .unwrap();
+    let out = writer.into_inner();
+
+    #[rustfmt::skip]
+    let record_header = [
+        0x01, 0x00, 0x00, 0x00,
+        0x20, 0xa1, 0x07, 0x00,
+        0x22, 0x00, 0x00, 0x00,
+        0x22, 0x00, 0x00, 0x00,
+    ];
+    let mut expected = GLOBAL_HEADER.to_vec();
+    expected.extend_from_slice(&record_header);
+    expected.extend_from_slice(LEGACY_RADIOTAP);
+    expected.extend_from_slice(ACK);
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn truncated_record() {
+    let mut writer = PcapWriter::new(Vec::new(), 4).unwrap();
+    writer
+        .write_frame(0, &radio(Some(PhyRate::Legacy(2))), ACK, 14)
+        .unwrap();
+    let out = writer.into_inner();
+    let record = &out[GLOBAL_HEADER.len()..];
+    // Included: radiotap plus 4 bytes. Original: radiotap plus 14.
+    assert_eq!(&record[8..12], &[28, 0, 0, 0]);
+    assert_eq!(&record[12..16], &[38, 0, 0, 0]);
+    assert_eq!(record.len(), 16 + 24 + 4);
+    assert_eq!(&record[16 + 24..], &ACK[..4]);
+}
+
+#[test]
+fn ht_radiotap() {
+    let mut buf = [0; MAX_RADIOTAP_LEN];
+    let rate = PhyRate::Ht {
+        mcs: 7,
+        ht40: false,
+        short_gi: true,
+    };
+    let mut info = radio(Some(rate));
+    info.has_fcs = true;
+    let len = radiotap_header(&info, &mut buf);
+    #[rustfmt::skip]
+    let expected = [
+        0x00, 0x00, 0x1b, 0x00,
+        // TSFT, flags, channel, antenna signal and noise, MCS.
+        0x6b, 0x00, 0x08, 0x00,
+        0x60, 0xe3, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Flags: FCS at end; then padding to align the channel.
+        0x10, 0x00,
+        0x85, 0x09, 0x80, 0x00,
+        0xd8,
+        0xa1,
+        // Known: bandwidth, MCS and guard interval; short GI; MCS 7.
+        0x07, 0x04, 0x07,
+    ];
+    assert_eq!(&buf[..len], &expected[..]);
+}
+
+#[test]
+fn no_rate() {
+    let mut buf = [0; MAX_RADIOTAP_LEN];
+    let len = radiotap_header(&radio(None), &mut buf);
+    assert_eq!(len, 24);
+    assert_eq!(&buf[4..8], &[0x6b, 0x00, 0x00, 0x00]);
+}
+
+#[test]
+fn frequencies() {
+    assert_eq!(channel_frequency(1), 2412);
+    assert_eq!(channel_frequency(6), 2437);
+    assert_eq!(channel_frequency(13), 2472);
+    assert_eq!(channel_frequency(14), 2484);
+}
@@ -0,0 +1,212 @@
+//! Loading, saving and migrating a config kept in `MemoryStorage`.
+
+use host_tests::impl_encode_decode;
+use host_tests::persistent_config::{
+    crc32, from_bytes, seal, to_bytes, unseal, DecodeError, LoadStatus, MemoryStorage,
+    PersistentConfig,
+};
+
+/// Version 1 of the schema.
+#[derive(Debug, Default, PartialEq)]
+struct SettingsV1 {
+    brightness: u8,
+    name: String,
+}
+impl_encode_decode!(SettingsV1 { brightness, name });
+
+/// Version 2 added `interval_s` and made `name` optional.
+#[derive(Debug, PartialEq)]
+struct Settings {
+    brightness: u8,
+    name: Option<String>,
+    interval_s: u32,
+    offset: i16,
+}
+impl_encode_decode!(Settings {
+    brightness,
+    name,
+    interval_s,
+    offset
+});
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            brightness: 128,
+            name: None,
+            interval_s: 60,
+            offset: 0,
+        }
+    }
+}
+
+const KEY: &str = "settings";
+
+fn migrate_v1(payload: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let old: SettingsV1 = from_bytes(payload)?;
+    Ok(to_bytes(&Settings {
+        brightness: old.brightness,
+        name: Some(old.name).filter(|n| !n.is_empty()),
+        ..Settings::default()
+    }))
+}
+
+fn load(storage: MemoryStorage) -> PersistentConfig<Settings, MemoryStorage> {
+    match PersistentConfig::new(storage, KEY, 2)
+        .migration(1, migrate_v1)
+        .load()
+    {
+        Ok(config) => config,
+        Err(e) => match e {},
+    }
+}
+
+#[test]
+fn missing_loads_defaults() {
+    let mut config = load(MemoryStorage::default());
+    assert_eq!(config.status(), LoadStatus::Missing);
+    assert_eq!(*config.get(), Settings::default());
+    // Nothing is written until the value is saved.
+    assert!(config.storage().blobs.is_empty());
+}
+
+#[test]
+fn save_and_reload() {
+    let mut config = load(MemoryStorage::default());
+    config
+        .update(|s| {
+            s.brightness = 7;
+            s.name = Some(String::from("kitchen"));
+            s.offset = -300;
+        })
+        .unwrap();
+
+    let (_, storage) = config.into_inner();
+    let config = load(storage);
+    assert_eq!(config.status(), LoadStatus::Loaded);
+    assert_eq!(
+        *config.get(),
+        Settings {
+            brightness: 7,
+            name: Some(String::from("kitchen")),
+            interval_s: 60,
+            offset: -300,
+        }
+    );
+}
+
+#[test]
+fn blob_layout() {
+    let mut config = load(MemoryStorage::default());
+    config.get_mut().interval_s = 300;
+    config.save().unwrap();
+
+    let blob = &config.storage().blobs[KEY];
+    // brightness, no name, interval 300 as a varint, offset 0.
+    let payload = [0x80, 0x01, 0x00, 0xac, 0x02, 0x00];
+    assert_eq!(blob[..2], [0x02, 0x00]);
+    assert_eq!(blob[2..6], crc32(&payload).to_le_bytes());
+    assert_eq!(blob[6..], payload);
+    assert_eq!(unseal(blob), Some((2, &payload[..])));
+}
+
+#[test]
+fn migrates_and_writes_back() {
+    let mut storage = MemoryStorage::default();
+    let old = SettingsV1 {
+        brightness: 42,
+        name: String::from("porch"),
+    };
+    storage
+        .blobs
+        .insert(String::from(KEY), seal(1, &to_bytes(&old)));
+
+    let mut config = load(storage);
+    assert_eq!(config.status(), LoadStatus::Migrated(1));
+    let expected = Settings {
+        brightness: 42,
+        name: Some(String::from("porch")),
+        ..Settings::default()
+    };
+    assert_eq!(*config.get(), expected);
+    assert_eq!(unseal(&config.storage().blobs[KEY]).unwrap().0, 2);
+
+    // The next load finds the migrated value.
+    let (_, storage) = config.into_inner();
+    let config = load(storage);
+    assert_eq!(config.status(), LoadStatus::Loaded);
+    assert_eq!(*config.get(), expected);
+}
+
+#[test]
+fn corrupt_falls_back_to_defaults() {
+    let good = seal(2, &to_bytes(&Settings::default()));
+    let mut flipped = good.clone();
+    flipped[7] ^= 1;
+    let blobs = [
+        // CRC mismatch.
+        flipped,
+        // Too short for the header.
+        good[..5].to_vec(),
+        // A version from newer firmware.
+        seal(3, &to_bytes(&Settings::default())),
+        // No migration from version 0.
+        seal(0, &[]),
+        // Trailing bytes.
+        seal(2, &[0x80, 0x01, 0x00, 0x3c, 0x00, 0x00]),
+        // A migration that fails.
+        seal(1, &[0x01]),
+    ];
+    for blob in blobs.iter() {
+        let mut storage = MemoryStorage::default();
+        storage.blobs.insert(String::from(KEY), blob.clone());
+        let mut config = load(storage);
+        assert_eq!(config.status(), LoadStatus::Corrupt);
+        assert_eq!(*config.get(), Settings::default());
+        // The stored value is left alone.
+        assert_eq!(&config.storage().blobs[KEY], blob);
+    }
+}
+
+#[test]
+fn encoding() {
+    assert_eq!(to_bytes(&300u32), [0xac, 0x02]);
+    assert_eq!(to_bytes(&-1i32), [0x01]);
+    assert_eq!(
+        to_bytes(&i64::MIN),
+        [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+    );
+    assert_eq!(to_bytes(&1.5f32), 1.5f32.to_le_bytes());
+    assert_eq!(to_bytes("hé"), [0x03, b'h', 0xc3, 0xa9]);
+    assert_eq!(to_bytes(&vec![true, false]), [0x02, 0x01, 0x00]);
+
+    assert_eq!(from_bytes::<i64>(&to_bytes(&i64::MIN)), Ok(i64::MIN));
+    assert_eq!(from_bytes::<u64>(&to_bytes(&u64::MAX)), Ok(u64::MAX));
+    assert_eq!(from_bytes::<u8>(&[0x80, 0x02]), Err(DecodeError::Overflow));
+    assert_eq!(
+        from_bytes::<i8>(&to_bytes(&-129i16)),
+        Err(DecodeError::Overflow)
+    );
+    assert_eq!(from_bytes::<u64>(&[0xff; 10]), Err(DecodeError::Overflow));
+    assert_eq!(from_bytes::<u32>(&[0x80]), Err(DecodeError::Truncated));
+    assert_eq!(from_bytes::<bool>(&[0x02]), Err(DecodeError::Invalid));
+    assert_eq!(
+        from_bytes::<String>(&[0x01, 0xff]),
+        Err(DecodeError::Invalid)
+    );
+    assert_eq!(
+        from_bytes::<Vec<u8>>(&[0x05, 0x01]),
+        Err(DecodeError::Truncated)
+    );
+    assert_eq!(
+        from_bytes::<u8>(&[0x01, 0x02]),
+        Err(DecodeError::TrailingBytes)
+    );
+}
+
+#[test]
+fn crc() {
+    // The standard check value.
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(b""), 0);
+}
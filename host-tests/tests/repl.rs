@@ -0,0 +1,224 @@
+//! Scripted terminal sessions against the console's editor and dispatcher.
+
+use std::sync::{Arc, Mutex};
+
+use host_tests::repl::{
+    split_args, CommandError, Commands, ExecuteError, LineEditor, ParseError, Repl,
+};
+
+/// A `Repl` with an `echo` command, a `sum` command that parses its
+/// arguments, and a `fail` command, with `echo`'s calls recorded.
+fn repl() -> (Repl, Arc<Mutex<Vec<Vec<String>>>>) {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let mut commands = Commands::new();
+    let recorded = calls.clone();
+    commands.register(
+        "echo",
+        "<words>...",
+        "Print the arguments",
+        move |args, out| {
+            recorded
+                .lock()
+                .unwrap()
+                .push(args.iter().map(String::from).collect());
+            writeln!(out, "{}", args.iter().collect::<Vec<_>>().join(" ")).unwrap();
+            Ok(())
+        },
+    );
+    commands.register("sum", "<a> <b>", "Add two numbers", |args, out| {
+        let a: i32 = args.parse(0)?;
+        let b: i32 = args.parse(1)?;
+        writeln!(out, "{}", a + b).unwrap();
+        Ok(())
+    });
+    commands.register("fail", "", "Always fails", |_, _| {
+        Err(CommandError::Failed("on purpose".into()))
+    });
+    (Repl::new(LineEditor::new("> ", 3), commands), calls)
+}
+
+/// Feed `input`, returning the output and the result of each line run.
+fn run(repl: &mut Repl, input: &[u8]) -> (String, Vec<Result<(), ExecuteError>>) {
+    let mut out = String::new();
+    let mut results = Vec::new();
+    for &byte in input {
+        if let Some(result) = repl.feed(byte, &mut out) {
+            results.push(result);
+        }
+    }
+    (out, results)
+}
+
+#[test]
+fn splitting() {
+    assert_eq!(
+        split_args("  nvs  set wifi 'my ssid' \"pass word\" a\\ b  ").unwrap(),
+        ["nvs", "set", "wifi", "my ssid", "pass word", "a b"]
+    );
+    assert_eq!(split_args("say \"\"").unwrap(), ["say", ""]);
+    assert_eq!(split_args("a\"b c\"d").unwrap(), ["ab cd"]);
+    assert!(split_args(" ").unwrap().is_empty());
+    assert_eq!(split_args("'open"), Err(ParseError::UnterminatedQuote));
+    assert_eq!(split_args("end\\"), Err(ParseError::TrailingBackslash));
+}
+
+#[test]
+fn runs_commands() {
+    let (mut repl, calls) = repl();
+    let mut out = String::new();
+    repl.start(&mut out);
+    assert_eq!(out, "> ");
+
+    let (out, results) = run(&mut repl, b"echo hello 'big world'\r\nsum 2 40\r");
+    assert_eq!(
+        out,
+        "echo hello 'big world'\nhello big world\n> sum 2 40\n42\n> "
+    );
+    assert_eq!(results, [Ok(()), Ok(())]);
+    assert_eq!(*calls.lock().unwrap(), [["hello", "big world"]]);
+}
+
+#[test]
+fn reports_errors() {
+    let (mut repl, _) = repl();
+    let (out, results) = run(&mut repl, b"nope\rsum 1\rsum 1 x\rfail\recho 'x\r\r");
+    assert_eq!(
+        out,
+        "nope\nunknown command \"nope\"; try \"help\"\n\
+         > sum 1\nusage: sum <a> <b>\n\
+         > sum 1 x\nerror: invalid argument \"x\"\n\
+         > fail\nerror: on purpose\n\
+         > echo 'x\nerror: unterminated quote\n\
+         > \n> "
+    );
+    assert_eq!(
+        results,
+        [
+            Err(ExecuteError::Unknown("nope".into())),
+            Err(ExecuteError::Command(CommandError::Usage)),
+            Err(ExecuteError::Command(CommandError::Failed(
+                "invalid argument \"x\"".into()
+            ))),
+            Err(ExecuteError::Command(CommandError::Failed(
+                "on purpose".into()
+            ))),
+            Err(ExecuteError::Parse(ParseError::UnterminatedQuote)),
+            Ok(()),
+        ]
+    );
+}
+
+#[test]
+fn help() {
+    let (mut repl, _) = repl();
+    let (out, _) = run(&mut repl, b"help\r");
+    assert_eq!(
+        out,
+        "help\n  \
+         echo <words>...  Print the arguments\n  \
+         fail             Always fails\n  \
+         sum <a> <b>      Add two numbers\n  \
+         help [command]   Show the commands, or how to use one\n> "
+    );
+
+    let (out, _) = run(&mut repl, b"help sum\rhelp fail\rhelp nope\r");
+    assert_eq!(
+        out,
+        "help sum\nusage: sum <a> <b>\nAdd two numbers\n\
+         > help fail\nusage: fail\nAlways fails\n\
+         > help nope\nerror: no command \"nope\"\n> "
+    );
+}
+
+#[test]
+fn replacing_a_command() {
+    let (mut repl, _) = repl();
+    repl.commands().register("sum", "", "Not really", |_, out| {
+        out.write_str("no\n").unwrap();
+        Ok(())
+    });
+    let (out, _) = run(&mut repl, b"sum 1 2\r");
+    assert_eq!(out, "sum 1 2\nno\n> ");
+}
+
+#[test]
+fn editing() {
+    let (mut repl, calls) = repl();
+    // Type "ecoh hi", fix it with Left, Backspace and retyping, then go
+    // Home and End.
+    let (_, results) = run(
+        &mut repl,
+        b"ecoh hi\x1b[D\x1b[D\x1b[D\x7f\x7fho\x1b[H\x1b[F!\r",
+    );
+    assert_eq!(results, [Ok(())]);
+    assert_eq!(*calls.lock().unwrap(), [["hi!"]]);
+
+    // Ctrl-U clears the line, Ctrl-A and Delete remove its first character,
+    // and Ctrl-C abandons a line without running it.
+    let (out, results) = run(&mut repl, b"junk\x15xecho a\x01\x1b[3~\rsum\x03");
+    assert_eq!(results, [Ok(())]);
+    assert_eq!(calls.lock().unwrap()[1], ["a"]);
+    assert!(out.ends_with("sum^C\n> "));
+}
+
+#[test]
+fn redraws_the_line() {
+    let mut editor = LineEditor::new("> ", 3);
+    let mut out = String::new();
+    for &byte in b"ac\x1b[Db" {
+        editor.feed(byte, &mut out);
+    }
+    // Moving the cursor or inserting before the end rewrites the line and
+    // moves the cursor back.
+    assert_eq!(out, "ac\r> ac\x1b[K\x1b[1D\r> abc\x1b[K\x1b[1D");
+}
+
+#[test]
+fn history() {
+    let (mut repl, calls) = repl();
+    run(&mut repl, b"echo 1\recho 2\recho 2\r\recho 3\recho 4\r");
+    // Repeats and empty lines aren't kept, and only the last three are.
+    assert_eq!(
+        repl.editor().history().collect::<Vec<_>>(),
+        ["echo 2", "echo 3", "echo 4"]
+    );
+
+    // Up twice recalls "echo 3", leaving "echo 3", "echo 4", "echo 3".
+    // Up past the oldest stays there, so one Down is "echo 4".
+    run(&mut repl, b"\x1b[A\x1b[A\r\x1b[A\x1b[A\x1b[A\x1b[A\x1b[B\r");
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls[calls.len() - 2], ["3"]);
+    assert_eq!(calls[calls.len() - 1], ["4"]);
+}
+
+#[test]
+fn history_keeps_the_draft() {
+    let (mut repl, calls) = repl();
+    run(&mut repl, b"echo old\r");
+    // Start a line, look at the history, and come back to it.
+    run(&mut repl, b"echo new\x1b[A\x1b[B\r");
+    assert_eq!(calls.lock().unwrap()[1], ["new"]);
+}
+
+#[test]
+fn interrupt_discards_the_line() {
+    let (mut repl, calls) = repl();
+    run(&mut repl, b"echo kept\r");
+    let (out, results) = run(&mut repl, b"echo dropped\x03");
+    assert!(results.is_empty());
+    assert!(out.ends_with("^C\n> "));
+    assert_eq!(repl.editor().history().collect::<Vec<_>>(), ["echo kept"]);
+
+    // Ctrl-C while browsing the history drops the draft too, so Down
+    // comes back to an empty line.
+    run(&mut repl, b"echo draft\x1b[A\x03\x1b[A\r\x1b[A\x1b[B\r");
+    assert_eq!(*calls.lock().unwrap(), [["kept"], ["kept"]]);
+}
+
+#[test]
+fn line_endings() {
+    let (mut repl, _) = repl();
+    // CR LF is one Enter, but LF alone works too.
+    let (_, results) = run(&mut repl, b"sum 1 1\r\nsum 2 2\nsum 3 3\r");
+    assert_eq!(results, [Ok(()), Ok(()), Ok(())]);
+}
@@ -31285,6 +31285,147 @@ extern "C" {
         cfg: *mut nvs_sec_cfg_t,
     ) -> esp_err_t;
 }
+pub const AF_UNSPEC: u32 = 0;
+pub const AF_INET: u32 = 2;
+pub const PF_INET: u32 = 2;
+pub const SOCK_STREAM: u32 = 1;
+pub const SOCK_DGRAM: u32 = 2;
+pub const SOCK_RAW: u32 = 3;
+pub const IPPROTO_IP: u32 = 0;
+pub const IPPROTO_ICMP: u32 = 1;
+pub const IPPROTO_TCP: u32 = 6;
+pub const IPPROTO_UDP: u32 = 17;
+pub const SOL_SOCKET: u32 = 4095;
+pub const SO_REUSEADDR: u32 = 4;
+pub const SO_KEEPALIVE: u32 = 8;
+pub const SO_BROADCAST: u32 = 32;
+pub const SO_SNDTIMEO: u32 = 4101;
+pub const SO_RCVTIMEO: u32 = 4102;
+pub const MSG_PEEK: u32 = 1;
+pub const MSG_WAITALL: u32 = 2;
+pub const MSG_OOB: u32 = 4;
+pub const MSG_DONTWAIT: u32 = 8;
+pub const MSG_MORE: u32 = 16;
+pub const SHUT_RD: u32 = 0;
+pub const SHUT_WR: u32 = 1;
+pub const SHUT_RDWR: u32 = 2;
+pub type sa_family_t = u8_t;
+pub type in_port_t = u16_t;
+pub type in_addr_t = u32_t;
+pub type socklen_t = u32_t;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct in_addr {
+    pub s_addr: in_addr_t,
+}
+#[doc = " members are in network byte order"]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sockaddr_in {
+    pub sin_len: u8_t,
+    pub sin_family: sa_family_t,
+    pub sin_port: in_port_t,
+    pub sin_addr: in_addr,
+    pub sin_zero: [crate::types::c_char; 8usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct sockaddr {
+    pub sa_len: u8_t,
+    pub sa_family: sa_family_t,
+    pub sa_data: [crate::types::c_char; 14usize],
+}
+extern "C" {
+    pub fn lwip_accept(
+        s: crate::types::c_int,
+        addr: *mut sockaddr,
+        addrlen: *mut socklen_t,
+    ) -> crate::types::c_int;
+}
+extern "C" {
+    pub fn lwip_bind(
+        s: crate::types::c_int,
+        name: *const sockaddr,
+        namelen: socklen_t,
+    ) -> crate::types::c_int;
+}
+extern "C" {
+    pub fn lwip_shutdown(s: crate::types::c_int, how: crate::types::c_int) -> crate::types::c_int;
+}
+extern "C" {
+    pub fn lwip_getsockopt(
+        s: crate::types::c_int,
+        level: crate::types::c_int,
+        optname: crate::types::c_int,
+        optval: *mut crate::types::c_void,
+        optlen: *mut socklen_t,
+    ) -> crate::types::c_int;
+}
+extern "C" {
+    pub fn lwip_setsockopt(
+        s: crate::types::c_int,
+        level: crate::types::c_int,
+        optname: crate::types::c_int,
+        optval: *const crate::types::c_void,
+        optlen: socklen_t,
+    ) -> crate::types::c_int;
+}
+extern "C" {
+    pub fn lwip_close(s: crate::types::c_int) -> crate::types::c_int;
+}
+extern "C" {
+    pub fn lwip_connect(
+        s: crate::types::c_int,
+        name: *const sockaddr,
+        namelen: socklen_t,
+    ) -> crate::types::c_int;
+}
+extern "C" {
+    pub fn lwip_listen(s: crate::types::c_int, backlog: crate::types::c_int) -> crate::types::c_int;
+}
+extern "C" {
+    pub fn lwip_recv(
+        s: crate::types::c_int,
+        mem: *mut crate::types::c_void,
+        len: size_t,
+        flags: crate::types::c_int,
+    ) -> ssize_t;
+}
+extern "C" {
+    pub fn lwip_recvfrom(
+        s: crate::types::c_int,
+        mem: *mut crate::types::c_void,
+        len: size_t,
+        flags: crate::types::c_int,
+        from: *mut sockaddr,
+        fromlen: *mut socklen_t,
+    ) -> ssize_t;
+}
+extern "C" {
+    pub fn lwip_send(
+        s: crate::types::c_int,
+        dataptr: *const crate::types::c_void,
+        size: size_t,
+        flags: crate::types::c_int,
+    ) -> ssize_t;
+}
+extern "C" {
+    pub fn lwip_sendto(
+        s: crate::types::c_int,
+        dataptr: *const crate::types::c_void,
+        size: size_t,
+        flags: crate::types::c_int,
+        to: *const sockaddr,
+        tolen: socklen_t,
+    ) -> ssize_t;
+}
+extern "C" {
+    pub fn lwip_socket(
+        domain: crate::types::c_int,
+        type_: crate::types::c_int,
+        protocol: crate::types::c_int,
+    ) -> crate::types::c_int;
+}
 pub type __builtin_va_list = __va_list_tag;
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -31293,3 +31434,25 @@ pub struct __va_list_tag {
     pub __va_reg: *mut crate::types::c_int,
     pub __va_ndx: crate::types::c_int,
 }
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct esp_backtrace_frame_t {
+    pub pc: u32,
+    pub sp: u32,
+    pub next_pc: u32,
+}
+extern "C" {
+    pub fn esp_backtrace_get_start(pc: *mut u32, sp: *mut u32, next_pc: *mut u32);
+}
+extern "C" {
+    pub fn esp_backtrace_get_next_frame(frame: *mut esp_backtrace_frame_t) -> bool;
+}
+extern "C" {
+    pub fn esp_backtrace_print(depth: crate::types::c_int) -> esp_err_t;
+}
+extern "C" {
+    pub fn esp_vfs_dev_uart_use_nonblocking(uart_num: crate::types::c_int);
+}
+extern "C" {
+    pub fn esp_vfs_dev_uart_use_driver(uart_num: crate::types::c_int);
+}
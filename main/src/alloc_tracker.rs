@@ -0,0 +1,441 @@
+//! A global allocator wrapper that keeps track of what is allocated, for
+//! finding leaks and explaining fragmentation. Enabled with the
+//! `alloc_tracking` feature, which makes [`TrackingAllocator`] the
+//! `#[global_allocator]`, `crate::ALLOC`.
+//!
+//! Allocations are counted in total, per power-of-two size class and per
+//! task, with their peaks. Up to `MAX_TRACKED` live allocations are also
+//! recorded individually, with the task that made them and its current tag
+//! (see [`set_tag()`]), so they can be listed, and compared between two
+//! [`Checkpoint`]s to find leaks:
+//!
+//! ```ignore
+//! let before = crate::ALLOC.checkpoint();
+//! do_work();
+//! let report = crate::ALLOC.leaks(&before, &crate::ALLOC.checkpoint());
+//! ```
+//!
+//! The bookkeeping never allocates, and only depends on esp-idf through a
+//! [`Platform`], so it can be exercised on the host on top of the system
+//! allocator.
+//!
+//! [`TrackingAllocator`]: struct.TrackingAllocator.html
+//! [`set_tag()`]: struct.TrackingAllocator.html#method.set_tag
+//! [`Checkpoint`]: struct.Checkpoint.html
+//! [`Platform`]: trait.Platform.html
+
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+
+/// Number of size classes: up to 16 bytes, up to 32 bytes, and so on, with
+/// the last one holding everything larger.
+pub const SIZE_CLASSES: usize = 12;
+/// Tasks with their own counters. Further tasks share `Stats::other_tasks`.
+pub const MAX_TASKS: usize = 16;
+/// Live allocations recorded individually.
+pub const MAX_TRACKED: usize = 256;
+
+/// What the tracker needs from the system.
+pub trait Platform {
+    /// An identifier of the running task, never 0.
+    fn current_task() -> usize;
+    /// Run `f` with the tracker's state locked against other tasks and
+    /// cores. `f` doesn't allocate.
+    fn locked<R>(f: impl FnOnce() -> R) -> R;
+}
+
+/// The size class of an allocation of `size` bytes.
+pub fn size_class(size: usize) -> usize {
+    let mut class = 0;
+    let mut limit = 16;
+    while size > limit && class < SIZE_CLASSES - 1 {
+        limit <<= 1;
+        class += 1;
+    }
+    class
+}
+
+/// The largest allocation in a size class, or `None` for the last one.
+pub fn size_class_limit(class: usize) -> Option<usize> {
+    if class < SIZE_CLASSES - 1 {
+        Some(16 << class)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub allocs: u32,
+    pub frees: u32,
+    pub live_bytes: usize,
+    pub peak_bytes: usize,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Counters {
+            allocs: 0,
+            frees: 0,
+            live_bytes: 0,
+            peak_bytes: 0,
+        }
+    }
+
+    pub fn live_allocs(&self) -> u32 {
+        self.allocs.wrapping_sub(self.frees)
+    }
+
+    fn alloc(&mut self, size: usize) {
+        self.allocs = self.allocs.wrapping_add(1);
+        self.live_bytes += size;
+        self.peak_bytes = self.peak_bytes.max(self.live_bytes);
+    }
+
+    fn free(&mut self, size: usize) {
+        self.frees = self.frees.wrapping_add(1);
+        // A task can free memory that another task allocated.
+        self.live_bytes = self.live_bytes.saturating_sub(size);
+    }
+}
+
+/// Counters of one task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TaskStats {
+    /// As returned by `Platform::current_task`.
+    pub task: usize,
+    /// The task's current tag.
+    pub tag: &'static str,
+    pub counters: Counters,
+}
+
+/// A live allocation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Allocation {
+    pub ptr: usize,
+    pub size: usize,
+    pub task: usize,
+    /// The task's tag when it allocated.
+    pub tag: &'static str,
+    /// Allocation order, compared with `Checkpoint`s.
+    pub seq: u32,
+}
+
+impl Allocation {
+    const EMPTY: Allocation = Allocation {
+        ptr: 0,
+        size: 0,
+        task: 0,
+        tag: "",
+        seq: 0,
+    };
+}
+
+/// Overall counters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stats {
+    pub total: Counters,
+    pub size_classes: [Counters; SIZE_CLASSES],
+    /// Tasks beyond the first `MAX_TASKS`.
+    pub other_tasks: Counters,
+    /// Live allocations that didn't fit in the table of `MAX_TRACKED`.
+    pub untracked: u32,
+}
+
+/// The tracker's state at some point, for [`leaks()`].
+///
+/// [`leaks()`]: struct.TrackingAllocator.html#method.leaks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    seq: u32,
+    total: Counters,
+}
+
+/// What changed between two checkpoints.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeakReport {
+    /// Change in live allocations.
+    pub allocs_delta: i64,
+    /// Change in live bytes.
+    pub bytes_delta: i64,
+    /// Allocations made between the checkpoints that are still live.
+    pub outstanding: Vec<Allocation>,
+}
+
+#[derive(Clone, Copy)]
+struct TaskSlot {
+    task: usize,
+    tag: &'static str,
+    counters: Counters,
+}
+
+impl TaskSlot {
+    const EMPTY: TaskSlot = TaskSlot {
+        task: 0,
+        tag: "",
+        counters: Counters::new(),
+    };
+}
+
+struct State {
+    stats: Stats,
+    tasks: [TaskSlot; MAX_TASKS],
+    live: [Allocation; MAX_TRACKED],
+    next_seq: u32,
+}
+
+impl State {
+    /// The index of `task`'s slot, claiming a free one if needed.
+    fn task_index(&mut self, task: usize) -> Option<usize> {
+        let i = self
+            .tasks
+            .iter()
+            .position(|s| s.task == task || s.task == 0)?;
+        self.tasks[i].task = task;
+        Some(i)
+    }
+
+    fn task_counters(&mut self, task: usize) -> &mut Counters {
+        match self.task_index(task) {
+            Some(i) => &mut self.tasks[i].counters,
+            None => &mut self.stats.other_tasks,
+        }
+    }
+
+    fn count_alloc(&mut self, size: usize, task: usize) {
+        self.stats.total.alloc(size);
+        self.stats.size_classes[size_class(size)].alloc(size);
+        self.task_counters(task).alloc(size);
+    }
+
+    fn count_free(&mut self, size: usize, task: usize) {
+        self.stats.total.free(size);
+        self.stats.size_classes[size_class(size)].free(size);
+        self.task_counters(task).free(size);
+    }
+
+    fn record_alloc(&mut self, ptr: usize, size: usize, task: usize) {
+        self.count_alloc(size, task);
+
+        let seq = self.next_seq;
+        self.next_seq = seq.wrapping_add(1);
+        let tag = match self.tasks.iter().find(|s| s.task == task) {
+            Some(slot) => slot.tag,
+            None => "",
+        };
+        match self.live.iter_mut().find(|a| a.ptr == 0) {
+            Some(entry) => {
+                *entry = Allocation {
+                    ptr,
+                    size,
+                    task,
+                    tag,
+                    seq,
+                }
+            }
+            None => self.stats.untracked += 1,
+        }
+    }
+
+    fn record_free(&mut self, ptr: usize, size: usize, current_task: usize) {
+        // Charge the task that made the allocation, if it was recorded.
+        let task = match self.live.iter_mut().find(|a| a.ptr == ptr) {
+            Some(entry) => {
+                let task = entry.task;
+                *entry = Allocation::EMPTY;
+                task
+            }
+            None => {
+                self.stats.untracked = self.stats.untracked.saturating_sub(1);
+                current_task
+            }
+        };
+        self.count_free(size, task);
+    }
+
+    /// Move a recorded allocation, keeping its task, tag and place in the
+    /// allocation order.
+    fn record_realloc(
+        &mut self,
+        old: usize,
+        old_size: usize,
+        new: usize,
+        new_size: usize,
+        current_task: usize,
+    ) {
+        match self.live.iter().position(|a| a.ptr == old) {
+            Some(i) => {
+                let task = self.live[i].task;
+                self.live[i].ptr = new;
+                self.live[i].size = new_size;
+                self.count_free(old_size, task);
+                self.count_alloc(new_size, task);
+            }
+            None => {
+                self.record_free(old, old_size, current_task);
+                self.record_alloc(new, new_size, current_task);
+            }
+        }
+    }
+}
+
+/// Wraps the allocator `A`, keeping track of allocations.
+pub struct TrackingAllocator<A, P> {
+    inner: A,
+    state: UnsafeCell<State>,
+    _platform: PhantomData<fn() -> P>,
+}
+
+// `state` is only accessed while `P::locked`.
+unsafe impl<A: Sync, P> Sync for TrackingAllocator<A, P> {}
+
+impl<A, P: Platform> TrackingAllocator<A, P> {
+    pub const fn new(inner: A) -> Self {
+        TrackingAllocator {
+            inner,
+            state: UnsafeCell::new(State {
+                stats: Stats {
+                    total: Counters::new(),
+                    size_classes: [Counters::new(); SIZE_CLASSES],
+                    other_tasks: Counters::new(),
+                    untracked: 0,
+                },
+                tasks: [TaskSlot::EMPTY; MAX_TASKS],
+                live: [Allocation::EMPTY; MAX_TRACKED],
+                next_seq: 0,
+            }),
+            _platform: PhantomData,
+        }
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        P::locked(|| f(unsafe { &mut *self.state.get() }))
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.with_state(|s| s.stats)
+    }
+
+    /// Counters of each task that has allocated.
+    pub fn tasks(&self) -> Vec<TaskStats> {
+        let mut out = Vec::with_capacity(MAX_TASKS);
+        self.with_state(|s| {
+            for slot in s.tasks.iter().filter(|s| s.task != 0) {
+                out.push(TaskStats {
+                    task: slot.task,
+                    tag: slot.tag,
+                    counters: slot.counters,
+                });
+            }
+        });
+        out
+    }
+
+    /// Set the tag recorded with the current task's allocations, returning
+    /// the previous one. Does nothing for tasks beyond the first
+    /// `MAX_TASKS`.
+    pub fn set_tag(&self, tag: &'static str) -> &'static str {
+        let task = P::current_task();
+        self.with_state(|s| match s.task_index(task) {
+            Some(i) => core::mem::replace(&mut s.tasks[i].tag, tag),
+            None => "",
+        })
+    }
+
+    /// Run `f` with the current task's allocations tagged with `tag`.
+    pub fn tagged<R>(&self, tag: &'static str, f: impl FnOnce() -> R) -> R {
+        let previous = self.set_tag(tag);
+        let r = f();
+        self.set_tag(previous);
+        r
+    }
+
+    /// The recorded live allocations, oldest first.
+    pub fn outstanding(&self) -> Vec<Allocation> {
+        self.outstanding_before(None, u32::max_value())
+    }
+
+    /// The recorded live allocations among the last `window` made before
+    /// sequence number `end`, or before now, oldest first.
+    fn outstanding_before(&self, end: Option<u32>, window: u32) -> Vec<Allocation> {
+        // Allocate before locking; the tracker can't allocate while locked.
+        let mut out = Vec::with_capacity(MAX_TRACKED);
+        let end = self.with_state(|s| {
+            let end = end.unwrap_or(s.next_seq);
+            // Sequence numbers wrap, so allocations are compared by how
+            // long before `end` they were made, which is at least 1.
+            out.extend(
+                s.live
+                    .iter()
+                    .filter(|a| a.ptr != 0 && end.wrapping_sub(a.seq).wrapping_sub(1) < window)
+                    .copied(),
+            );
+            end
+        });
+        out.sort_by_key(|a| core::cmp::Reverse(end.wrapping_sub(a.seq)));
+        out
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.with_state(|s| Checkpoint {
+            seq: s.next_seq,
+            total: s.stats.total,
+        })
+    }
+
+    /// Compare two checkpoints, listing the allocations made between them
+    /// that are still live.
+    pub fn leaks(&self, from: &Checkpoint, to: &Checkpoint) -> LeakReport {
+        LeakReport {
+            allocs_delta: to.total.live_allocs() as i64 - from.total.live_allocs() as i64,
+            bytes_delta: to.total.live_bytes as i64 - from.total.live_bytes as i64,
+            outstanding: self.outstanding_before(Some(to.seq), to.seq.wrapping_sub(from.seq)),
+        }
+    }
+
+    /// Set the sequence number of the next allocation, to exercise
+    /// wraparound.
+    #[doc(hidden)]
+    pub fn set_next_seq(&self, seq: u32) {
+        self.with_state(|s| s.next_seq = seq);
+    }
+}
+
+unsafe impl<A: GlobalAlloc, P: Platform> GlobalAlloc for TrackingAllocator<A, P> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            let task = P::current_task();
+            self.with_state(|s| s.record_alloc(ptr as usize, layout.size(), task));
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            let task = P::current_task();
+            self.with_state(|s| s.record_alloc(ptr as usize, layout.size(), task));
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let task = P::current_task();
+        self.with_state(|s| s.record_free(ptr as usize, layout.size(), task));
+        self.inner.dealloc(ptr, layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = self.inner.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            let task = P::current_task();
+            self.with_state(|s| {
+                s.record_realloc(ptr as usize, layout.size(), new as usize, new_size, task)
+            });
+        }
+        new
+    }
+}
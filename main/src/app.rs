@@ -1,16 +1,24 @@
 use core::fmt::Write as _;
 use embedded_hal::digital::v2::OutputPin as _;
-use esp_idf_hal::{errors::EspError, gpio, i2c};
+use esp_idf_hal::{gpio, i2c};
 use ssd1306::{prelude::*, Builder};
 
+use crate::console::Console;
 use crate::freertos_task::{Cpu, CpuAffinity, CurrentTask, Task};
 use crate::freertos_units::Duration;
+use crate::nvs::NvsPartition;
 
 #[no_mangle]
 pub fn app_main() {
-    EspError(unsafe { esp_idf_sys::nvs_flash_init() })
-        .into_result()
-        .unwrap();
+    crate::logger::init().unwrap();
+    crate::println!(
+        "firmware {} on ESP-IDF {}, {}",
+        crate::system::firmware_version(),
+        crate::system::idf_version(),
+        crate::system::ChipInfo::get()
+    );
+    let nvs = NvsPartition::init_default().unwrap();
+    let _ = crate::crash::report_last(&nvs);
 
     let oled_fn = move || {
         let oled_i2c_master = unsafe {
@@ -53,15 +61,8 @@ pub fn app_main() {
     let led_blink_fn = move || {
         let mut led_gpio = unsafe { gpio::OutputPin::new(25) };
 
-        let mut n = 0;
+        // The console shares UART0 with `println!`, so stay quiet here.
         loop {
-            n += 1;
-            crate::println!(
-                "loop {} stack_hw_mark: {}",
-                n,
-                CurrentTask::get_stack_high_water_mark()
-            );
-
             led_gpio.set_high().unwrap();
             CurrentTask::delay(Duration::ms(100));
             led_gpio.set_low().unwrap();
@@ -76,4 +77,6 @@ pub fn app_main() {
         .unwrap();
 
     crate::wifi::initialize_wifi();
+
+    let _console_h = Console::new().builtin_commands(nvs).start().unwrap();
 }
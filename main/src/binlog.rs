@@ -0,0 +1,119 @@
+//! Compact binary logging, enabled with the `binlog` feature.
+//!
+//! [`binlog!`] call sites don't format anything on the device: each one
+//! places an [`Entry`] describing its format string in the `.rodata.binlog`
+//! input sections, and sends only the entry's address, a timestamp and the
+//! raw arguments over the console UART. The format strings and targets go
+//! in `.binlog.strings`, which `binlog.ld` keeps out of the flashed image.
+//! `binlog-decoder` turns the stream back into text using the firmware's
+//! ELF file. See `binlog_wire` for the frame format.
+//!
+//! Without the feature, `binlog!` forwards to the `log` crate, so call sites
+//! don't need to change. Format strings may only use the placeholders the
+//! decoder understands: `{}`, `{:?}`, `{:x}`, `{:X}`, `{:#x}` and `{:#X}`.
+//!
+//! [`binlog!`]: ../macro.binlog.html
+//! [`Entry`]: struct.Entry.html
+
+use crate::binlog_wire::{PayloadWriter, MAX_FRAME};
+
+#[doc(hidden)]
+pub use log as __log;
+
+/// Describes a call site. The decoder reads these from the ELF file; all
+/// fields are little-endian 32-bit words on the ESP32. The strings are only
+/// in the ELF file, so the device must never read them.
+#[repr(C)]
+pub struct Entry {
+    /// The `log::Level`, from 1 (error) to 5 (trace).
+    pub level: u32,
+    pub format: *const u8,
+    pub format_len: usize,
+    pub target: *const u8,
+    pub target_len: usize,
+}
+unsafe impl Sync for Entry {}
+
+/// Copy a string literal into an array, for `binlog!`'s statics.
+#[doc(hidden)]
+pub const fn __bytes<const N: usize>(s: &str) -> [u8; N] {
+    let bytes = s.as_bytes();
+    let mut out = [0; N];
+    let mut i = 0;
+    while i < N {
+        out[i] = bytes[i];
+        i += 1;
+    }
+    out
+}
+
+/// Sends one record. Used by [`binlog!`].
+///
+/// [`binlog!`]: ../macro.binlog.html
+pub struct Encoder {
+    pub payload: PayloadWriter,
+}
+
+impl Encoder {
+    pub fn new(entry: &'static Entry) -> Self {
+        let timestamp = unsafe { esp_idf_sys::esp_log_timestamp() };
+        Encoder {
+            payload: PayloadWriter::new(entry as *const Entry as u32, timestamp),
+        }
+    }
+
+    /// Write the record to the console. The frame has no line feeds for
+    /// the VFS to translate.
+    pub fn send(self) {
+        let mut frame = [0u8; MAX_FRAME];
+        let len = self.payload.frame(&mut frame);
+        let _lock = crate::lock();
+        let _ = crate::print::write_all(1, &frame[..len]);
+    }
+}
+
+/// Log a record in the compact binary format. The level must be a constant
+/// `log::Level`.
+///
+/// ```ignore
+/// binlog!(log::Level::Info, "rssi {} on channel {}", rssi, channel);
+/// ```
+#[cfg(feature = "binlog")]
+#[macro_export]
+macro_rules! binlog {
+    ($level:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {{
+        let level: $crate::binlog::__log::Level = $level;
+        if level <= $crate::binlog::__log::STATIC_MAX_LEVEL
+            && level <= $crate::binlog::__log::max_level()
+        {
+            #[link_section = ".binlog.strings"]
+            static FORMAT: [u8; $fmt.len()] = $crate::binlog::__bytes($fmt);
+            #[link_section = ".binlog.strings"]
+            static TARGET: [u8; module_path!().len()] =
+                $crate::binlog::__bytes(module_path!());
+            #[link_section = ".rodata.binlog"]
+            #[used]
+            static ENTRY: $crate::binlog::Entry = $crate::binlog::Entry {
+                level: $level as u32,
+                format: FORMAT.as_ptr(),
+                format_len: FORMAT.len(),
+                target: TARGET.as_ptr(),
+                target_len: TARGET.len(),
+            };
+            #[allow(unused_mut)]
+            let mut encoder = $crate::binlog::Encoder::new(&ENTRY);
+            $( $crate::binlog_wire::Format::encode(&$arg, &mut encoder.payload); )*
+            encoder.send();
+        }
+    }};
+}
+
+/// Log a record in the compact binary format. Without the `binlog` feature
+/// this forwards to `log::log!`.
+#[cfg(not(feature = "binlog"))]
+#[macro_export]
+macro_rules! binlog {
+    ($level:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {
+        $crate::binlog::__log::log!(target: module_path!(), $level, $fmt $(, $arg)*)
+    };
+}
@@ -0,0 +1,423 @@
+//! Wire format of the compact binary log, shared by the firmware's encoder
+//! and the host-side decoder in `binlog-decoder`.
+//!
+//! Each record is a payload, COBS-encoded and written between two `0x00`
+//! bytes, so the decoder can resynchronize and tell records apart from
+//! plain text on the same UART. The console's VFS turns every `\n` into
+//! `\r\n`, so line feeds and `ESCAPE` itself are then escaped as `ESCAPE`
+//! followed by the byte XOR `0x20`. A payload is:
+//!
+//! | Field     | Encoding                                            |
+//! |-----------|-----------------------------------------------------|
+//! | magic     | `MAGIC`                                             |
+//! | flags     | one byte; `FLAG_TRUNCATED` if arguments were dropped|
+//! | entry     | address of the call site's `Entry`, u32 LE          |
+//! | timestamp | milliseconds since boot, LEB128                     |
+//! | arguments | a tag byte each, followed by the value              |
+//!
+//! This module is plain Rust and doesn't touch `esp-idf`, so it can be
+//! exercised on the host.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const MAGIC: u8 = 0xb1;
+pub const FLAG_TRUNCATED: u8 = 0x01;
+
+pub const ESCAPE: u8 = 0x7d;
+const ESCAPE_XOR: u8 = 0x20;
+
+/// Largest payload, before COBS encoding.
+pub const MAX_PAYLOAD: usize = 128;
+/// Largest COBS-encoded payload.
+const MAX_ENCODED: usize = MAX_PAYLOAD + MAX_PAYLOAD / 254 + 1;
+/// Largest frame: the encoded payload with every byte escaped, and both
+/// delimiters.
+pub const MAX_FRAME: usize = 2 * MAX_ENCODED + 2;
+
+const TAG_UNSIGNED: u8 = 0;
+const TAG_SIGNED: u8 = 1;
+const TAG_F32: u8 = 2;
+const TAG_BOOL: u8 = 3;
+const TAG_STR: u8 = 4;
+const TAG_BYTES: u8 = 5;
+
+/// Builds a payload in a fixed buffer. Arguments that don't fit are dropped
+/// and the record is marked as truncated.
+pub struct PayloadWriter {
+    buf: [u8; MAX_PAYLOAD],
+    len: usize,
+}
+
+impl PayloadWriter {
+    pub fn new(entry: u32, timestamp: u32) -> Self {
+        let mut w = PayloadWriter {
+            buf: [0; MAX_PAYLOAD],
+            len: 0,
+        };
+        w.buf[0] = MAGIC;
+        w.buf[2..6].copy_from_slice(&entry.to_le_bytes());
+        w.len = 6;
+        let mut ts = [0; 10];
+        let n = varint(timestamp as u64, &mut ts);
+        w.push(&ts[..n]);
+        w
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.buf[1] & FLAG_TRUNCATED != 0
+    }
+
+    /// Append one tagged argument, all or nothing.
+    fn push_arg(&mut self, tag: u8, head: &[u8], body: &[u8]) {
+        if self.is_truncated() || self.len + 1 + head.len() + body.len() > MAX_PAYLOAD {
+            self.buf[1] |= FLAG_TRUNCATED;
+            return;
+        }
+        self.push(&[tag]);
+        self.push(head);
+        self.push(body);
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    pub fn unsigned(&mut self, v: u64) {
+        let mut b = [0; 10];
+        let n = varint(v, &mut b);
+        self.push_arg(TAG_UNSIGNED, &b[..n], &[]);
+    }
+
+    pub fn signed(&mut self, v: i64) {
+        let mut b = [0; 10];
+        let n = varint(((v << 1) ^ (v >> 63)) as u64, &mut b);
+        self.push_arg(TAG_SIGNED, &b[..n], &[]);
+    }
+
+    pub fn f32(&mut self, v: f32) {
+        self.push_arg(TAG_F32, &v.to_bits().to_le_bytes(), &[]);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.push_arg(TAG_BOOL, &[v as u8], &[]);
+    }
+
+    pub fn str(&mut self, v: &str) {
+        let mut b = [0; 10];
+        let n = varint(v.len() as u64, &mut b);
+        self.push_arg(TAG_STR, &b[..n], v.as_bytes());
+    }
+
+    pub fn bytes(&mut self, v: &[u8]) {
+        let mut b = [0; 10];
+        let n = varint(v.len() as u64, &mut b);
+        self.push_arg(TAG_BYTES, &b[..n], v);
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Write the delimited, COBS-encoded and escaped frame into `out`,
+    /// returning its length.
+    pub fn frame(&self, out: &mut [u8; MAX_FRAME]) -> usize {
+        // Encode into the end of `out`, then escape it into the start: even
+        // with every byte escaped, the writes never catch up with the bytes
+        // still to be read.
+        let start = MAX_FRAME - MAX_ENCODED;
+        let n = cobs_encode(self.payload(), &mut out[start..]);
+        out[0] = 0;
+        let mut len = 1;
+        for i in start..start + n {
+            let b = out[i];
+            if b == b'\n' || b == ESCAPE {
+                out[len] = ESCAPE;
+                out[len + 1] = b ^ ESCAPE_XOR;
+                len += 2;
+            } else {
+                out[len] = b;
+                len += 1;
+            }
+        }
+        out[len] = 0;
+        len + 1
+    }
+}
+
+fn varint(mut v: u64, out: &mut [u8; 10]) -> usize {
+    let mut i = 0;
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            out[i] = byte;
+            return i + 1;
+        }
+        out[i] = byte | 0x80;
+        i += 1;
+    }
+}
+
+/// COBS-encode `data` into `out`, which must hold at least
+/// `data.len() + data.len() / 254 + 1` bytes. Returns the encoded length.
+pub fn cobs_encode(data: &[u8], out: &mut [u8]) -> usize {
+    let mut code_idx = 0;
+    let mut code = 1u8;
+    let mut o = 1;
+    for &b in data {
+        if b == 0 {
+            out[code_idx] = code;
+            code_idx = o;
+            o += 1;
+            code = 1;
+        } else {
+            out[o] = b;
+            o += 1;
+            code += 1;
+            if code == 0xff {
+                out[code_idx] = code;
+                code_idx = o;
+                o += 1;
+                code = 1;
+            }
+        }
+    }
+    out[code_idx] = code;
+    o
+}
+
+/// Undo the escaping of a frame without its delimiters, or `None` if it
+/// ends in the middle of an escape.
+pub fn unescape(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&b) = bytes.next() {
+        if b == ESCAPE {
+            out.push(bytes.next()? ^ ESCAPE_XOR);
+        } else {
+            out.push(b);
+        }
+    }
+    Some(out)
+}
+
+/// Decode a COBS block without its delimiters.
+pub fn cobs_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let code = data[i] as usize;
+        if code == 0 || i + code > data.len() + 1 {
+            return None;
+        }
+        let end = (i + code).min(data.len());
+        out.extend_from_slice(&data[i + 1..end]);
+        i += code;
+        if code < 0xff && i < data.len() {
+            out.push(0);
+        }
+    }
+    Some(out)
+}
+
+/// A value that can be logged.
+pub trait Format {
+    fn encode(&self, w: &mut PayloadWriter);
+}
+
+impl<T: Format + ?Sized> Format for &T {
+    fn encode(&self, w: &mut PayloadWriter) {
+        (**self).encode(w)
+    }
+}
+
+macro_rules! impl_format {
+    ($method:ident as $as:ty: $($ty:ty),*) => {
+        $(
+            impl Format for $ty {
+                fn encode(&self, w: &mut PayloadWriter) {
+                    w.$method(*self as $as)
+                }
+            }
+        )*
+    };
+}
+
+impl_format!(unsigned as u64: u8, u16, u32, u64, usize);
+impl_format!(signed as i64: i8, i16, i32, i64, isize);
+impl_format!(f32 as f32: f32);
+impl_format!(bool as bool: bool);
+
+impl Format for str {
+    fn encode(&self, w: &mut PayloadWriter) {
+        w.str(self)
+    }
+}
+
+impl Format for String {
+    fn encode(&self, w: &mut PayloadWriter) {
+        w.str(self)
+    }
+}
+
+impl Format for [u8] {
+    fn encode(&self, w: &mut PayloadWriter) {
+        w.bytes(self)
+    }
+}
+
+/// A decoded argument.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Arg {
+    Unsigned(u64),
+    Signed(i64),
+    F32(f32),
+    Bool(bool),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+/// A decoded payload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub entry: u32,
+    pub timestamp: u32,
+    pub args: Vec<Arg>,
+    pub truncated: bool,
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.0.len() {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.take(1)?[0];
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Some(v);
+            }
+        }
+        None
+    }
+}
+
+impl Record {
+    /// Parse a decoded payload, or `None` if it isn't a record.
+    pub fn parse(payload: &[u8]) -> Option<Record> {
+        let mut r = Reader(payload);
+        let head = r.take(6)?;
+        if head[0] != MAGIC {
+            return None;
+        }
+        let truncated = head[1] & FLAG_TRUNCATED != 0;
+        let entry = u32::from_le_bytes([head[2], head[3], head[4], head[5]]);
+        let timestamp = r.varint()? as u32;
+
+        let mut args = Vec::new();
+        while !r.0.is_empty() {
+            let arg = match r.take(1)?[0] {
+                TAG_UNSIGNED => Arg::Unsigned(r.varint()?),
+                TAG_SIGNED => {
+                    let v = r.varint()?;
+                    Arg::Signed(((v >> 1) as i64) ^ -((v & 1) as i64))
+                }
+                TAG_F32 => {
+                    let b = r.take(4)?;
+                    Arg::F32(f32::from_bits(u32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+                }
+                TAG_BOOL => Arg::Bool(r.take(1)?[0] != 0),
+                TAG_STR => {
+                    let len = r.varint()? as usize;
+                    Arg::Str(String::from_utf8_lossy(r.take(len)?).into())
+                }
+                TAG_BYTES => {
+                    let len = r.varint()? as usize;
+                    Arg::Bytes(r.take(len)?.into())
+                }
+                _ => return None,
+            };
+            args.push(arg);
+        }
+
+        Some(Record {
+            entry,
+            timestamp,
+            args,
+            truncated,
+        })
+    }
+}
+
+/// Render `format` with `args`, supporting the `{}`, `{:?}`, `{:x}`, `{:X}`,
+/// `{:#x}` and `{:#X}` placeholders and `{{`/`}}` escapes. Missing
+/// arguments are shown as `{?}`.
+pub fn render(format: &str, args: &[Arg]) -> String {
+    use core::fmt::Write as _;
+
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut rest = format;
+    while let Some(i) = rest.find(&['{', '}'][..]) {
+        out.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        if let Some(after) = tail.strip_prefix('}') {
+            out.push('}');
+            rest = after;
+            continue;
+        }
+        let end = match tail.find('}') {
+            Some(end) => end,
+            None => {
+                out.push_str(tail);
+                return out;
+            }
+        };
+        let spec = &tail[1..end];
+        rest = &tail[end + 1..];
+
+        let arg = match args.next() {
+            Some(arg) => arg,
+            None => {
+                out.push_str("{?}");
+                continue;
+            }
+        };
+        let _ = match (spec, arg) {
+            (":x", Arg::Unsigned(v)) => write!(out, "{:x}", v),
+            (":X", Arg::Unsigned(v)) => write!(out, "{:X}", v),
+            (":#x", Arg::Unsigned(v)) => write!(out, "{:#x}", v),
+            (":#X", Arg::Unsigned(v)) => write!(out, "{:#X}", v),
+            (":x", Arg::Signed(v)) => write!(out, "{:x}", v),
+            (":X", Arg::Signed(v)) => write!(out, "{:X}", v),
+            (":#x", Arg::Signed(v)) => write!(out, "{:#x}", v),
+            (":#X", Arg::Signed(v)) => write!(out, "{:#X}", v),
+            (":?", Arg::Str(v)) => write!(out, "{:?}", v),
+            (":?", Arg::F32(v)) => write!(out, "{:?}", v),
+            (_, Arg::Unsigned(v)) => write!(out, "{}", v),
+            (_, Arg::Signed(v)) => write!(out, "{}", v),
+            (_, Arg::F32(v)) => write!(out, "{}", v),
+            (_, Arg::Bool(v)) => write!(out, "{}", v),
+            (_, Arg::Str(v)) => write!(out, "{}", v),
+            (_, Arg::Bytes(v)) => write!(out, "{:?}", v),
+        };
+    }
+    out.push_str(rest);
+    out
+}
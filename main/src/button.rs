@@ -0,0 +1,343 @@
+//! Debounced push buttons that report clicks, double clicks and long
+//! presses.
+//!
+//! ```ignore
+//! let button = Button::new(0).start()?;
+//! while let Some(event) = button.receive(Duration::infinite()) {
+//!     match event {
+//!         ButtonEvent::Click => crate::println!("click"),
+//!         ButtonEvent::LongPress => crate::println!("long press"),
+//!         _ => (),
+//!     }
+//! }
+//! ```
+//!
+//! The pin's interrupt timestamps each edge and queues it, then (re)starts
+//! a FreeRTOS software timer. The timer's callback, in the timer task,
+//! feeds the edges to a [`Recognizer`] and sets the timer again for
+//! whatever the recognizer is waiting for next, so nothing polls the pin
+//! while the button is left alone.
+//!
+//! [`Recognizer`]: ../button_gesture/struct.Recognizer.html
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use esp_idf_hal::errors::EspError;
+use esp_idf_sys::types::c_void;
+
+pub use crate::button_gesture::{ButtonEvent, Timings};
+pub use crate::gpio_interrupt::Pull;
+
+use crate::button_gesture::Recognizer;
+use crate::freertos_queue::Queue;
+use crate::freertos_task;
+use crate::freertos_units::{Duration, DurationTicks};
+use crate::gpio_interrupt::{InterruptPin, Trigger};
+
+// `tmrCOMMAND_CHANGE_PERIOD`, `tmrCOMMAND_DELETE` and
+// `tmrCOMMAND_CHANGE_PERIOD_FROM_ISR`, which are macros.
+const CHANGE_PERIOD: esp_idf_sys::BaseType_t = 4;
+const DELETE: esp_idf_sys::BaseType_t = 5;
+const CHANGE_PERIOD_FROM_ISR: esp_idf_sys::BaseType_t = 9;
+
+/// FreeRTOS keeps the pointer rather than copying the name.
+const TIMER_NAME: &[u8] = b"button\0";
+
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    Esp(EspError),
+    Task(freertos_task::Error),
+}
+
+impl From<EspError> for Error {
+    fn from(e: EspError) -> Self {
+        Error::Esp(e)
+    }
+}
+
+impl From<freertos_task::Error> for Error {
+    fn from(e: freertos_task::Error) -> Self {
+        Error::Task(e)
+    }
+}
+
+/// A level change seen by the interrupt.
+#[derive(Copy, Clone)]
+struct RawEdge {
+    /// Milliseconds since boot.
+    at: u64,
+    high: bool,
+}
+
+/// Owned by the timer: only its callback touches it.
+struct Shared {
+    pin: i32,
+    active_low: bool,
+    recognizer: Recognizer,
+    /// The level of the last edge fed to the recognizer.
+    high: bool,
+    pressed: Arc<AtomicBool>,
+    raw: Arc<Queue<RawEdge>>,
+    events: Arc<Queue<ButtonEvent>>,
+}
+
+/// What the interrupt handler needs.
+struct Isr {
+    pin: i32,
+    raw: Arc<Queue<RawEdge>>,
+    timer: esp_idf_sys::TimerHandle_t,
+    debounce: esp_idf_sys::TickType_t,
+}
+
+// The timer handle is only used with `FromISR` commands.
+unsafe impl Send for Isr {}
+
+/// Helper for setting a button up. Instantiate with [`Button::new()`].
+///
+/// [`Button::new()`]: struct.Button.html#method.new
+pub struct ButtonBuilder {
+    pin: i32,
+    active_low: bool,
+    pull: Pull,
+    timings: Timings,
+    queue_len: usize,
+}
+
+impl ButtonBuilder {
+    /// Set whether the input is low while the button is pressed. Defaults
+    /// to true, for a button to ground.
+    pub fn active_low(self, active_low: bool) -> Self {
+        ButtonBuilder { active_low, ..self }
+    }
+
+    /// Set the pull resistor. Defaults to a pull-up.
+    pub fn pull(self, pull: Pull) -> Self {
+        ButtonBuilder { pull, ..self }
+    }
+
+    /// Set the debounce, double-click, long-press and repeat times.
+    pub fn timings(self, timings: Timings) -> Self {
+        ButtonBuilder { timings, ..self }
+    }
+
+    /// Set how many events can wait to be received. Later ones are dropped
+    /// while the queue is full. Defaults to 16.
+    pub fn queue_len(self, queue_len: usize) -> Self {
+        ButtonBuilder { queue_len, ..self }
+    }
+
+    pub fn start(self) -> Result<Button, Error> {
+        let events = Arc::new(Queue::new(self.queue_len)?);
+        let raw = Arc::new(Queue::new(self.queue_len)?);
+        let debounce = Duration::ms(self.timings.debounce_ms).to_ticks().max(1);
+        let pressed = Arc::new(AtomicBool::new(false));
+        let shared = Box::into_raw(Box::new(Shared {
+            pin: self.pin,
+            active_low: self.active_low,
+            recognizer: Recognizer::new(self.timings),
+            // Whatever the level is, the first edge below brings it up to
+            // date.
+            high: self.active_low,
+            pressed: pressed.clone(),
+            raw: raw.clone(),
+            events: events.clone(),
+        }));
+        let timer = unsafe {
+            esp_idf_sys::xTimerCreate(
+                TIMER_NAME.as_ptr() as *const _,
+                debounce,
+                // One-shot; the callback sets it again.
+                0,
+                shared as *mut c_void,
+                Some(on_timer),
+            )
+        };
+        if timer.is_null() {
+            drop(unsafe { Box::from_raw(shared) });
+            return Err(Error::Task(freertos_task::Error::NoMem));
+        }
+
+        let isr = Isr {
+            pin: self.pin,
+            raw: raw.clone(),
+            timer,
+            debounce,
+        };
+        // Reading the pin, sending from an interrupt and timer commands
+        // from an interrupt are all safe in the handler.
+        let pin = unsafe {
+            InterruptPin::new(self.pin)
+                .trigger(Trigger::AnyEdge)
+                .pull(self.pull)
+                .on_interrupt(move |woken| isr.edge(woken))
+        };
+        let pin = match pin {
+            Ok(pin) => pin,
+            Err(e) => {
+                unsafe { delete(timer, shared) };
+                return Err(e.into());
+            }
+        };
+
+        // Start from the current level, in case the button is already
+        // held.
+        let _ = raw.send(
+            RawEdge {
+                at: now_ms(),
+                high: pin.is_high(),
+            },
+            Duration::zero(),
+        );
+        unsafe { set_timer(timer, debounce, Duration::infinite().to_ticks()) };
+        Ok(Button {
+            pin: Some(pin),
+            timer,
+            shared,
+            pressed,
+            events,
+        })
+    }
+}
+
+/// A button on a GPIO input. Dropping it stops watching the pin.
+pub struct Button {
+    /// Only `None` while dropping.
+    pin: Option<InterruptPin>,
+    timer: esp_idf_sys::TimerHandle_t,
+    shared: *mut Shared,
+    pressed: Arc<AtomicBool>,
+    events: Arc<Queue<ButtonEvent>>,
+}
+
+unsafe impl Send for Button {}
+unsafe impl Sync for Button {}
+
+impl Button {
+    /// Prepare a builder object for a button on GPIO `pin`.
+    pub fn new(pin: i32) -> ButtonBuilder {
+        ButtonBuilder {
+            pin,
+            active_low: true,
+            pull: Pull::Up,
+            timings: Timings::default(),
+            queue_len: 16,
+        }
+    }
+
+    /// Wait up to `timeout` for the next event.
+    pub fn receive(&self, timeout: impl DurationTicks) -> Option<ButtonEvent> {
+        self.events.receive(timeout)
+    }
+
+    /// The queue the events are sent to, e.g. to hand to another task.
+    pub fn events(&self) -> Arc<Queue<ButtonEvent>> {
+        self.events.clone()
+    }
+
+    /// Whether the button is pressed, once debounced.
+    pub fn is_pressed(&self) -> bool {
+        self.pressed.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for Button {
+    fn drop(&mut self) {
+        // No more edges once the handler is gone.
+        drop(self.pin.take());
+        unsafe { delete(self.timer, self.shared) };
+    }
+}
+
+impl Isr {
+    /// Runs in interrupt context.
+    fn edge(&self, woken: &mut bool) {
+        let edge = RawEdge {
+            at: now_ms(),
+            high: unsafe { esp_idf_sys::gpio_get_level(self.pin) } != 0,
+        };
+        // Restarting the timer only after queueing means the callback
+        // either sees the edge or runs again afterwards. Both are dropped
+        // while their queues are full; the callback catches up from the
+        // pin's level.
+        if self.raw.send_from_isr(edge, woken).is_err() {
+            return;
+        }
+        let mut higher_priority_woken = 0;
+        unsafe {
+            esp_idf_sys::xTimerGenericCommand(
+                self.timer,
+                CHANGE_PERIOD_FROM_ISR,
+                self.debounce,
+                &mut higher_priority_woken,
+                0,
+            )
+        };
+        *woken |= higher_priority_woken != 0;
+    }
+}
+
+fn now_ms() -> u64 {
+    unsafe { esp_idf_sys::esp_timer_get_time() as u64 / 1000 }
+}
+
+/// (Re)start `timer` to fire after `period` ticks.
+unsafe fn set_timer(
+    timer: esp_idf_sys::TimerHandle_t,
+    period: esp_idf_sys::TickType_t,
+    wait: esp_idf_sys::TickType_t,
+) {
+    esp_idf_sys::xTimerGenericCommand(timer, CHANGE_PERIOD, period, ptr::null_mut(), wait);
+}
+
+/// Delete `timer`, then free `shared` once the timer task is done with it.
+/// Commands and pended calls are handled in order, so a callback that's
+/// already running finishes first.
+unsafe fn delete(timer: esp_idf_sys::TimerHandle_t, shared: *mut Shared) {
+    let forever = Duration::infinite().to_ticks();
+    esp_idf_sys::xTimerGenericCommand(timer, DELETE, 0, ptr::null_mut(), forever);
+    esp_idf_sys::xTimerPendFunctionCall(Some(free_shared), shared as *mut c_void, 0, forever);
+}
+
+unsafe extern "C" fn free_shared(shared: *mut c_void, _: u32) {
+    drop(Box::from_raw(shared as *mut Shared));
+}
+
+/// Runs in the timer task, which has a small stack: no formatting here.
+unsafe extern "C" fn on_timer(timer: esp_idf_sys::TimerHandle_t) {
+    let shared = &mut *(esp_idf_sys::pvTimerGetTimerID(timer) as *mut Shared);
+    let events = &shared.events;
+    let mut emit = |event| {
+        let _ = events.send(event, Duration::zero());
+    };
+
+    while let Some(edge) = shared.raw.receive(Duration::zero()) {
+        shared.high = edge.high;
+        let pressed = edge.high != shared.active_low;
+        shared.recognizer.edge(edge.at, pressed, &mut emit);
+    }
+    let now = now_ms();
+    let high = esp_idf_sys::gpio_get_level(shared.pin) != 0;
+    if high != shared.high {
+        // Edges were dropped while the queue was full.
+        shared.high = high;
+        let pressed = high != shared.active_low;
+        shared.recognizer.edge(now, pressed, &mut emit);
+    }
+    shared.recognizer.update(now, &mut emit);
+    shared
+        .pressed
+        .store(shared.recognizer.is_pressed(), Ordering::Release);
+
+    if let Some(deadline) = shared.recognizer.next_deadline() {
+        let wait = deadline.saturating_sub(now).min(u32::max_value() as u64) as u32;
+        set_timer(timer, Duration::ms(wait).to_ticks().max(1), 0);
+    }
+    // An edge that arrived after draining restarted the timer before the
+    // command above; make sure it's still seen in time.
+    if !shared.raw.is_empty() {
+        let debounce = Duration::ms(shared.recognizer.timings().debounce_ms);
+        set_timer(timer, debounce.to_ticks().max(1), 0);
+    }
+}
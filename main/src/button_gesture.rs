@@ -0,0 +1,221 @@
+//! Debouncing and gesture recognition for the buttons in [`button`].
+//!
+//! A [`Recognizer`] is fed the raw edges of a button's input, each with the
+//! time it was seen, and the current time whenever its
+//! [`next_deadline()`] passes. It works out presses, clicks, double
+//! clicks, long presses and repeats from those alone, so it can be tested
+//! on the host; see `host-tests`.
+//!
+//! [`button`]: ../button/index.html
+//! [`Recognizer`]: struct.Recognizer.html
+//! [`next_deadline()`]: struct.Recognizer.html#method.next_deadline
+
+/// Something the user did with a button.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum ButtonEvent {
+    Pressed,
+    Released,
+    /// A short press and release, not followed by another press within the
+    /// double-click time.
+    Click,
+    /// Two short presses within the double-click time. Replaces the clicks.
+    DoubleClick,
+    /// The button has been held for the long-press time. Its release isn't
+    /// a click.
+    LongPress,
+    /// The button is still held after a long press; sent every repeat
+    /// interval.
+    Repeat,
+}
+
+/// How long things take, in milliseconds.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub struct Timings {
+    /// How long the input must be steady before a change counts.
+    pub debounce_ms: u32,
+    /// How soon after a click a second press makes a double click. With 0,
+    /// clicks are sent as soon as the button is released.
+    pub double_click_ms: u32,
+    /// How long the button must be held for a long press. 0 disables long
+    /// presses.
+    pub long_press_ms: u32,
+    /// How often to repeat while held after a long press. 0 disables
+    /// repeats.
+    pub repeat_interval_ms: u32,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Timings {
+            debounce_ms: 20,
+            double_click_ms: 300,
+            long_press_ms: 800,
+            repeat_interval_ms: 0,
+        }
+    }
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+enum State {
+    Idle,
+    Held {
+        /// Whether this press follows a click closely enough to be the
+        /// second of a double click.
+        second: bool,
+        /// When the next long press or repeat is due, if any is.
+        next: Option<u64>,
+        long: bool,
+    },
+    /// A click that may turn out to be the first of a double click.
+    Clicked {
+        at: u64,
+    },
+}
+
+/// Turns a button's raw edges into `ButtonEvent`s. Times are in
+/// milliseconds, from any fixed point, and must not go backwards.
+#[derive(Clone, Debug)]
+pub struct Recognizer {
+    timings: Timings,
+    /// The debounced state.
+    pressed: bool,
+    /// The raw state since the last edge, if it hasn't been steady for the
+    /// debounce time yet.
+    unsettled: Option<(bool, u64)>,
+    state: State,
+}
+
+impl Recognizer {
+    /// Start with the button released.
+    pub fn new(timings: Timings) -> Self {
+        Recognizer {
+            timings,
+            pressed: false,
+            unsettled: None,
+            state: State::Idle,
+        }
+    }
+
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    /// Whether the button is pressed, once debounced.
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// The input changed to `pressed` at `at`. Anything due before then
+    /// happens first.
+    pub fn edge(&mut self, at: u64, pressed: bool, emit: &mut dyn FnMut(ButtonEvent)) {
+        self.update(at, emit);
+        self.unsettled = Some((pressed, at));
+    }
+
+    /// Act on everything due by `now`, in order.
+    pub fn update(&mut self, now: u64, emit: &mut dyn FnMut(ButtonEvent)) {
+        while let Some(due) = self.next_deadline() {
+            if due > now {
+                break;
+            }
+            match self.settle_time() {
+                Some(settle) if settle <= due => self.settle(settle, emit),
+                _ => self.expire(due, emit),
+            }
+        }
+    }
+
+    /// When `update` next has something to do.
+    pub fn next_deadline(&self) -> Option<u64> {
+        let gesture = match self.state {
+            State::Idle => None,
+            State::Held { next, .. } => next,
+            State::Clicked { at } => Some(at + self.timings.double_click_ms as u64),
+        };
+        match (self.settle_time(), gesture) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn settle_time(&self) -> Option<u64> {
+        self.unsettled
+            .map(|(_, since)| since + self.timings.debounce_ms as u64)
+    }
+
+    /// The input has been steady since the last edge.
+    fn settle(&mut self, at: u64, emit: &mut dyn FnMut(ButtonEvent)) {
+        let (pressed, _) = self.unsettled.take().unwrap();
+        if pressed == self.pressed {
+            return;
+        }
+        self.pressed = pressed;
+        if pressed {
+            self.press(at, emit);
+        } else {
+            self.release(at, emit);
+        }
+    }
+
+    fn press(&mut self, at: u64, emit: &mut dyn FnMut(ButtonEvent)) {
+        emit(ButtonEvent::Pressed);
+        let long_press = self.timings.long_press_ms;
+        self.state = State::Held {
+            second: matches!(self.state, State::Clicked { .. }),
+            next: if long_press == 0 {
+                None
+            } else {
+                Some(at + long_press as u64)
+            },
+            long: false,
+        };
+    }
+
+    fn release(&mut self, at: u64, emit: &mut dyn FnMut(ButtonEvent)) {
+        emit(ButtonEvent::Released);
+        self.state = match self.state {
+            State::Held { long: true, .. } => State::Idle,
+            State::Held { second: true, .. } => {
+                emit(ButtonEvent::DoubleClick);
+                State::Idle
+            }
+            _ if self.timings.double_click_ms == 0 => {
+                emit(ButtonEvent::Click);
+                State::Idle
+            }
+            _ => State::Clicked { at },
+        };
+    }
+
+    /// A gesture deadline has passed.
+    fn expire(&mut self, at: u64, emit: &mut dyn FnMut(ButtonEvent)) {
+        let interval = self.timings.repeat_interval_ms as u64;
+        self.state = match self.state {
+            State::Held { second, long, .. } => {
+                if !long {
+                    if second {
+                        // The earlier click stands on its own.
+                        emit(ButtonEvent::Click);
+                    }
+                    emit(ButtonEvent::LongPress);
+                } else {
+                    emit(ButtonEvent::Repeat);
+                }
+                State::Held {
+                    second: false,
+                    next: if interval == 0 {
+                        None
+                    } else {
+                        Some(at + interval)
+                    },
+                    long: true,
+                }
+            }
+            State::Clicked { .. } => {
+                emit(ButtonEvent::Click);
+                State::Idle
+            }
+            State::Idle => State::Idle,
+        };
+    }
+}
@@ -0,0 +1,509 @@
+//! Wi-Fi provisioning through a SoftAP captive portal.
+//!
+//! The device brings up an access point, answers every DNS query with its own
+//! address so that phones and laptops pop up their captive-portal browser, and
+//! serves a form asking for the network's SSID and password. Submitted
+//! credentials are checked by connecting to the network, and are persisted by
+//! the Wi-Fi driver once that succeeds.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use esp_idf_hal::errors::EspError;
+use esp_idf_sys::types::c_void;
+
+use crate::dns;
+use crate::freertos_task::{self, Task};
+use crate::freertos_units::{Duration, DurationTicks};
+use crate::http::{self, Method, Request, Response};
+use crate::net::{Ipv4Addr, SocketAddrV4};
+use crate::netif::NetworkInterface;
+use crate::socket::{self, TcpListener, TcpStream, UdpSocket};
+
+const CONNECTED_BIT: esp_idf_sys::EventBits_t = esp_idf_sys::BIT0;
+const FAILED_BIT: esp_idf_sys::EventBits_t = esp_idf_sys::BIT1;
+
+/// How often the server loops wake up to check whether they should stop.
+const POLL_INTERVAL_MS: u32 = 500;
+
+/// Credentials accepted through the portal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    pub ssid: String,
+    pub password: String,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    Esp(EspError),
+    Socket(socket::Error),
+    Task(freertos_task::Error),
+}
+
+impl From<EspError> for Error {
+    fn from(e: EspError) -> Self {
+        Error::Esp(e)
+    }
+}
+
+impl From<socket::Error> for Error {
+    fn from(e: socket::Error) -> Self {
+        Error::Socket(e)
+    }
+}
+
+impl From<freertos_task::Error> for Error {
+    fn from(e: freertos_task::Error) -> Self {
+        Error::Task(e)
+    }
+}
+
+/// Helper for running the portal. Instantiate with [`CaptivePortal::new()`].
+///
+/// [`CaptivePortal::new()`]: struct.CaptivePortal.html#method.new
+pub struct CaptivePortalBuilder<'a> {
+    ap_ssid: &'a str,
+    ap_password: &'a str,
+    channel: u8,
+    connect_timeout: Duration,
+}
+
+impl<'a> CaptivePortalBuilder<'a> {
+    /// Set the SSID of the provisioning access point.
+    pub fn ap_ssid<'b>(self, ssid: &'b str) -> CaptivePortalBuilder<'b>
+    where
+        'a: 'b,
+    {
+        CaptivePortalBuilder {
+            ap_ssid: ssid,
+            ..self
+        }
+    }
+
+    /// Protect the access point with WPA2. It is open by default; the password
+    /// must be at least 8 characters long.
+    pub fn ap_password<'b>(self, password: &'b str) -> CaptivePortalBuilder<'b>
+    where
+        'a: 'b,
+    {
+        CaptivePortalBuilder {
+            ap_password: password,
+            ..self
+        }
+    }
+
+    /// Set the access point's channel.
+    pub fn channel(self, channel: u8) -> Self {
+        CaptivePortalBuilder { channel, ..self }
+    }
+
+    /// Set how long to wait for the device to join the submitted network.
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        CaptivePortalBuilder {
+            connect_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// Run the portal until valid credentials have been submitted, then shut
+    /// the access point down and return them. The device is left connected
+    /// to the new network in station mode.
+    ///
+    /// This blocks the calling task. The Wi-Fi driver must already have been
+    /// initialized, e.g. with `wifi::init_driver`.
+    pub fn run(self) -> Result<Credentials, Error> {
+        start_access_point(self.ap_ssid, self.ap_password, self.channel)?;
+        let ap_ip = NetworkInterface::Ap.ip_info()?.ip;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let dns_stop = stop.clone();
+        Task::new()
+            .name("captive_dns")
+            .stack_size(3072)
+            .start(move || {
+                if let Err(e) = serve_dns(ap_ip, &dns_stop) {
+                    crate::println!("captive portal DNS failed: {:?}", e);
+                }
+            })?;
+
+        let result = serve_http(ap_ip, self.connect_timeout);
+        stop.store(true, Ordering::Release);
+
+        let credentials = result?;
+        EspError(unsafe { esp_idf_sys::esp_wifi_set_mode(esp_idf_sys::wifi_mode_t_WIFI_MODE_STA) })
+            .into_result()?;
+        Ok(credentials)
+    }
+}
+
+/// Marker type for the portal. Instantiate with [`CaptivePortal::new()`].
+pub struct CaptivePortal;
+
+impl CaptivePortal {
+    /// Prepare a builder object for the portal.
+    pub fn new() -> CaptivePortalBuilder<'static> {
+        CaptivePortalBuilder {
+            ap_ssid: "esp32-setup",
+            ap_password: "",
+            channel: 1,
+            connect_timeout: Duration::ms(15_000),
+        }
+    }
+}
+
+fn start_access_point(ssid: &str, password: &str, channel: u8) -> Result<(), EspError> {
+    let invalid_arg = EspError(esp_idf_sys::ESP_ERR_INVALID_ARG as esp_idf_sys::esp_err_t);
+    let mut config: esp_idf_sys::wifi_config_t = unsafe { core::mem::zeroed() };
+    unsafe {
+        let ap = &mut config.ap;
+        if ssid.len() > ap.ssid.len() || password.len() >= ap.password.len() {
+            return Err(invalid_arg);
+        }
+        if !password.is_empty() && password.len() < 8 {
+            return Err(invalid_arg);
+        }
+        ap.ssid[..ssid.len()].copy_from_slice(ssid.as_bytes());
+        ap.ssid_len = ssid.len() as u8;
+        ap.password[..password.len()].copy_from_slice(password.as_bytes());
+        ap.channel = channel;
+        ap.authmode = if password.is_empty() {
+            esp_idf_sys::wifi_auth_mode_t_WIFI_AUTH_OPEN
+        } else {
+            esp_idf_sys::wifi_auth_mode_t_WIFI_AUTH_WPA2_PSK
+        };
+        ap.max_connection = 4;
+        ap.beacon_interval = 100;
+
+        // The provisioning AP's settings shouldn't outlive this boot.
+        EspError(esp_idf_sys::esp_wifi_set_storage(
+            esp_idf_sys::wifi_storage_t_WIFI_STORAGE_RAM,
+        ))
+        .into_result()?;
+        EspError(esp_idf_sys::esp_wifi_set_mode(
+            esp_idf_sys::wifi_mode_t_WIFI_MODE_APSTA,
+        ))
+        .into_result()?;
+        EspError(esp_idf_sys::esp_wifi_set_config(
+            esp_idf_sys::esp_interface_t_ESP_IF_WIFI_AP,
+            &mut config,
+        ))
+        .into_result()?;
+        EspError(esp_idf_sys::esp_wifi_start()).into_result()
+    }
+}
+
+fn serve_dns(ap_ip: Ipv4Addr, stop: &AtomicBool) -> Result<(), socket::Error> {
+    let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 53))?;
+    socket.set_read_timeout(Some(Duration::ms(POLL_INTERVAL_MS)))?;
+
+    let mut query = [0u8; dns::MAX_MESSAGE_LEN];
+    let mut response = [0u8; dns::MAX_MESSAGE_LEN];
+    while !stop.load(Ordering::Acquire) {
+        let (len, from) = match socket.recv_from(&mut query) {
+            Ok(r) => r,
+            Err(e) if e.is_timeout() => continue,
+            Err(e) => return Err(e),
+        };
+        if let Ok(resp_len) = dns::build_response(&query[..len], ap_ip, &mut response) {
+            let _ = socket.send_to(&response[..resp_len], from);
+        }
+    }
+    Ok(())
+}
+
+/// What the portal has seen so far.
+enum Status {
+    WaitingForInput,
+    Failed(String),
+    Connected(Credentials),
+}
+
+fn serve_http(ap_ip: Ipv4Addr, connect_timeout: Duration) -> Result<Credentials, Error> {
+    let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 80))?;
+    listener.set_accept_timeout(Some(Duration::ms(POLL_INTERVAL_MS)))?;
+
+    let host = format!("{}", ap_ip);
+    let home = format!("http://{}/", ap_ip);
+    let mut status = Status::WaitingForInput;
+    // Once connected, keep serving a little longer so the browser can fetch
+    // the status page before the access point goes away.
+    let mut linger = 0;
+
+    loop {
+        if let Status::Connected(_) = status {
+            if linger == 0 {
+                break;
+            }
+            linger -= 1;
+        }
+
+        let mut stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.is_timeout() => continue,
+            Err(e) => return Err(e.into()),
+        };
+        stream.set_read_timeout(Some(Duration::ms(5_000)))?;
+
+        let mut buf = [0u8; 2048];
+        let len = match read_request(&mut stream, &mut buf) {
+            Ok(len) => len,
+            Err(_) => continue,
+        };
+        let request = match Request::parse_within(&buf[..len], buf.len()) {
+            Ok(request) => request,
+            Err(_) => continue,
+        };
+
+        // Anything addressed to another host is a connectivity check, or a
+        // page the user tried to open; send it to the portal.
+        if request.header("Host") != Some(host.as_str()) {
+            respond(&mut stream, &Response::redirect(&home));
+            continue;
+        }
+
+        match (request.method, request.path) {
+            (Method::Post, "/connect") => {
+                let body = core::str::from_utf8(request.body).unwrap_or("");
+                let ssid = http::form_value(body, "ssid").unwrap_or_default();
+                let password = http::form_value(body, "password").unwrap_or_default();
+                if ssid.is_empty() {
+                    respond(
+                        &mut stream,
+                        &Response::html(&form_page(Some("Please enter an SSID."))),
+                    );
+                    continue;
+                }
+
+                // The connection attempt may move the access point to another
+                // channel, so answer first and let the page poll for status.
+                respond(&mut stream, &Response::html(&connecting_page(&ssid)));
+                drop(stream);
+
+                status = match try_connect(&ssid, &password, connect_timeout) {
+                    Ok(()) => {
+                        linger = 20_000 / POLL_INTERVAL_MS;
+                        Status::Connected(Credentials { ssid, password })
+                    }
+                    Err(_) => Status::Failed(ssid),
+                };
+            }
+            (Method::Get, "/status") => {
+                let page = match &status {
+                    Status::Connected(c) => connected_page(&c.ssid),
+                    Status::Failed(ssid) => {
+                        let msg = format!("Couldn't connect to \"{}\".", ssid);
+                        form_page(Some(&msg))
+                    }
+                    Status::WaitingForInput => form_page(None),
+                };
+                respond(&mut stream, &Response::html(&page));
+                if let Status::Connected(_) = status {
+                    linger = 0;
+                }
+            }
+            (Method::Get, _) | (Method::Head, _) => {
+                respond(&mut stream, &Response::html(&form_page(None)));
+            }
+            _ => respond(&mut stream, &Response::redirect(&home)),
+        }
+    }
+
+    match status {
+        Status::Connected(credentials) => Ok(credentials),
+        _ => unreachable!("portal only stops once connected"),
+    }
+}
+
+/// Read until a complete request has arrived, returning its length.
+fn read_request(stream: &mut TcpStream, buf: &mut [u8]) -> Result<usize, socket::Error> {
+    let mut len = 0;
+    while len < buf.len() {
+        let n = stream.read(&mut buf[len..])?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+        match Request::parse_within(&buf[..len], buf.len()) {
+            Err(http::ParseError::Incomplete) => (),
+            _ => break,
+        }
+    }
+    Ok(len)
+}
+
+fn respond(stream: &mut TcpStream, response: &Response) {
+    let mut out = Vec::new();
+    response.write_to(&mut out);
+    let _ = stream.write_all(&out);
+    let _ = stream.shutdown_write();
+}
+
+const PAGE_HEAD: &str = "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+<meta name=\"viewport\" content=\"width=device-width,initial-scale=1\">\
+<title>Wi-Fi setup</title></head><body><h1>Wi-Fi setup</h1>";
+const PAGE_TAIL: &str = "</body></html>";
+
+fn form_page(error: Option<&str>) -> String {
+    let mut page = String::from(PAGE_HEAD);
+    if let Some(error) = error {
+        page.push_str("<p><b>");
+        http::html_escape(error, &mut page);
+        page.push_str("</b></p>");
+    }
+    page.push_str(
+        "<form method=\"post\" action=\"/connect\">\
+<p><label>Network name<br><input name=\"ssid\" maxlength=\"32\" required></label></p>\
+<p><label>Password<br><input name=\"password\" type=\"password\" maxlength=\"64\"></label></p>\
+<p><input type=\"submit\" value=\"Connect\"></p></form>",
+    );
+    page.push_str(PAGE_TAIL);
+    page
+}
+
+fn connecting_page(ssid: &str) -> String {
+    let mut page = String::from(PAGE_HEAD);
+    page.push_str("<meta http-equiv=\"refresh\" content=\"10;url=/status\"><p>Connecting to \"");
+    http::html_escape(ssid, &mut page);
+    page.push_str("\"&hellip;</p>");
+    page.push_str(PAGE_TAIL);
+    page
+}
+
+fn connected_page(ssid: &str) -> String {
+    let mut page = String::from(PAGE_HEAD);
+    page.push_str("<p>Connected to \"");
+    http::html_escape(ssid, &mut page);
+    page.push_str("\". You can close this page.</p>");
+    page.push_str(PAGE_TAIL);
+    page
+}
+
+/// Try to join `ssid` as a station, and persist the configuration if that
+/// succeeds.
+fn try_connect(ssid: &str, password: &str, timeout: Duration) -> Result<(), EspError> {
+    let invalid_arg = EspError(esp_idf_sys::ESP_ERR_INVALID_ARG as esp_idf_sys::esp_err_t);
+    let mut config: esp_idf_sys::wifi_config_t = unsafe { core::mem::zeroed() };
+    unsafe {
+        let sta = &mut config.sta;
+        if ssid.len() > sta.ssid.len() || password.len() > sta.password.len() {
+            return Err(invalid_arg);
+        }
+        sta.ssid[..ssid.len()].copy_from_slice(ssid.as_bytes());
+        sta.password[..password.len()].copy_from_slice(password.as_bytes());
+    }
+
+    unsafe {
+        let _ = esp_idf_sys::esp_wifi_disconnect();
+        EspError(esp_idf_sys::esp_wifi_set_config(
+            esp_idf_sys::esp_interface_t_ESP_IF_WIFI_STA,
+            &mut config,
+        ))
+        .into_result()?;
+
+        let event_group = esp_idf_sys::xEventGroupCreate();
+        if event_group.is_null() {
+            return Err(EspError(
+                esp_idf_sys::ESP_ERR_NO_MEM as esp_idf_sys::esp_err_t,
+            ));
+        }
+        let register = |base, id| {
+            EspError(esp_idf_sys::esp_event_handler_register(
+                base,
+                id,
+                Some(connect_event_handler),
+                event_group,
+            ))
+            .into_result()
+        };
+        let unregister = || {
+            esp_idf_sys::esp_event_handler_unregister(
+                esp_idf_sys::WIFI_EVENT,
+                esp_idf_sys::wifi_event_t_WIFI_EVENT_STA_DISCONNECTED as i32,
+                Some(connect_event_handler),
+            );
+            esp_idf_sys::esp_event_handler_unregister(
+                esp_idf_sys::IP_EVENT,
+                esp_idf_sys::ip_event_t_IP_EVENT_STA_GOT_IP as i32,
+                Some(connect_event_handler),
+            );
+        };
+
+        let result = register(
+            esp_idf_sys::WIFI_EVENT,
+            esp_idf_sys::wifi_event_t_WIFI_EVENT_STA_DISCONNECTED as i32,
+        )
+        .and_then(|()| {
+            register(
+                esp_idf_sys::IP_EVENT,
+                esp_idf_sys::ip_event_t_IP_EVENT_STA_GOT_IP as i32,
+            )
+        })
+        .and_then(|()| EspError(esp_idf_sys::esp_wifi_connect()).into_result())
+        .and_then(|()| {
+            let bits = esp_idf_sys::xEventGroupWaitBits(
+                event_group,
+                CONNECTED_BIT | FAILED_BIT,
+                1,
+                0,
+                timeout.to_ticks(),
+            );
+            if bits & CONNECTED_BIT != 0 {
+                Ok(())
+            } else if bits & FAILED_BIT != 0 {
+                Err(EspError(esp_idf_sys::ESP_FAIL))
+            } else {
+                Err(EspError(
+                    esp_idf_sys::ESP_ERR_TIMEOUT as esp_idf_sys::esp_err_t,
+                ))
+            }
+        });
+        unregister();
+        esp_idf_sys::vEventGroupDelete(event_group);
+
+        if result.is_err() {
+            let _ = esp_idf_sys::esp_wifi_disconnect();
+            return result;
+        }
+
+        // Store the working configuration so the driver uses it on the next
+        // boot.
+        EspError(esp_idf_sys::esp_wifi_set_storage(
+            esp_idf_sys::wifi_storage_t_WIFI_STORAGE_FLASH,
+        ))
+        .into_result()?;
+        EspError(esp_idf_sys::esp_wifi_set_config(
+            esp_idf_sys::esp_interface_t_ESP_IF_WIFI_STA,
+            &mut config,
+        ))
+        .into_result()
+    }
+}
+
+extern "C" fn connect_event_handler(
+    arg: *mut c_void,
+    event_base: esp_idf_sys::esp_event_base_t,
+    event_id: i32,
+    event_data: *mut c_void,
+) {
+    let event_group = arg as esp_idf_sys::EventGroupHandle_t;
+    unsafe {
+        if event_base == esp_idf_sys::IP_EVENT
+            && event_id as u32 == esp_idf_sys::ip_event_t_IP_EVENT_STA_GOT_IP
+        {
+            esp_idf_sys::xEventGroupSetBits(event_group, CONNECTED_BIT);
+        } else if event_base == esp_idf_sys::WIFI_EVENT
+            && event_id as u32 == esp_idf_sys::wifi_event_t_WIFI_EVENT_STA_DISCONNECTED
+        {
+            // `try_connect`'s own `esp_wifi_disconnect()` is reported
+            // asynchronously, possibly after the handler is registered.
+            let event = &*(event_data as *const esp_idf_sys::wifi_event_sta_disconnected_t);
+            if event.reason as esp_idf_sys::wifi_err_reason_t
+                != esp_idf_sys::wifi_err_reason_t_WIFI_REASON_ASSOC_LEAVE
+            {
+                esp_idf_sys::xEventGroupSetBits(event_group, FAILED_BIT);
+            }
+        }
+    }
+}
@@ -0,0 +1,532 @@
+//! An interactive serial console.
+//!
+//! ```ignore
+//! let _console = Console::new()
+//!     .builtin_commands(nvs)
+//!     .register_command("led", "on|off", "Switch the LED", move |args, out| {
+//!         match args.required(0)? {
+//!             "on" => led.set_high().map_err(CommandError::failed)?,
+//!             "off" => led.set_low().map_err(CommandError::failed)?,
+//!             _ => return Err(CommandError::Usage),
+//!         }
+//!         writeln!(out, "ok")?;
+//!         Ok(())
+//!     })
+//!     .start()?;
+//! ```
+//!
+//! Line editing and dispatch are in [`repl`]. The console installs the
+//! UART's driver and routes the port's VFS device through it, so `print!`
+//! and the logger keep working if they share the port; their output may
+//! still land in the middle of a line being typed.
+//!
+//! [`repl`]: ../repl/index.html
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use esp_idf_hal::errors::EspError;
+use log::LevelFilter;
+
+use crate::freertos_task::{self, Cpu, CpuAffinity, Task, TaskState};
+use crate::heap::HeapStats;
+use crate::logger;
+use crate::net::MacAddress;
+use crate::netif::NetworkInterface;
+use crate::nvs::{NvsNamespace, NvsPartition, NvsType, NvsValue, OpenMode};
+use crate::repl::{Args, CommandError, Commands, LineEditor, Repl};
+use crate::system;
+use crate::uart::{Port, Uart};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    Esp(EspError),
+    Task(freertos_task::Error),
+}
+
+impl From<EspError> for Error {
+    fn from(e: EspError) -> Self {
+        Error::Esp(e)
+    }
+}
+
+impl From<freertos_task::Error> for Error {
+    fn from(e: freertos_task::Error) -> Self {
+        Error::Task(e)
+    }
+}
+
+/// Helper for starting the console. Instantiate with [`Console::new()`].
+///
+/// [`Console::new()`]: struct.Console.html#method.new
+pub struct ConsoleBuilder<'a> {
+    port: Port,
+    baud_rate: u32,
+    pins: Option<(i32, i32)>,
+    prompt: &'a str,
+    history_len: usize,
+    stack_size: u32,
+    commands: Commands,
+}
+
+impl<'a> ConsoleBuilder<'a> {
+    /// Set the UART. Defaults to UART0, the one the bootloader prints to.
+    pub fn port(self, port: Port) -> Self {
+        ConsoleBuilder { port, ..self }
+    }
+
+    /// Set the baud rate. Defaults to 115200.
+    pub fn baud_rate(self, baud_rate: u32) -> Self {
+        ConsoleBuilder { baud_rate, ..self }
+    }
+
+    /// Route the UART to these GPIOs. By default the pins are left as they
+    /// are, which suits UART0.
+    pub fn pins(self, tx_pin: i32, rx_pin: i32) -> Self {
+        ConsoleBuilder {
+            pins: Some((tx_pin, rx_pin)),
+            ..self
+        }
+    }
+
+    pub fn prompt<'b>(self, prompt: &'b str) -> ConsoleBuilder<'b>
+    where
+        'a: 'b,
+    {
+        ConsoleBuilder { prompt, ..self }
+    }
+
+    /// Set how many lines Up and Down can recall. Defaults to 16.
+    pub fn history_len(self, history_len: usize) -> Self {
+        ConsoleBuilder {
+            history_len,
+            ..self
+        }
+    }
+
+    /// Set the console task's stack size. Handlers run on it.
+    pub fn stack_size(self, stack_size: u32) -> Self {
+        ConsoleBuilder { stack_size, ..self }
+    }
+
+    /// Add a command, replacing any with the same name. See
+    /// [`Commands::register()`].
+    ///
+    /// [`Commands::register()`]: ../repl/struct.Commands.html#method.register
+    pub fn register_command<F>(
+        mut self,
+        name: &'static str,
+        usage: &'static str,
+        help: &'static str,
+        handler: F,
+    ) -> Self
+    where
+        F: FnMut(Args<'_>, &mut dyn fmt::Write) -> Result<(), CommandError> + Send + 'static,
+    {
+        self.commands.register(name, usage, help, handler);
+        self
+    }
+
+    /// Add the `tasks`, `heap`, `restart`, `nvs`, `wifi` and `log` commands.
+    /// `nvs` works on `partition`.
+    pub fn builtin_commands(self, partition: NvsPartition) -> Self {
+        self.register_command(
+            "tasks",
+            "",
+            "List tasks with their state, priority and least free stack",
+            tasks,
+        )
+        .register_command("heap", "", "Show free heap memory", heap)
+        .register_command("restart", "", "Restart the chip", |_, out| {
+            writeln!(out, "restarting")?;
+            system::restart()
+        })
+        .register_command(
+            "nvs",
+            "list [namespace] | get <namespace> <key> | set <namespace> <key> <value> [type]",
+            "Read and write stored settings",
+            move |args, out| nvs(&partition, args, out),
+        )
+        .register_command(
+            "wifi",
+            "status | scan | connect <ssid> [password]",
+            "Show and change the station's connection",
+            wifi,
+        )
+        .register_command(
+            "log",
+            "level [tag] <off|error|warn|info|debug|trace>",
+            "Set the log level of a tag, or of every tag",
+            log_level,
+        )
+    }
+
+    /// Take over the UART and serve the console from a new task.
+    pub fn start(self) -> Result<Task, Error> {
+        let mut uart = Uart::new(self.port).baud_rate(self.baud_rate);
+        if let Some((tx_pin, rx_pin)) = self.pins {
+            uart = uart.pins(tx_pin, rx_pin);
+        }
+        let uart = uart.start()?;
+        // Writing to the port's FIFO directly would race with the driver.
+        unsafe { esp_idf_sys::esp_vfs_dev_uart_use_driver(self.port.to_raw() as _) };
+        let repl = Repl::new(
+            LineEditor::new(self.prompt, self.history_len),
+            self.commands,
+        );
+        let task = Task::new()
+            .name("console")
+            .stack_size(self.stack_size)
+            .start(move || serve(uart, repl))?;
+        Ok(task)
+    }
+}
+
+/// Marker type for the console. Instantiate with [`Console::new()`].
+pub struct Console;
+
+impl Console {
+    /// Prepare a builder object for the console.
+    pub fn new() -> ConsoleBuilder<'static> {
+        ConsoleBuilder {
+            port: Port::Uart0,
+            baud_rate: 115_200,
+            pins: None,
+            prompt: "> ",
+            history_len: 16,
+            stack_size: 6144,
+            commands: Commands::new(),
+        }
+    }
+}
+
+fn serve(mut uart: Uart, mut repl: Repl) {
+    let mut buf = [0; 64];
+    // Echo and command output take the console lock, so they aren't
+    // interleaved with `println!` from other tasks.
+    {
+        let _lock = crate::lock();
+        repl.start(&mut Terminal(&mut uart));
+    }
+    loop {
+        let len = match uart.read(&mut buf) {
+            Ok(len) => len,
+            Err(e) => {
+                crate::println!("console read failed: {:?}", e);
+                // Before dropping `uart` deletes the driver.
+                let port = uart.port().to_raw();
+                unsafe { esp_idf_sys::esp_vfs_dev_uart_use_nonblocking(port as _) };
+                return;
+            }
+        };
+        let _lock = crate::lock();
+        for &byte in &buf[..len] {
+            repl.feed(byte, &mut Terminal(&mut uart));
+        }
+    }
+}
+
+/// Writes to the UART, turning `\n` into the `\r\n` terminals expect.
+struct Terminal<'a>(&'a mut Uart);
+
+impl fmt::Write for Terminal<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut lines = s.split('\n');
+        if let Some(first) = lines.next() {
+            self.0.write(first.as_bytes()).map_err(|_| fmt::Error)?;
+        }
+        for line in lines {
+            self.0.write(b"\r\n").map_err(|_| fmt::Error)?;
+            self.0.write(line.as_bytes()).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+fn tasks(_: Args<'_>, out: &mut dyn fmt::Write) -> Result<(), CommandError> {
+    let mut tasks = freertos_task::snapshot();
+    tasks.sort_by(|a, b| a.name.cmp(&b.name));
+    writeln!(
+        out,
+        "{:16} {:9} {:>4} {:>6} {:>4}",
+        "name", "state", "prio", "stack", "core"
+    )?;
+    for task in &tasks {
+        let state = match task.state {
+            TaskState::Running => "running",
+            TaskState::Ready => "ready",
+            TaskState::Blocked => "blocked",
+            TaskState::Suspended => "suspended",
+            TaskState::Deleted => "deleted",
+        };
+        let core = match task.affinity {
+            CpuAffinity::Cpu(Cpu::Pro) => "0",
+            CpuAffinity::Cpu(Cpu::App) => "1",
+            CpuAffinity::NoAffinity => "-",
+        };
+        writeln!(
+            out,
+            "{:16} {:9} {:>4} {:>6} {:>4}",
+            task.name, state, task.priority, task.stack_high_water_mark, core
+        )?;
+    }
+    Ok(())
+}
+
+fn heap(_: Args<'_>, out: &mut dyn fmt::Write) -> Result<(), CommandError> {
+    for (capability, stats) in HeapStats::all().iter() {
+        writeln!(out, "{:8} {}", capability.name(), stats)?;
+    }
+    writeln!(
+        out,
+        "total    {} free (min {})",
+        system::free_heap_size(),
+        system::minimum_free_heap_size()
+    )?;
+    Ok(())
+}
+
+/// The names `nvs` uses for stored types.
+const NVS_TYPES: [(NvsType, &str); 10] = [
+    (NvsType::U8, "u8"),
+    (NvsType::I8, "i8"),
+    (NvsType::U16, "u16"),
+    (NvsType::I16, "i16"),
+    (NvsType::U32, "u32"),
+    (NvsType::I32, "i32"),
+    (NvsType::U64, "u64"),
+    (NvsType::I64, "i64"),
+    (NvsType::Str, "str"),
+    (NvsType::Blob, "blob"),
+];
+
+fn nvs_type_name(ty: Option<NvsType>) -> &'static str {
+    NVS_TYPES
+        .iter()
+        .find(|&&(t, _)| Some(t) == ty)
+        .map_or("?", |&(_, name)| name)
+}
+
+fn nvs(
+    partition: &NvsPartition,
+    args: Args<'_>,
+    out: &mut dyn fmt::Write,
+) -> Result<(), CommandError> {
+    match args.required(0)? {
+        "list" => {
+            let entries = partition
+                .entries(args.get(1), None)
+                .map_err(CommandError::failed)?;
+            for entry in entries {
+                let ty = nvs_type_name(entry.ty);
+                writeln!(out, "{}:{} ({})", entry.namespace, entry.key, ty)?;
+            }
+        }
+        "get" => {
+            let (namespace, key) = (args.required(1)?, args.required(2)?);
+            let entry = partition
+                .entries(Some(namespace), None)
+                .map_err(CommandError::failed)?
+                .find(|entry| entry.key == key)
+                .ok_or_else(|| CommandError::Failed("not found".into()))?;
+            let namespace = partition
+                .open(namespace, OpenMode::ReadOnly)
+                .map_err(CommandError::failed)?;
+            match entry.ty {
+                Some(NvsType::U8) => show::<u8>(&namespace, key, out)?,
+                Some(NvsType::I8) => show::<i8>(&namespace, key, out)?,
+                Some(NvsType::U16) => show::<u16>(&namespace, key, out)?,
+                Some(NvsType::I16) => show::<i16>(&namespace, key, out)?,
+                Some(NvsType::U32) => show::<u32>(&namespace, key, out)?,
+                Some(NvsType::I32) => show::<i32>(&namespace, key, out)?,
+                Some(NvsType::U64) => show::<u64>(&namespace, key, out)?,
+                Some(NvsType::I64) => show::<i64>(&namespace, key, out)?,
+                Some(NvsType::Str) => show::<String>(&namespace, key, out)?,
+                Some(NvsType::Blob) => {
+                    let blob: Vec<u8> = get(&namespace, key)?;
+                    for byte in &blob {
+                        write!(out, "{:02x}", byte)?;
+                    }
+                    writeln!(out)?;
+                }
+                None => return Err(CommandError::Failed("unknown type".into())),
+            }
+        }
+        "set" => {
+            let (namespace, key, value) = (args.required(1)?, args.required(2)?, args.required(3)?);
+            let ty = match args.get(4) {
+                None => NvsType::Str,
+                Some(name) => NVS_TYPES
+                    .iter()
+                    .find(|&&(_, n)| n == name)
+                    .map(|&(ty, _)| ty)
+                    .ok_or(CommandError::Usage)?,
+            };
+            let mut namespace = partition
+                .open(namespace, OpenMode::ReadWrite)
+                .map_err(CommandError::failed)?;
+            let result = match ty {
+                NvsType::U8 => namespace.set(key, &args.parse::<u8>(3)?),
+                NvsType::I8 => namespace.set(key, &args.parse::<i8>(3)?),
+                NvsType::U16 => namespace.set(key, &args.parse::<u16>(3)?),
+                NvsType::I16 => namespace.set(key, &args.parse::<i16>(3)?),
+                NvsType::U32 => namespace.set(key, &args.parse::<u32>(3)?),
+                NvsType::I32 => namespace.set(key, &args.parse::<i32>(3)?),
+                NvsType::U64 => namespace.set(key, &args.parse::<u64>(3)?),
+                NvsType::I64 => namespace.set(key, &args.parse::<i64>(3)?),
+                NvsType::Str => namespace.set_str(key, value),
+                NvsType::Blob => namespace.set_blob(key, &parse_hex(value)?),
+            };
+            result
+                .and_then(|()| namespace.commit())
+                .map_err(CommandError::failed)?;
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+fn get<T: NvsValue>(namespace: &NvsNamespace, key: &str) -> Result<T, CommandError> {
+    namespace
+        .get(key)
+        .map_err(CommandError::failed)?
+        .ok_or_else(|| CommandError::Failed("not found".into()))
+}
+
+fn show<T: NvsValue + fmt::Debug>(
+    namespace: &NvsNamespace,
+    key: &str,
+    out: &mut dyn fmt::Write,
+) -> Result<(), CommandError> {
+    let value: T = get(namespace, key)?;
+    writeln!(out, "{:?}", value)?;
+    Ok(())
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, CommandError> {
+    let invalid = || CommandError::Failed("expected an even number of hex digits".into());
+    if s.len() % 2 != 0 {
+        return Err(invalid());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+fn wifi(args: Args<'_>, out: &mut dyn fmt::Write) -> Result<(), CommandError> {
+    match args.required(0)? {
+        "status" => {
+            let mut ap: esp_idf_sys::wifi_ap_record_t = unsafe { core::mem::zeroed() };
+            let ret = unsafe { esp_idf_sys::esp_wifi_sta_get_ap_info(&mut ap) };
+            if ret == esp_idf_sys::ESP_ERR_WIFI_NOT_CONNECT as esp_idf_sys::esp_err_t {
+                writeln!(out, "not connected")?;
+                return Ok(());
+            }
+            EspError(ret).into_result().map_err(CommandError::failed)?;
+            writeln!(
+                out,
+                "connected to {:?} ({}), channel {}, {} dBm",
+                ssid(&ap.ssid),
+                MacAddress::from(ap.bssid),
+                ap.primary,
+                ap.rssi
+            )?;
+            let info = NetworkInterface::Sta
+                .ip_info()
+                .map_err(CommandError::failed)?;
+            writeln!(
+                out,
+                "address {}, netmask {}, gateway {}",
+                info.ip, info.netmask, info.gw
+            )?;
+        }
+        "scan" => {
+            let mut records = unsafe {
+                EspError(esp_idf_sys::esp_wifi_scan_start(core::ptr::null(), true))
+                    .into_result()
+                    .map_err(CommandError::failed)?;
+                let mut count = 0;
+                EspError(esp_idf_sys::esp_wifi_scan_get_ap_num(&mut count))
+                    .into_result()
+                    .map_err(CommandError::failed)?;
+                let mut records = vec![core::mem::zeroed(); count as usize];
+                EspError(esp_idf_sys::esp_wifi_scan_get_ap_records(
+                    &mut count,
+                    records.as_mut_ptr(),
+                ))
+                .into_result()
+                .map_err(CommandError::failed)?;
+                records.truncate(count as usize);
+                records
+            };
+            records.sort_by_key(|ap: &esp_idf_sys::wifi_ap_record_t| core::cmp::Reverse(ap.rssi));
+            writeln!(out, "{:>4} {:>3} {:17} ssid", "dBm", "ch", "bssid")?;
+            for ap in &records {
+                writeln!(
+                    out,
+                    "{:>4} {:>3} {} {}",
+                    ap.rssi,
+                    ap.primary,
+                    MacAddress::from(ap.bssid),
+                    ssid(&ap.ssid)
+                )?;
+            }
+        }
+        "connect" => {
+            let ssid = args.required(1)?;
+            let password = args.get(2).unwrap_or("");
+            connect(ssid, password).map_err(CommandError::failed)?;
+            writeln!(out, "connecting to {:?}; see \"wifi status\"", ssid)?;
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+fn ssid(raw: &[u8]) -> String {
+    let len = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..len]).into()
+}
+
+/// Start joining `ssid` as a station, without waiting for the outcome.
+fn connect(ssid: &str, password: &str) -> Result<(), EspError> {
+    let mut config: esp_idf_sys::wifi_config_t = unsafe { core::mem::zeroed() };
+    unsafe {
+        let sta = &mut config.sta;
+        if ssid.len() > sta.ssid.len() || password.len() > sta.password.len() {
+            return Err(EspError(
+                esp_idf_sys::ESP_ERR_INVALID_ARG as esp_idf_sys::esp_err_t,
+            ));
+        }
+        sta.ssid[..ssid.len()].copy_from_slice(ssid.as_bytes());
+        sta.password[..password.len()].copy_from_slice(password.as_bytes());
+
+        let _ = esp_idf_sys::esp_wifi_disconnect();
+        EspError(esp_idf_sys::esp_wifi_set_config(
+            esp_idf_sys::esp_interface_t_ESP_IF_WIFI_STA,
+            &mut config,
+        ))
+        .into_result()?;
+        EspError(esp_idf_sys::esp_wifi_connect()).into_result()
+    }
+}
+
+fn log_level(args: Args<'_>, out: &mut dyn fmt::Write) -> Result<(), CommandError> {
+    if args.required(0)? != "level" {
+        return Err(CommandError::Usage);
+    }
+    let (tag, level) = match args.len() {
+        2 => ("*", args.parse::<LevelFilter>(1)?),
+        3 => (args.required(1)?, args.parse::<LevelFilter>(2)?),
+        _ => return Err(CommandError::Usage),
+    };
+    logger::set_level(tag, level);
+    writeln!(out, "{} set to {}", tag, level)?;
+    Ok(())
+}
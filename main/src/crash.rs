@@ -0,0 +1,478 @@
+//! Crash reporting for panics and allocation failures.
+//!
+//! The panic and allocation-error handlers print the task, core, heap
+//! statistics and a backtrace, then save a compact [`CrashReport`] in the
+//! `crash` NVS namespace before aborting. Call [`report_last()`] after
+//! initializing NVS on the next boot to print and clear it.
+//!
+//! Building and printing the report doesn't allocate, so it works when the
+//! heap is exhausted. Saving the record goes through NVS, which can
+//! allocate while writing; [`report_last()`] keeps a handle open for it to
+//! keep that to a minimum, but with no memory left at all the record may be
+//! lost.
+//!
+//! [`CrashReport`]: struct.CrashReport.html
+//! [`report_last()`]: fn.report_last.html
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use cstr_core::CStr;
+use esp_idf_hal::errors::EspError;
+
+use crate::freertos_task::CurrentTask;
+use crate::nvs::{NvsPartition, OpenMode};
+use crate::system::ResetReason;
+
+/// NVS namespace and key of the saved record.
+pub const NAMESPACE: &str = "crash";
+pub const KEY: &str = "last";
+
+/// Most frames kept in a backtrace.
+pub const MAX_FRAMES: usize = 16;
+
+const VERSION: u8 = 1;
+const MAX_TASK_NAME: usize = 16;
+const MAX_FILE: usize = 64;
+const MAX_MESSAGE: usize = 160;
+const MAX_RECORD: usize = 32 + 4 * MAX_FRAMES + 3 + MAX_TASK_NAME + MAX_FILE + MAX_MESSAGE;
+
+static CRASHING: AtomicBool = AtomicBool::new(false);
+/// The handle `save` writes through, kept open by `report_last`. NVS never
+/// hands out 0.
+static HANDLE: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrashKind {
+    Panic,
+    /// An allocation of `size` bytes aligned to `align` failed.
+    AllocError {
+        size: u32,
+        align: u32,
+    },
+}
+
+/// What was saved about the last crash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CrashReport {
+    pub kind: CrashKind,
+    pub message: String,
+    pub file: String,
+    pub line: u32,
+    pub task: String,
+    pub core: u8,
+    /// Milliseconds since boot.
+    pub uptime_ms: u32,
+    pub free_heap: u32,
+    pub min_free_heap: u32,
+    /// Program counters, innermost first.
+    pub backtrace: Vec<u32>,
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            CrashKind::Panic => write!(f, "panic")?,
+            CrashKind::AllocError { size, align } => {
+                write!(f, "allocation of {} bytes (align {}) failed", size, align)?
+            }
+        }
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        if !self.file.is_empty() {
+            write!(f, " at {}:{}", self.file, self.line)?;
+        }
+        write!(
+            f,
+            " in task {:?} on core {} after {} ms; heap free {} (min {})",
+            self.task, self.core, self.uptime_ms, self.free_heap, self.min_free_heap
+        )?;
+        if !self.backtrace.is_empty() {
+            f.write_str("\nBacktrace:")?;
+            for pc in &self.backtrace {
+                write!(f, " 0x{:08x}", pc)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CrashReport {
+    /// Parse a saved record, or `None` if it is malformed or from another
+    /// version.
+    pub fn parse(bytes: &[u8]) -> Option<CrashReport> {
+        let mut r = Reader(bytes);
+        if r.u8()? != VERSION {
+            return None;
+        }
+        let kind = match r.u8()? {
+            0 => CrashKind::Panic,
+            1 => CrashKind::AllocError {
+                size: r.u32()?,
+                align: r.u32()?,
+            },
+            _ => return None,
+        };
+        let line = r.u32()?;
+        let core = r.u8()?;
+        let uptime_ms = r.u32()?;
+        let free_heap = r.u32()?;
+        let min_free_heap = r.u32()?;
+        let frames = r.u8()? as usize;
+        let mut backtrace = Vec::with_capacity(frames);
+        for _ in 0..frames {
+            backtrace.push(r.u32()?);
+        }
+        Some(CrashReport {
+            kind,
+            line,
+            core,
+            uptime_ms,
+            free_heap,
+            min_free_heap,
+            backtrace,
+            task: r.str()?,
+            file: r.str()?,
+            message: r.str()?,
+        })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.0.len() {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let b = self.take(4)?;
+        Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u8()? as usize;
+        Some(String::from_utf8_lossy(self.take(len)?).into())
+    }
+}
+
+/// A message buffer that drops whatever doesn't fit.
+struct Message {
+    buf: [u8; MAX_MESSAGE],
+    len: usize,
+}
+
+impl Message {
+    fn new() -> Self {
+        Message {
+            buf: [0; MAX_MESSAGE],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let n = c.len_utf8();
+            if self.len + n > MAX_MESSAGE {
+                break;
+            }
+            c.encode_utf8(&mut self.buf[self.len..]);
+            self.len += n;
+        }
+        Ok(())
+    }
+}
+
+/// A record being built in a fixed buffer.
+struct RecordWriter {
+    buf: [u8; MAX_RECORD],
+    len: usize,
+}
+
+impl RecordWriter {
+    fn put(&mut self, bytes: &[u8]) {
+        let n = bytes.len().min(MAX_RECORD - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.put(&v.to_le_bytes());
+    }
+
+    /// A length-prefixed string, cut to `max` bytes on a character boundary.
+    fn str(&mut self, s: &str, max: usize) {
+        let mut end = s.len().min(max);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.put(&[end as u8]);
+        self.put(&s.as_bytes()[..end]);
+    }
+}
+
+/// Program counters and stack pointers, innermost first.
+struct Backtrace {
+    frames: [(u32, u32); MAX_FRAMES],
+    len: usize,
+    corrupted: bool,
+}
+
+/// Undo the window-increment bits that the windowed ABI keeps in return
+/// addresses, and point at the call instruction rather than after it.
+fn stack_pc(pc: u32) -> u32 {
+    let pc = if pc & 0x8000_0000 != 0 {
+        (pc & 0x3fff_ffff) | 0x4000_0000
+    } else {
+        pc
+    };
+    pc.wrapping_sub(3)
+}
+
+#[inline(never)]
+fn backtrace() -> Backtrace {
+    let mut bt = Backtrace {
+        frames: [(0, 0); MAX_FRAMES],
+        len: 0,
+        corrupted: false,
+    };
+    let mut frame = esp_idf_sys::esp_backtrace_frame_t {
+        pc: 0,
+        sp: 0,
+        next_pc: 0,
+    };
+    unsafe {
+        // Spills the register windows to the stack, so every caller's frame
+        // can be followed through its save area.
+        esp_idf_sys::esp_backtrace_get_start(&mut frame.pc, &mut frame.sp, &mut frame.next_pc);
+        loop {
+            bt.frames[bt.len] = (stack_pc(frame.pc), frame.sp);
+            bt.len += 1;
+            if bt.len == MAX_FRAMES || frame.next_pc == 0 {
+                break;
+            }
+            if !esp_idf_sys::esp_backtrace_get_next_frame(&mut frame) {
+                bt.corrupted = true;
+                break;
+            }
+        }
+    }
+    bt
+}
+
+fn crash(kind: CrashKind, message: fmt::Arguments, file: &str, line: u32) -> ! {
+    // A panic while reporting a crash goes straight to `abort`.
+    if CRASHING.swap(true, Ordering::SeqCst) {
+        unsafe { esp_idf_sys::abort() };
+    }
+
+    let mut msg = Message::new();
+    let _ = msg.write_fmt(message);
+
+    let task = unsafe {
+        let name = esp_idf_sys::pcTaskGetTaskName(core::ptr::null_mut());
+        if name.is_null() {
+            ""
+        } else {
+            CStr::from_ptr(name).to_str().unwrap_or("")
+        }
+    };
+    let core = CurrentTask::cpu() as u8;
+    let uptime_ms = unsafe { esp_idf_sys::esp_log_timestamp() };
+    let caps = esp_idf_sys::MALLOC_CAP_DEFAULT;
+    let (free_heap, min_free_heap, largest_block) = unsafe {
+        (
+            esp_idf_sys::heap_caps_get_free_size(caps) as u32,
+            esp_idf_sys::heap_caps_get_minimum_free_size(caps) as u32,
+            esp_idf_sys::heap_caps_get_largest_free_block(caps) as u32,
+        )
+    };
+    let bt = backtrace();
+
+    {
+        let _lock = crate::lock();
+        match kind {
+            CrashKind::Panic => crate::eprintln!("\npanic: {}", msg.as_str()),
+            CrashKind::AllocError { size, align } => {
+                crate::eprintln!("\nallocation of {} bytes (align {}) failed", size, align)
+            }
+        }
+        if !file.is_empty() {
+            crate::eprintln!("location: {}:{}", file, line);
+        }
+        crate::eprintln!(
+            "task {:?} on core {}, {} ms after boot",
+            task,
+            core,
+            uptime_ms
+        );
+        crate::eprintln!(
+            "heap: {} free, {} minimum free, {} largest block",
+            free_heap,
+            min_free_heap,
+            largest_block
+        );
+        // The same format as ESP-IDF's, so `idf_monitor` decodes it.
+        crate::eprint!("Backtrace:");
+        for &(pc, sp) in &bt.frames[..bt.len] {
+            crate::eprint!("0x{:08x}:0x{:08x} ", pc, sp);
+        }
+        crate::eprintln!("{}", if bt.corrupted { "|<-CORRUPTED" } else { "" });
+    }
+
+    let mut record = RecordWriter {
+        buf: [0; MAX_RECORD],
+        len: 0,
+    };
+    record.put(&[VERSION]);
+    match kind {
+        CrashKind::Panic => record.put(&[0]),
+        CrashKind::AllocError { size, align } => {
+            record.put(&[1]);
+            record.u32(size);
+            record.u32(align);
+        }
+    }
+    record.u32(line);
+    record.put(&[core]);
+    record.u32(uptime_ms);
+    record.u32(free_heap);
+    record.u32(min_free_heap);
+    record.put(&[bt.len as u8]);
+    for &(pc, _) in &bt.frames[..bt.len] {
+        record.u32(pc);
+    }
+    record.str(task, MAX_TASK_NAME);
+    record.str(file, MAX_FILE);
+    record.str(msg.as_str(), MAX_MESSAGE);
+    save(&record.buf[..record.len]);
+
+    unsafe { esp_idf_sys::abort() };
+    unreachable!("post-abort")
+}
+
+const NAMESPACE_C: &[u8] = b"crash\0";
+const KEY_C: &[u8] = b"last\0";
+
+/// Open a raw NVS handle to the `crash` namespace on the default partition,
+/// or 0 on failure.
+fn open_handle() -> esp_idf_sys::nvs_handle_t {
+    let mut handle: esp_idf_sys::nvs_handle_t = 0;
+    let err = unsafe {
+        esp_idf_sys::nvs_open(
+            NAMESPACE_C.as_ptr() as *const _,
+            esp_idf_sys::nvs_open_mode_t_NVS_READWRITE,
+            &mut handle,
+        )
+    };
+    if err == esp_idf_sys::ESP_OK as esp_idf_sys::esp_err_t {
+        handle
+    } else {
+        0
+    }
+}
+
+/// Save the record with the raw NVS API, through the handle kept open by
+/// `report_last` if there is one. Fails quietly if NVS isn't initialized.
+fn save(record: &[u8]) {
+    let handle = match HANDLE.load(Ordering::Acquire) {
+        0 => open_handle(),
+        handle => handle,
+    };
+    if handle == 0 {
+        return;
+    }
+    unsafe {
+        if esp_idf_sys::nvs_set_blob(
+            handle,
+            KEY_C.as_ptr() as *const _,
+            record.as_ptr() as *const _,
+            record.len() as _,
+        ) == esp_idf_sys::ESP_OK as esp_idf_sys::esp_err_t
+        {
+            esp_idf_sys::nvs_commit(handle);
+        }
+    }
+}
+
+/// Report a panic. Called by the `#[panic_handler]`.
+pub(crate) fn panic(info: &PanicInfo) -> ! {
+    let (file, line) = match info.location() {
+        Some(location) => (location.file(), location.line()),
+        None => ("", 0),
+    };
+    if let Some(args) = info.message() {
+        crash(CrashKind::Panic, format_args!("{}", args), file, line)
+    }
+    match info.payload().downcast_ref::<&str>() {
+        Some(s) => crash(CrashKind::Panic, format_args!("{}", s), file, line),
+        None => crash(CrashKind::Panic, format_args!(""), file, line),
+    }
+}
+
+/// Report an allocation failure. Called by the `#[alloc_error_handler]`.
+pub(crate) fn alloc_error(layout: Layout) -> ! {
+    let kind = CrashKind::AllocError {
+        size: layout.size() as u32,
+        align: layout.align() as u32,
+    };
+    crash(kind, format_args!(""), "", 0)
+}
+
+/// Print the reset reason, and the report saved by the last crash if there
+/// is one. The report is removed, so it's only shown once.
+///
+/// The namespace is then kept open, and the next crash is saved through it.
+pub fn report_last(partition: &NvsPartition) -> Result<Option<CrashReport>, EspError> {
+    crate::println!("last reset: {}", ResetReason::get());
+
+    let mut namespace = partition.open(NAMESPACE, OpenMode::ReadWrite)?;
+    let record = namespace.get::<Vec<u8>>(KEY)?;
+    if record.is_some() {
+        namespace.remove(KEY)?;
+        namespace.commit()?;
+    }
+    if HANDLE
+        .compare_exchange(
+            0,
+            namespace.raw_handle(),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .is_ok()
+    {
+        core::mem::forget(namespace);
+    }
+
+    let record = match record {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    let report = CrashReport::parse(&record);
+    match &report {
+        Some(report) => crate::println!("last crash: {}", report),
+        None => crate::println!("last crash: unreadable record"),
+    }
+    Ok(report)
+}
@@ -0,0 +1,341 @@
+//! Wi-Fi Channel State Information (CSI) capture.
+//!
+//! The driver reports CSI for received frames from the Wi-Fi task. Each report
+//! is copied into a lock-free queue and decoded on a dispatcher task, which
+//! hands an owned `CsiFrame` to a Rust callback.
+
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+use esp_idf_hal::errors::EspError;
+use esp_idf_sys::types::c_void;
+
+use crate::dispatcher::{Dispatcher, Slot};
+use crate::net::MacAddress;
+
+/// Largest CSI buffer the driver produces: LLTF, HT-LTF and STBC HT-LTF, each
+/// with 64 subcarriers of two bytes.
+pub const MAX_CSI_LEN: usize = 384;
+
+/// Which training fields to report CSI for, and how to process them.
+///
+/// The defaults match `esp-idf`'s.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub struct CsiConfig {
+    /// Report the legacy long training field.
+    pub lltf: bool,
+    /// Report the HT long training field.
+    pub htltf: bool,
+    /// Report the second HT-LTF of STBC frames.
+    pub stbc_htltf2: bool,
+    /// For HT frames, average the LLTF and HT-LTF data.
+    pub ltf_merge: bool,
+    /// Smooth adjacent subcarriers.
+    pub channel_filter: bool,
+    /// Scale the values by `1 << shift` rather than automatically.
+    pub manual_scale: Option<u8>,
+}
+
+impl Default for CsiConfig {
+    fn default() -> Self {
+        CsiConfig {
+            lltf: true,
+            htltf: true,
+            stbc_htltf2: true,
+            ltf_merge: true,
+            channel_filter: true,
+            manual_scale: None,
+        }
+    }
+}
+
+impl CsiConfig {
+    fn to_raw(self) -> esp_idf_sys::wifi_csi_config_t {
+        esp_idf_sys::wifi_csi_config_t {
+            lltf_en: self.lltf,
+            htltf_en: self.htltf,
+            stbc_htltf2_en: self.stbc_htltf2,
+            ltf_merge_en: self.ltf_merge,
+            channel_filter_en: self.channel_filter,
+            manu_scale: self.manual_scale.is_some(),
+            shift: self.manual_scale.unwrap_or(0),
+        }
+    }
+}
+
+/// The channel response of a single subcarrier.
+#[derive(Copy, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Complex {
+    pub re: i8,
+    pub im: i8,
+}
+
+impl Complex {
+    pub fn amplitude(self) -> f32 {
+        libm::sqrtf(self.amplitude_squared() as f32)
+    }
+
+    /// Squared amplitude, which avoids the square root.
+    pub fn amplitude_squared(self) -> u32 {
+        let (re, im) = (self.re as i32, self.im as i32);
+        (re * re + im * im) as u32
+    }
+
+    /// Phase in radians, in `[-pi, pi]`.
+    pub fn phase(self) -> f32 {
+        libm::atan2f(self.im as f32, self.re as f32)
+    }
+}
+
+/// An owned copy of one CSI report.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CsiFrame {
+    /// Transmitter of the frame the CSI was measured on.
+    pub mac: MacAddress,
+    /// Signal strength in dBm.
+    pub rssi: i8,
+    /// Noise floor in dBm.
+    pub noise_floor: i8,
+    pub channel: u8,
+    /// Secondary channel: 0 for none, 1 above, 2 below.
+    pub secondary_channel: u8,
+    /// Whether the frame was an HT (802.11n) frame.
+    pub ht: bool,
+    /// Whether the frame used a 40 MHz channel.
+    pub ht40: bool,
+    /// The driver's local receive timestamp, in microseconds.
+    pub timestamp_us: u32,
+    /// The first four bytes of the driver's buffer are known to be invalid on
+    /// some hardware; the first two subcarriers should be ignored if so.
+    pub first_word_invalid: bool,
+    pub subcarriers: Vec<Complex>,
+}
+
+/// Version byte at the start of each serialized frame.
+const SERIALIZATION_VERSION: u8 = 1;
+/// Length of the fixed part of a serialized frame.
+const SERIALIZED_HEADER_LEN: usize = 20;
+
+const FLAG_HT: u8 = 0x01;
+const FLAG_HT40: u8 = 0x02;
+const FLAG_FIRST_WORD_INVALID: u8 = 0x04;
+
+impl CsiFrame {
+    /// Amplitude of each subcarrier.
+    pub fn amplitudes(&self) -> impl Iterator<Item = f32> + '_ {
+        self.subcarriers.iter().map(|c| c.amplitude())
+    }
+
+    /// Phase of each subcarrier, in radians.
+    pub fn phases(&self) -> impl Iterator<Item = f32> + '_ {
+        self.subcarriers.iter().map(|c| c.phase())
+    }
+
+    /// Append a compact binary encoding of the frame to `out`.
+    ///
+    /// The layout is, with multi-byte integers little-endian:
+    ///
+    /// | bytes | field                                   |
+    /// |-------|-----------------------------------------|
+    /// | 1     | version (1)                             |
+    /// | 1     | flags: HT, HT40, first word invalid     |
+    /// | 6     | MAC address                             |
+    /// | 1     | RSSI (i8)                               |
+    /// | 1     | noise floor (i8)                        |
+    /// | 1     | channel                                 |
+    /// | 1     | secondary channel                       |
+    /// | 4     | timestamp (u32)                         |
+    /// | 2     | reserved                                |
+    /// | 2     | number of subcarriers `n` (u16)         |
+    /// | 2 * n | subcarriers, each real then imaginary   |
+    pub fn serialize(&self, out: &mut Vec<u8>) {
+        let mut flags = 0;
+        if self.ht {
+            flags |= FLAG_HT;
+        }
+        if self.ht40 {
+            flags |= FLAG_HT40;
+        }
+        if self.first_word_invalid {
+            flags |= FLAG_FIRST_WORD_INVALID;
+        }
+
+        out.reserve(SERIALIZED_HEADER_LEN + 2 * self.subcarriers.len());
+        out.push(SERIALIZATION_VERSION);
+        out.push(flags);
+        out.extend_from_slice(&self.mac.0);
+        out.push(self.rssi as u8);
+        out.push(self.noise_floor as u8);
+        out.push(self.channel);
+        out.push(self.secondary_channel);
+        out.extend_from_slice(&self.timestamp_us.to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(&(self.subcarriers.len() as u16).to_le_bytes());
+        for c in &self.subcarriers {
+            out.push(c.re as u8);
+            out.push(c.im as u8);
+        }
+    }
+
+    /// Decode a frame written by `serialize`, returning it and the number of
+    /// bytes consumed.
+    pub fn deserialize(buf: &[u8]) -> Option<(CsiFrame, usize)> {
+        if buf.len() < SERIALIZED_HEADER_LEN || buf[0] != SERIALIZATION_VERSION {
+            return None;
+        }
+        let flags = buf[1];
+        let n = u16::from_le_bytes([buf[18], buf[19]]) as usize;
+        let len = SERIALIZED_HEADER_LEN + 2 * n;
+        let data = buf.get(SERIALIZED_HEADER_LEN..len)?;
+
+        let frame = CsiFrame {
+            mac: MacAddress::from_slice(&buf[2..8])?,
+            rssi: buf[8] as i8,
+            noise_floor: buf[9] as i8,
+            channel: buf[10],
+            secondary_channel: buf[11],
+            ht: flags & FLAG_HT != 0,
+            ht40: flags & FLAG_HT40 != 0,
+            timestamp_us: u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]),
+            first_word_invalid: flags & FLAG_FIRST_WORD_INVALID != 0,
+            subcarriers: data
+                .chunks(2)
+                .map(|c| Complex {
+                    re: c[0] as i8,
+                    im: c[1] as i8,
+                })
+                .collect(),
+        };
+        Some((frame, len))
+    }
+}
+
+/// A CSI report as copied out of the driver's callback.
+struct RawCsi {
+    mac: [u8; 6],
+    rssi: i8,
+    noise_floor: i8,
+    channel: u8,
+    secondary_channel: u8,
+    ht: bool,
+    ht40: bool,
+    timestamp_us: u32,
+    first_word_invalid: bool,
+    len: usize,
+    buf: [i8; MAX_CSI_LEN],
+}
+
+impl RawCsi {
+    fn decode(&self) -> CsiFrame {
+        CsiFrame {
+            mac: MacAddress(self.mac),
+            rssi: self.rssi,
+            noise_floor: self.noise_floor,
+            channel: self.channel,
+            secondary_channel: self.secondary_channel,
+            ht: self.ht,
+            ht40: self.ht40,
+            timestamp_us: self.timestamp_us,
+            first_word_invalid: self.first_word_invalid,
+            // The driver stores each subcarrier as the imaginary part followed
+            // by the real part.
+            subcarriers: self.buf[..self.len]
+                .chunks_exact(2)
+                .map(|c| Complex { re: c[1], im: c[0] })
+                .collect(),
+        }
+    }
+}
+
+/// Where the CSI callback sends reports. Only one capture can be active,
+/// since the callback is global.
+static CSI: Slot<RawCsi> = Slot::new();
+
+/// Handle for a running CSI capture. Capturing stops when this is dropped.
+///
+/// CSI is reported for frames received from the connected AP, or for all
+/// frames while promiscuous mode is enabled.
+pub struct CsiCapture {
+    dispatcher: Dispatcher<RawCsi>,
+}
+
+impl CsiCapture {
+    /// Enable CSI reporting with `config`, and call `callback` on a dispatcher
+    /// task for every report.
+    ///
+    /// The Wi-Fi driver must already be initialized and started, and have been
+    /// built with `CONFIG_ESP32_WIFI_CSI_ENABLED`.
+    pub fn start(
+        config: CsiConfig,
+        mut callback: impl FnMut(CsiFrame) + Send + 'static,
+    ) -> Result<CsiCapture, EspError> {
+        let dispatcher = Dispatcher::new()
+            .name("csi_task")
+            .start(&CSI, move |reports| {
+                while let Some(raw) = reports.pop() {
+                    callback(raw.decode());
+                }
+            })?;
+
+        // Dropping `capture` on an error below turns CSI reporting back off.
+        let capture = CsiCapture { dispatcher };
+        let raw_config = config.to_raw();
+        unsafe {
+            EspError(esp_idf_sys::esp_wifi_set_csi_config(&raw_config)).into_result()?;
+            EspError(esp_idf_sys::esp_wifi_set_csi_rx_cb(
+                Some(csi_callback),
+                core::ptr::null_mut(),
+            ))
+            .into_result()?;
+            EspError(esp_idf_sys::esp_wifi_set_csi(true)).into_result()?;
+        }
+
+        Ok(capture)
+    }
+
+    /// Number of reports dropped because the dispatcher fell behind.
+    pub fn dropped(&self) -> u32 {
+        self.dispatcher.dropped()
+    }
+}
+
+impl Drop for CsiCapture {
+    fn drop(&mut self) {
+        unsafe {
+            esp_idf_sys::esp_wifi_set_csi(false);
+            esp_idf_sys::esp_wifi_set_csi_rx_cb(None, core::ptr::null_mut());
+        }
+        // `dispatcher` is dropped next, which waits for a callback that's
+        // still running.
+    }
+}
+
+extern "C" fn csi_callback(_: *mut c_void, info: *mut esp_idf_sys::wifi_csi_info_t) {
+    if info.is_null() {
+        return;
+    }
+    let info = unsafe { &*info };
+    let rx = &info.rx_ctrl;
+    let len = core::cmp::min(info.len as usize, MAX_CSI_LEN);
+
+    unsafe {
+        CSI.feed(|slot: &mut MaybeUninit<RawCsi>| {
+            let raw = slot.as_mut_ptr();
+            (*raw).mac = info.mac;
+            (*raw).rssi = rx.rssi() as i8;
+            (*raw).noise_floor = rx.noise_floor() as i8;
+            (*raw).channel = rx.channel() as u8;
+            (*raw).secondary_channel = rx.secondary_channel() as u8;
+            (*raw).ht = rx.sig_mode() != 0;
+            (*raw).ht40 = rx.cwb() != 0;
+            (*raw).timestamp_us = rx.timestamp();
+            (*raw).first_word_invalid = info.first_word_invalid;
+            (*raw).len = len;
+            if !info.buf.is_null() {
+                let buf = &mut (*raw).buf;
+                buf[..len].copy_from_slice(core::slice::from_raw_parts(info.buf, len));
+            } else {
+                (*raw).len = 0;
+            }
+        })
+    };
+}
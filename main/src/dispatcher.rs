@@ -0,0 +1,244 @@
+//! Handing items from a driver callback to a Rust closure on a dedicated
+//! task.
+//!
+//! Driver callbacks run on the driver's own task and must not block, so the
+//! callback copies each item into a lock-free queue and notifies a
+//! dispatcher task, which runs the handler. The callback finds the queue
+//! through a [`Slot`], a `static` that holds at most one dispatcher at a
+//! time, since the driver callbacks this is used for are global.
+//!
+//! [`Slot`]: struct.Slot.html
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering};
+use esp_idf_hal::errors::EspError;
+
+use crate::freertos_task::{CpuAffinity, CurrentTask, Task, TaskNotification, TaskPriority};
+use crate::freertos_units::Duration;
+use crate::spsc;
+
+struct Shared {
+    stop: AtomicBool,
+    dropped: AtomicU32,
+}
+
+/// The callback's end of a dispatcher, owned by the `Slot` while attached.
+struct Feeder<T> {
+    producer: spsc::Producer<T>,
+    task: esp_idf_sys::TaskHandle_t,
+    shared: Arc<Shared>,
+}
+
+/// Where a driver callback finds the dispatcher to feed.
+pub struct Slot<T> {
+    feeder: AtomicPtr<Feeder<T>>,
+    /// How many callbacks are between announcing themselves and being done
+    /// with `feeder`.
+    busy: AtomicU32,
+}
+
+impl<T> Slot<T> {
+    pub const fn new() -> Self {
+        Slot {
+            feeder: AtomicPtr::new(core::ptr::null_mut()),
+            busy: AtomicU32::new(0),
+        }
+    }
+
+    /// Detach `feeder`, if it's attached, and free it once no callback can
+    /// still be using it.
+    fn detach(&self, feeder: *mut Feeder<T>) {
+        if self
+            .feeder
+            .compare_exchange(
+                feeder,
+                core::ptr::null_mut(),
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_err()
+        {
+            return;
+        }
+        while self.busy.load(Ordering::SeqCst) != 0 {
+            CurrentTask::delay(Duration::eps());
+        }
+        drop(unsafe { Box::from_raw(feeder) });
+    }
+}
+
+impl<T: Send> Slot<T> {
+    /// Queue an item for the attached dispatcher, initializing it in place,
+    /// and wake the dispatcher. Does nothing if none is attached.
+    ///
+    /// # Safety
+    ///
+    /// `init` must fully initialize the slot it is given. Only the driver's
+    /// callback may call this, and the driver must never run that callback
+    /// concurrently with itself.
+    pub unsafe fn feed(&self, init: impl FnOnce(&mut MaybeUninit<T>)) {
+        // Announcing the callback before loading `feeder` means `detach`
+        // either waits for it or has already cleared `feeder`.
+        self.busy.fetch_add(1, Ordering::SeqCst);
+        let feeder = self.feeder.load(Ordering::SeqCst);
+        if !feeder.is_null() {
+            let feeder = &mut *feeder;
+            if feeder.producer.push_with(init) {
+                let _ = Task::from_raw_handle(feeder.task).notify(TaskNotification::Increment);
+            } else {
+                feeder.shared.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.busy.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Helper for starting a dispatcher. Instantiate with [`Dispatcher::new()`].
+///
+/// [`Dispatcher::new()`]: struct.Dispatcher.html#method.new
+pub struct DispatcherBuilder<'a, T> {
+    name: &'a str,
+    stack_size: u32,
+    priority: TaskPriority,
+    queue_len: usize,
+    wake_interval: Duration,
+    _item: PhantomData<T>,
+}
+
+impl<'a, T: Send + 'static> DispatcherBuilder<'a, T> {
+    /// Set the dispatcher task's name.
+    pub fn name<'b>(self, name: &'b str) -> DispatcherBuilder<'b, T> {
+        DispatcherBuilder {
+            name,
+            stack_size: self.stack_size,
+            priority: self.priority,
+            queue_len: self.queue_len,
+            wake_interval: self.wake_interval,
+            _item: PhantomData,
+        }
+    }
+
+    /// Set the dispatcher task's stack size, in words.
+    pub fn stack_size(self, stack_size: u32) -> Self {
+        DispatcherBuilder { stack_size, ..self }
+    }
+
+    /// Set the dispatcher task's priority.
+    pub fn priority(self, priority: TaskPriority) -> Self {
+        DispatcherBuilder { priority, ..self }
+    }
+
+    /// Set how many items can be queued before new ones are dropped.
+    pub fn queue_len(self, queue_len: usize) -> Self {
+        DispatcherBuilder { queue_len, ..self }
+    }
+
+    /// Also run the handler `interval` after it last ran, even if nothing
+    /// arrived. By default it only runs when woken by the callback.
+    pub fn wake_interval(self, wake_interval: Duration) -> Self {
+        DispatcherBuilder {
+            wake_interval,
+            ..self
+        }
+    }
+
+    /// Start the dispatcher task and attach it to `slot`. `handler` runs on
+    /// the task whenever it wakes, and should drain the queue it is passed.
+    ///
+    /// Fails with `ESP_ERR_INVALID_STATE` if `slot` already has a
+    /// dispatcher.
+    pub fn start<F>(self, slot: &'static Slot<T>, mut handler: F) -> Result<Dispatcher<T>, EspError>
+    where
+        F: FnMut(&mut spsc::Consumer<T>) + Send + 'static,
+    {
+        let (producer, mut consumer) = spsc::channel(self.queue_len);
+        let shared = Arc::new(Shared {
+            stop: AtomicBool::new(false),
+            dropped: AtomicU32::new(0),
+        });
+
+        let task_shared = shared.clone();
+        let wait = self.wake_interval;
+        let task = Task::new()
+            .name(self.name)
+            .stack_size(self.stack_size)
+            .priority(self.priority)
+            .core_affinity(CpuAffinity::NoAffinity)
+            .start(move || {
+                let this = Task::current().unwrap();
+                while !task_shared.stop.load(Ordering::Acquire) {
+                    let _ = this.wait_for_notification(0, u32::max_value(), wait);
+                    handler(&mut consumer);
+                }
+            })
+            .map_err(|_| EspError(esp_idf_sys::ESP_ERR_NO_MEM as esp_idf_sys::esp_err_t))?;
+
+        let feeder = Box::into_raw(Box::new(Feeder {
+            producer,
+            task: task.raw_handle(),
+            shared: shared.clone(),
+        }));
+        let attached = slot.feeder.compare_exchange(
+            core::ptr::null_mut(),
+            feeder,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+        // Dropping the dispatcher stops the task, and only detaches `feeder`
+        // if it was attached.
+        let dispatcher = Dispatcher {
+            slot,
+            feeder,
+            shared,
+            task,
+        };
+        if attached.is_err() {
+            drop(unsafe { Box::from_raw(feeder) });
+            return Err(EspError(
+                esp_idf_sys::ESP_ERR_INVALID_STATE as esp_idf_sys::esp_err_t,
+            ));
+        }
+        Ok(dispatcher)
+    }
+}
+
+/// A running dispatcher task. Dropping it detaches it from its slot, so
+/// the callback stops feeding it, and stops the task.
+pub struct Dispatcher<T: 'static> {
+    slot: &'static Slot<T>,
+    feeder: *mut Feeder<T>,
+    shared: Arc<Shared>,
+    task: Task,
+}
+
+unsafe impl<T: Send> Send for Dispatcher<T> {}
+
+impl<T: Send + 'static> Dispatcher<T> {
+    /// Prepare a builder object for a dispatcher of `T`s.
+    pub fn new() -> DispatcherBuilder<'static, T> {
+        DispatcherBuilder {
+            name: "dispatcher_task",
+            stack_size: 4096,
+            priority: TaskPriority(2),
+            queue_len: 16,
+            wake_interval: Duration::infinite(),
+            _item: PhantomData,
+        }
+    }
+
+    /// Number of items dropped because the handler fell behind.
+    pub fn dropped(&self) -> u32 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: 'static> Drop for Dispatcher<T> {
+    fn drop(&mut self) {
+        self.slot.detach(self.feeder);
+        self.shared.stop.store(true, Ordering::Release);
+        let _ = self.task.notify(TaskNotification::Increment);
+    }
+}
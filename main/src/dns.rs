@@ -0,0 +1,153 @@
+//! A minimal DNS responder that answers every `A` query with one address, as
+//! used by captive portals.
+//!
+//! This module is plain Rust and doesn't touch `esp-idf`, so it can be
+//! exercised on the host.
+
+use alloc::string::String;
+
+use crate::net::Ipv4Addr;
+
+const HEADER_LEN: usize = 12;
+/// Largest message we handle, per RFC 1035's limit for UDP.
+pub const MAX_MESSAGE_LEN: usize = 512;
+
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+const FLAG_QR: u16 = 0x8000;
+const FLAG_AA: u16 = 0x0400;
+const FLAG_RD: u16 = 0x0100;
+const OPCODE_MASK: u16 = 0x7800;
+const RCODE_NOT_IMPLEMENTED: u16 = 4;
+
+/// Reasons a query couldn't be answered.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The message ends before the header or question does.
+    Truncated,
+    /// The message is a response rather than a query.
+    NotAQuery,
+    /// The query doesn't contain exactly one question.
+    UnsupportedQuestionCount(u16),
+    /// A label in the name is malformed, or uses compression.
+    BadName,
+    /// The output buffer is too small for the response.
+    BufferTooSmall,
+}
+
+/// The question from a DNS query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub id: u16,
+    /// The queried name, as dot-separated labels without the trailing dot.
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+    /// Offset of the end of the question section.
+    question_end: usize,
+}
+
+impl Query {
+    /// Parse a query containing a single question.
+    pub fn parse(msg: &[u8]) -> Result<Query, Error> {
+        if msg.len() < HEADER_LEN {
+            return Err(Error::Truncated);
+        }
+        let id = read_u16(msg, 0)?;
+        let flags = read_u16(msg, 2)?;
+        if flags & FLAG_QR != 0 {
+            return Err(Error::NotAQuery);
+        }
+        let qdcount = read_u16(msg, 4)?;
+        if qdcount != 1 {
+            return Err(Error::UnsupportedQuestionCount(qdcount));
+        }
+
+        let mut name = String::new();
+        let mut pos = HEADER_LEN;
+        loop {
+            let len = *msg.get(pos).ok_or(Error::Truncated)? as usize;
+            pos += 1;
+            if len == 0 {
+                break;
+            }
+            // Queries never need name compression, and labels are at most 63
+            // bytes long.
+            if len > 63 {
+                return Err(Error::BadName);
+            }
+            let label = msg.get(pos..pos + len).ok_or(Error::Truncated)?;
+            if !name.is_empty() {
+                name.push('.');
+            }
+            name.push_str(core::str::from_utf8(label).map_err(|_| Error::BadName)?);
+            pos += len;
+        }
+
+        let qtype = read_u16(msg, pos)?;
+        let qclass = read_u16(msg, pos + 2)?;
+        Ok(Query {
+            id,
+            name,
+            qtype,
+            qclass,
+            question_end: pos + 4,
+        })
+    }
+
+    /// Whether an `A` record answers this query.
+    pub fn wants_ipv4(&self) -> bool {
+        (self.qtype == TYPE_A || self.qtype == TYPE_ANY) && self.qclass == CLASS_IN
+    }
+}
+
+/// Build a response to `query` which resolves any name to `addr`, writing it
+/// to `out` and returning its length.
+///
+/// Queries for other record types get an empty answer, and non-standard
+/// opcodes are refused with `NOTIMP`.
+pub fn build_response(query: &[u8], addr: Ipv4Addr, out: &mut [u8]) -> Result<usize, Error> {
+    let parsed = Query::parse(query)?;
+    let flags = read_u16(query, 2)?;
+    let standard_query = flags & OPCODE_MASK == 0;
+    let answer = standard_query && parsed.wants_ipv4();
+
+    let question = &query[HEADER_LEN..parsed.question_end];
+    let len = HEADER_LEN + question.len() + if answer { 16 } else { 0 };
+    if out.len() < len {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let mut resp_flags = FLAG_QR | FLAG_AA | (flags & (OPCODE_MASK | FLAG_RD));
+    if !standard_query {
+        resp_flags |= RCODE_NOT_IMPLEMENTED;
+    }
+    out[0..2].copy_from_slice(&parsed.id.to_be_bytes());
+    out[2..4].copy_from_slice(&resp_flags.to_be_bytes());
+    out[4..6].copy_from_slice(&1u16.to_be_bytes());
+    out[6..8].copy_from_slice(&(answer as u16).to_be_bytes());
+    out[8..12].copy_from_slice(&[0, 0, 0, 0]);
+    out[HEADER_LEN..HEADER_LEN + question.len()].copy_from_slice(question);
+
+    if answer {
+        let rr = &mut out[HEADER_LEN + question.len()..len];
+        // Point back at the name in the question.
+        rr[0..2].copy_from_slice(&(0xc000u16 | HEADER_LEN as u16).to_be_bytes());
+        rr[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+        rr[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        // A short TTL, so clients don't keep the bogus answer once provisioned.
+        rr[6..10].copy_from_slice(&60u32.to_be_bytes());
+        rr[10..12].copy_from_slice(&4u16.to_be_bytes());
+        rr[12..16].copy_from_slice(&addr.octets());
+    }
+
+    Ok(len)
+}
+
+fn read_u16(msg: &[u8], offset: usize) -> Result<u16, Error> {
+    msg.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or(Error::Truncated)
+}
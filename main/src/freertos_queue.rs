@@ -0,0 +1,123 @@
+//! Typed FreeRTOS queues, for passing values between tasks and from
+//! interrupts.
+
+use core::marker::PhantomData;
+use core::mem::{self, MaybeUninit};
+use esp_idf_sys::types::c_void;
+
+use crate::freertos_task::Error;
+use crate::freertos_units::DurationTicks;
+
+// `queueQUEUE_TYPE_BASE` and `queueSEND_TO_BACK`, which are macros.
+const QUEUE_TYPE_BASE: u8 = 0;
+const SEND_TO_BACK: esp_idf_sys::BaseType_t = 0;
+
+/// A fixed-length queue of `T`s, copied in and out. Share it between tasks
+/// with an `Arc`.
+pub struct Queue<T: Copy + Send> {
+    handle: esp_idf_sys::QueueHandle_t,
+    _item: PhantomData<T>,
+}
+
+unsafe impl<T: Copy + Send> Send for Queue<T> {}
+unsafe impl<T: Copy + Send> Sync for Queue<T> {}
+
+impl<T: Copy + Send> Queue<T> {
+    /// Create a queue with room for `len` items.
+    pub fn new(len: usize) -> Result<Queue<T>, Error> {
+        let handle = unsafe {
+            esp_idf_sys::xQueueGenericCreate(
+                len as esp_idf_sys::UBaseType_t,
+                mem::size_of::<T>() as esp_idf_sys::UBaseType_t,
+                QUEUE_TYPE_BASE,
+            )
+        };
+        if handle.is_null() {
+            Err(Error::NoMem)
+        } else {
+            Ok(Queue {
+                handle,
+                _item: PhantomData,
+            })
+        }
+    }
+
+    /// Get the underlying FreeRTOS queue handle.
+    pub fn raw_handle(&self) -> esp_idf_sys::QueueHandle_t {
+        self.handle
+    }
+
+    /// Add `item` to the back of the queue, waiting up to `timeout` for
+    /// room. Gives `item` back if the queue stayed full.
+    pub fn send(&self, item: T, timeout: impl DurationTicks) -> Result<(), T> {
+        let sent = unsafe {
+            esp_idf_sys::xQueueGenericSend(
+                self.handle,
+                &item as *const T as *const c_void,
+                timeout.to_ticks(),
+                SEND_TO_BACK,
+            )
+        };
+        if sent == 1 {
+            Ok(())
+        } else {
+            Err(item)
+        }
+    }
+
+    /// Add `item` from an interrupt handler, without waiting. `woken` is
+    /// set if a higher-priority task was waiting for the item, in which case
+    /// the handler should yield before returning.
+    pub fn send_from_isr(&self, item: T, woken: &mut bool) -> Result<(), T> {
+        let mut higher_priority_woken = 0;
+        let sent = unsafe {
+            esp_idf_sys::xQueueGenericSendFromISR(
+                self.handle,
+                &item as *const T as *const c_void,
+                &mut higher_priority_woken,
+                SEND_TO_BACK,
+            )
+        };
+        *woken |= higher_priority_woken != 0;
+        if sent == 1 {
+            Ok(())
+        } else {
+            Err(item)
+        }
+    }
+
+    /// Take the item at the front of the queue, waiting up to `timeout` for
+    /// one to arrive.
+    pub fn receive(&self, timeout: impl DurationTicks) -> Option<T> {
+        let mut item = MaybeUninit::<T>::uninit();
+        // `xQueueReceive` is a macro.
+        let received = unsafe {
+            esp_idf_sys::xQueueGenericReceive(
+                self.handle,
+                item.as_mut_ptr() as *mut c_void,
+                timeout.to_ticks(),
+                0,
+            )
+        };
+        if received == 1 {
+            Some(unsafe { item.assume_init() })
+        } else {
+            None
+        }
+    }
+
+    /// The number of items waiting.
+    pub fn len(&self) -> usize {
+        unsafe { esp_idf_sys::uxQueueMessagesWaiting(self.handle) as usize }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Copy + Send> Drop for Queue<T> {
+    fn drop(&mut self) {
+        unsafe { esp_idf_sys::vQueueDelete(self.handle) };
+    }
+}
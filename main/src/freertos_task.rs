@@ -1,12 +1,14 @@
 //! Adapted from `freertos_rs`'s `Task` abstraction.
 
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
 use cstr_core::{CStr, CString};
 use embedded_hal::blocking::delay::DelayMs;
 use esp_idf_sys::{
     pcTaskGetTaskName, types::c_void, uxTaskGetStackHighWaterMark, vTaskDelay, vTaskDelete,
     xTaskCreatePinnedToCore, xTaskGetCurrentTaskHandle, xTaskGetCurrentTaskHandleForCPU,
-    xTaskNotify, xTaskNotifyWait,
+    xTaskNotify, xTaskNotifyFromISR, xTaskNotifyWait,
 };
 
 use crate::freertos_units::{Duration, DurationTicks};
@@ -215,6 +217,20 @@ impl Task {
         }
     }
 
+    /// Wrap a raw FreeRTOS task handle.
+    ///
+    /// # Safety
+    ///
+    /// `task_handle` must refer to a task that hasn't been deleted.
+    pub unsafe fn from_raw_handle(task_handle: esp_idf_sys::TaskHandle_t) -> Task {
+        Task { task_handle }
+    }
+
+    /// Get the underlying FreeRTOS task handle.
+    pub fn raw_handle(&self) -> esp_idf_sys::TaskHandle_t {
+        self.task_handle
+    }
+
     /// Get the name of the current task.
     pub fn get_name(&self) -> Result<&'_ CStr, ()> {
         unsafe {
@@ -262,6 +278,21 @@ impl Task {
         Ok(())
     }
 
+    /// Notify this task from an interrupt handler. `woken` is set if the
+    /// task has a higher priority than the one interrupted, in which case the
+    /// handler should yield before returning.
+    pub fn notify_from_isr(
+        &self,
+        notification: TaskNotification,
+        woken: &mut bool,
+    ) -> Result<(), Error> {
+        let (u, e) = notification.to_freertos();
+        let mut higher_priority_woken = 0;
+        let r = unsafe { xTaskNotifyFromISR(self.task_handle, u, e, &mut higher_priority_woken) };
+        *woken |= higher_priority_woken != 0;
+        FreeRTOSError(r).into_result()
+    }
+
     /// Wait for a notification to be posted.
     pub fn wait_for_notification(
         &self,
@@ -288,6 +319,105 @@ impl Task {
     }
 }
 
+/// What a task was doing when it was looked at.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum TaskState {
+    Running,
+    Ready,
+    Blocked,
+    Suspended,
+    /// Deleted, but not yet cleaned up by the idle task.
+    Deleted,
+}
+
+impl TaskState {
+    fn from_raw(state: esp_idf_sys::eTaskState) -> Self {
+        match state {
+            esp_idf_sys::eTaskState_eRunning => TaskState::Running,
+            esp_idf_sys::eTaskState_eReady => TaskState::Ready,
+            esp_idf_sys::eTaskState_eBlocked => TaskState::Blocked,
+            esp_idf_sys::eTaskState_eSuspended => TaskState::Suspended,
+            _ => TaskState::Deleted,
+        }
+    }
+}
+
+/// A task, as listed by [`snapshot()`].
+///
+/// [`snapshot()`]: fn.snapshot.html
+#[derive(Clone, Debug)]
+pub struct TaskInfo {
+    pub name: String,
+    pub state: TaskState,
+    pub priority: u32,
+    /// The least stack the task has had left, in bytes.
+    pub stack_high_water_mark: u32,
+    pub affinity: CpuAffinity,
+}
+
+/// List every task.
+///
+/// The kernel fills in each task's state, priority and stack under its own
+/// lock, which also keeps tasks from being freed meanwhile. Names and
+/// affinities aren't part of that, so they're read just after, as
+/// FreeRTOS's `vTaskList` does; tasks already deleted are listed without
+/// them, since their memory is about to be freed.
+///
+/// Needs `CONFIG_FREERTOS_USE_TRACE_FACILITY`.
+pub fn snapshot() -> Vec<TaskInfo> {
+    // Room for a few tasks created between counting and listing.
+    let capacity = unsafe { esp_idf_sys::uxTaskGetNumberOfTasks() } as usize + 4;
+    let mut statuses: Vec<esp_idf_sys::TaskStatus_t> = Vec::with_capacity(capacity);
+    unsafe {
+        let count = esp_idf_sys::uxTaskGetSystemState(
+            statuses.as_mut_ptr(),
+            capacity as esp_idf_sys::UBaseType_t,
+            core::ptr::null_mut(),
+        );
+        statuses.set_len(count as usize);
+    }
+
+    let mut tasks = Vec::with_capacity(statuses.len());
+    for status in &statuses {
+        let state = TaskState::from_raw(status.eCurrentState);
+        let (name, affinity) = match state {
+            TaskState::Deleted => (String::new(), CpuAffinity::NoAffinity),
+            _ => unsafe { (task_name(status.pcTaskName), affinity(status.xHandle)) },
+        };
+        tasks.push(TaskInfo {
+            name,
+            state,
+            priority: status.uxCurrentPriority as u32,
+            stack_high_water_mark: status.usStackHighWaterMark,
+            affinity,
+        });
+    }
+    tasks
+}
+
+/// Copy a name from a TCB, which holds at most `configMAX_TASK_NAME_LEN`
+/// bytes.
+unsafe fn task_name(name: *const esp_idf_sys::types::c_char) -> String {
+    if name.is_null() {
+        return String::new();
+    }
+    let mut bytes = [0u8; esp_idf_sys::configMAX_TASK_NAME_LEN as usize];
+    let mut len = 0;
+    while len < bytes.len() && *name.add(len) != 0 {
+        bytes[len] = *name.add(len) as u8;
+        len += 1;
+    }
+    String::from(core::str::from_utf8(&bytes[..len]).unwrap_or("?"))
+}
+
+unsafe fn affinity(handle: esp_idf_sys::TaskHandle_t) -> CpuAffinity {
+    match esp_idf_sys::xTaskGetAffinity(handle) {
+        0 => CpuAffinity::Cpu(Cpu::Pro),
+        1 => CpuAffinity::Cpu(Cpu::App),
+        _ => CpuAffinity::NoAffinity,
+    }
+}
+
 /// Helper methods to be performed on the task that is currently executing.
 pub struct CurrentTask;
 impl CurrentTask {
@@ -302,6 +432,18 @@ impl CurrentTask {
     pub fn get_stack_high_water_mark() -> u32 {
         unsafe { uxTaskGetStackHighWaterMark(core::ptr::null_mut()) as u32 }
     }
+
+    /// Get the CPU the current task is running on.
+    pub fn cpu() -> Cpu {
+        unsafe {
+            let current = xTaskGetCurrentTaskHandle();
+            if xTaskGetCurrentTaskHandleForCPU(Cpu::App as esp_idf_sys::BaseType_t) == current {
+                Cpu::App
+            } else {
+                Cpu::Pro
+            }
+        }
+    }
 }
 
 impl DelayMs<u8> for CurrentTask {
@@ -0,0 +1,339 @@
+//! GPIO inputs that run a handler when they change.
+//!
+//! ```ignore
+//! let presses = Arc::new(Queue::new(8)?);
+//! let _button = InterruptPin::new(0)
+//!     .pull(Pull::Up)
+//!     .trigger(Trigger::FallingEdge)
+//!     .send_to(presses.clone(), ())?;
+//! while let Some(()) = presses.receive(Duration::infinite()) {
+//!     crate::println!("pressed");
+//! }
+//! ```
+//!
+//! Handlers run in interrupt context, from ESP-IDF's GPIO ISR service,
+//! which is installed the first time a pin is set up. The service is
+//! installed without `ESP_INTR_FLAG_IRAM`, so handlers may live in flash
+//! like the rest of the crate; the cost is that their interrupts are held
+//! off while flash is being written, e.g. by NVS. Anything a handler calls
+//! must be safe from an interrupt, which is why [`notify()`] and
+//! [`send_to()`] are the safe way to set a pin up.
+//!
+//! [`notify()`]: struct.InterruptPinBuilder.html#method.notify
+//! [`send_to()`]: struct.InterruptPinBuilder.html#method.send_to
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering};
+use esp_idf_hal::errors::EspError;
+use esp_idf_sys::types::c_void;
+
+use crate::freertos_queue::Queue;
+use crate::freertos_task::{CurrentTask, Task, TaskNotification};
+use crate::freertos_units::Duration;
+
+static SERVICE_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Pins with a handler, one bit per GPIO, as the ISR service only keeps
+/// one handler per pin.
+static CLAIMED: [AtomicU32; 2] = [AtomicU32::new(0), AtomicU32::new(0)];
+
+/// Mark `pin` as having a handler, failing if it already has one.
+fn claim(pin: i32) -> Result<(), EspError> {
+    if !(0..esp_idf_sys::gpio_num_t_GPIO_NUM_MAX).contains(&pin) {
+        return Err(EspError(
+            esp_idf_sys::ESP_ERR_INVALID_ARG as esp_idf_sys::esp_err_t,
+        ));
+    }
+    let bit = 1 << (pin % 32);
+    if CLAIMED[pin as usize / 32].fetch_or(bit, Ordering::AcqRel) & bit != 0 {
+        return Err(EspError(
+            esp_idf_sys::ESP_ERR_INVALID_STATE as esp_idf_sys::esp_err_t,
+        ));
+    }
+    Ok(())
+}
+
+fn release(pin: i32) {
+    CLAIMED[pin as usize / 32].fetch_and(!(1 << (pin % 32)), Ordering::AcqRel);
+}
+
+/// Where `dispatch` finds a pin's handler. The ISR service is only passed
+/// the pin number, as it may still call the handler for a moment after
+/// it's removed.
+struct Slot {
+    registration: AtomicPtr<Registration>,
+    /// How many `dispatch` calls are between announcing themselves and
+    /// being done with `registration`.
+    busy: AtomicU32,
+}
+
+// Only used to initialize `SLOTS`.
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: Slot = Slot {
+    registration: AtomicPtr::new(core::ptr::null_mut()),
+    busy: AtomicU32::new(0),
+};
+
+static SLOTS: [Slot; esp_idf_sys::gpio_num_t_GPIO_NUM_MAX as usize] =
+    [EMPTY_SLOT; esp_idf_sys::gpio_num_t_GPIO_NUM_MAX as usize];
+
+/// What makes the interrupt fire.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    RisingEdge,
+    FallingEdge,
+    AnyEdge,
+    /// Fires once while the input is low, then stays disabled until
+    /// `InterruptPin::enable` is called.
+    LowLevel,
+    /// Fires once while the input is high, like `LowLevel`.
+    HighLevel,
+}
+
+impl Trigger {
+    fn to_raw(self) -> esp_idf_sys::gpio_int_type_t {
+        match self {
+            Trigger::RisingEdge => esp_idf_sys::gpio_int_type_t_GPIO_INTR_POSEDGE,
+            Trigger::FallingEdge => esp_idf_sys::gpio_int_type_t_GPIO_INTR_NEGEDGE,
+            Trigger::AnyEdge => esp_idf_sys::gpio_int_type_t_GPIO_INTR_ANYEDGE,
+            Trigger::LowLevel => esp_idf_sys::gpio_int_type_t_GPIO_INTR_LOW_LEVEL,
+            Trigger::HighLevel => esp_idf_sys::gpio_int_type_t_GPIO_INTR_HIGH_LEVEL,
+        }
+    }
+
+    fn is_level(self) -> bool {
+        matches!(self, Trigger::LowLevel | Trigger::HighLevel)
+    }
+}
+
+/// The input's internal pull resistor. GPIOs 34 to 39 have none.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// Runs in interrupt context; sets its argument if it woke a
+/// higher-priority task.
+type Handler = Box<dyn FnMut(&mut bool) + Send>;
+
+struct Registration {
+    pin: i32,
+    level_triggered: bool,
+    handler: Handler,
+}
+
+/// Helper for setting a pin up. Instantiate with [`InterruptPin::new()`].
+///
+/// [`InterruptPin::new()`]: struct.InterruptPin.html#method.new
+pub struct InterruptPinBuilder {
+    pin: i32,
+    trigger: Trigger,
+    pull: Pull,
+}
+
+impl InterruptPinBuilder {
+    /// Set what makes the interrupt fire. Defaults to `AnyEdge`.
+    pub fn trigger(self, trigger: Trigger) -> Self {
+        InterruptPinBuilder { trigger, ..self }
+    }
+
+    /// Set the pull resistor. Defaults to none.
+    pub fn pull(self, pull: Pull) -> Self {
+        InterruptPinBuilder { pull, ..self }
+    }
+
+    /// Send `notification` to `task` on every interrupt.
+    pub fn notify(
+        self,
+        task: Task,
+        notification: TaskNotification,
+    ) -> Result<InterruptPin, EspError> {
+        // Notifying is safe from an interrupt.
+        unsafe {
+            self.on_interrupt(move |woken| {
+                let _ = task.notify_from_isr(notification, woken);
+            })
+        }
+    }
+
+    /// Send `item` to `queue` on every interrupt. Items are dropped while
+    /// the queue is full.
+    pub fn send_to<T: Copy + Send + 'static>(
+        self,
+        queue: Arc<Queue<T>>,
+        item: T,
+    ) -> Result<InterruptPin, EspError> {
+        // Sending is safe from an interrupt, and the `Arc` is only dropped
+        // with the pin.
+        unsafe {
+            self.on_interrupt(move |woken| {
+                let _ = queue.send_from_isr(item, woken);
+            })
+        }
+    }
+
+    /// Run `handler` on every interrupt. It's passed a flag to set if it
+    /// woke a higher-priority task, e.g. through `Queue::send_from_isr`, so
+    /// that the interrupt yields to that task.
+    ///
+    /// Fails with `ESP_ERR_INVALID_STATE` if the pin already has a handler.
+    ///
+    /// # Safety
+    ///
+    /// `handler` runs in interrupt context. It must not block, allocate or
+    /// free memory, or call anything other than the `FromISR` flavours of
+    /// FreeRTOS functions and others documented as safe from interrupts.
+    pub unsafe fn on_interrupt(
+        self,
+        handler: impl FnMut(&mut bool) + Send + 'static,
+    ) -> Result<InterruptPin, EspError> {
+        install_service()?;
+        claim(self.pin)?;
+        let registration = Box::into_raw(Box::new(Registration {
+            pin: self.pin,
+            level_triggered: self.trigger.is_level(),
+            handler: Box::new(handler),
+        }));
+        SLOTS[self.pin as usize]
+            .registration
+            .store(registration, Ordering::SeqCst);
+        // Cleans up if any of the setup below fails.
+        let pin = InterruptPin {
+            pin: self.pin,
+            registration,
+        };
+
+        let config = esp_idf_sys::gpio_config_t {
+            pin_bit_mask: 1 << self.pin,
+            mode: esp_idf_sys::gpio_mode_t_GPIO_MODE_INPUT,
+            pull_up_en: if self.pull == Pull::Up {
+                esp_idf_sys::gpio_pullup_t_GPIO_PULLUP_ENABLE
+            } else {
+                esp_idf_sys::gpio_pullup_t_GPIO_PULLUP_DISABLE
+            },
+            pull_down_en: if self.pull == Pull::Down {
+                esp_idf_sys::gpio_pulldown_t_GPIO_PULLDOWN_ENABLE
+            } else {
+                esp_idf_sys::gpio_pulldown_t_GPIO_PULLDOWN_DISABLE
+            },
+            // Enabled once the handler is in place.
+            intr_type: esp_idf_sys::gpio_int_type_t_GPIO_INTR_DISABLE,
+        };
+        EspError(esp_idf_sys::gpio_config(&config)).into_result()?;
+        EspError(esp_idf_sys::gpio_isr_handler_add(
+            self.pin,
+            Some(dispatch),
+            self.pin as *mut c_void,
+        ))
+        .into_result()?;
+        EspError(esp_idf_sys::gpio_set_intr_type(
+            self.pin,
+            self.trigger.to_raw(),
+        ))
+        .into_result()?;
+        pin.enable()?;
+        Ok(pin)
+    }
+}
+
+/// A GPIO input with a registered interrupt handler. Dropping it disables
+/// the interrupt and removes the handler.
+pub struct InterruptPin {
+    pin: i32,
+    registration: *mut Registration,
+}
+
+unsafe impl Send for InterruptPin {}
+
+impl InterruptPin {
+    /// Prepare a builder object for GPIO `pin`.
+    pub fn new(pin: i32) -> InterruptPinBuilder {
+        InterruptPinBuilder {
+            pin,
+            trigger: Trigger::AnyEdge,
+            pull: Pull::None,
+        }
+    }
+
+    pub fn pin(&self) -> i32 {
+        self.pin
+    }
+
+    pub fn is_high(&self) -> bool {
+        unsafe { esp_idf_sys::gpio_get_level(self.pin) != 0 }
+    }
+
+    /// Enable the interrupt, e.g. to re-arm a level trigger once the level
+    /// has been dealt with.
+    pub fn enable(&self) -> Result<(), EspError> {
+        EspError(unsafe { esp_idf_sys::gpio_intr_enable(self.pin) }).into_result()
+    }
+
+    pub fn disable(&self) -> Result<(), EspError> {
+        EspError(unsafe { esp_idf_sys::gpio_intr_disable(self.pin) }).into_result()
+    }
+}
+
+impl Drop for InterruptPin {
+    fn drop(&mut self) {
+        unsafe {
+            esp_idf_sys::gpio_intr_disable(self.pin);
+            esp_idf_sys::gpio_isr_handler_remove(self.pin);
+        }
+        // The handler may still be running on the other core.
+        let slot = &SLOTS[self.pin as usize];
+        slot.registration
+            .store(core::ptr::null_mut(), Ordering::SeqCst);
+        while slot.busy.load(Ordering::SeqCst) != 0 {
+            CurrentTask::delay(Duration::eps());
+        }
+        drop(unsafe { Box::from_raw(self.registration) });
+        release(self.pin);
+    }
+}
+
+/// Install the GPIO ISR service, unless it already is. If another task is
+/// installing it at the same time, this returns straight away and adding
+/// the handler fails if the installation does.
+fn install_service() -> Result<(), EspError> {
+    if SERVICE_INSTALLED.swap(true, Ordering::AcqRel) {
+        return Ok(());
+    }
+    // Default flags: a level 1 interrupt that's held off during flash
+    // writes.
+    let ret = unsafe { esp_idf_sys::gpio_install_isr_service(0) };
+    // Someone else installed it outside this module.
+    if ret == esp_idf_sys::ESP_ERR_INVALID_STATE as esp_idf_sys::esp_err_t {
+        return Ok(());
+    }
+    let result = EspError(ret).into_result();
+    if result.is_err() {
+        SERVICE_INSTALLED.store(false, Ordering::Release);
+    }
+    result
+}
+
+unsafe extern "C" fn dispatch(arg: *mut c_void) {
+    let slot = &SLOTS[arg as usize];
+    // Announcing the call before loading `registration` means `Drop`
+    // either waits for it or has already cleared `registration`.
+    slot.busy.fetch_add(1, Ordering::SeqCst);
+    let registration = slot.registration.load(Ordering::SeqCst);
+    let mut woken = false;
+    if !registration.is_null() {
+        let registration = &mut *registration;
+        if registration.level_triggered {
+            // Otherwise it fires again as soon as it returns.
+            esp_idf_sys::gpio_intr_disable(registration.pin);
+        }
+        (registration.handler)(&mut woken);
+    }
+    slot.busy.fetch_sub(1, Ordering::SeqCst);
+    if woken {
+        // `portYIELD_FROM_ISR`.
+        esp_idf_sys::_frxt_setup_switch();
+    }
+}
@@ -0,0 +1,261 @@
+//! Heap statistics and allocation from memory with specific capabilities.
+//!
+//! The global allocator takes memory from the default heap. Buffers that
+//! must be DMA-capable, or that should live in external SPI RAM, can be
+//! allocated with a [`CapsAllocator`]:
+//!
+//! ```ignore
+//! let mut framebuffer = Vec::with_capacity_in(128 * 64 / 8, DmaAllocator::new());
+//! ```
+//!
+//! [`CapsAllocator`]: struct.CapsAllocator.html
+
+use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
+use core::fmt;
+use core::mem;
+use core::ptr::{self, NonNull};
+
+use esp_idf_sys::types::c_void;
+
+use crate::alloc_tracker::Platform;
+
+/// Alignment of every block returned by `heap_caps_malloc`.
+const MIN_ALIGN: usize = mem::size_of::<usize>();
+
+/// A kind of memory, as a set of `MALLOC_CAP_*` flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    /// Usable by DMA peripherals.
+    Dma,
+    /// Byte-addressable.
+    Byte,
+    /// Only accessible as 32-bit words.
+    Word,
+    /// Internal RAM, as opposed to SPI RAM.
+    Internal,
+    /// External SPI RAM.
+    SpiRam,
+    /// What `malloc` uses.
+    Default,
+}
+
+impl Capability {
+    /// Every capability except `Default`.
+    pub const ALL: [Capability; 5] = [
+        Capability::Dma,
+        Capability::Byte,
+        Capability::Word,
+        Capability::Internal,
+        Capability::SpiRam,
+    ];
+
+    pub fn caps(self) -> u32 {
+        match self {
+            Capability::Dma => esp_idf_sys::MALLOC_CAP_DMA,
+            Capability::Byte => esp_idf_sys::MALLOC_CAP_8BIT,
+            Capability::Word => esp_idf_sys::MALLOC_CAP_32BIT,
+            Capability::Internal => esp_idf_sys::MALLOC_CAP_INTERNAL,
+            Capability::SpiRam => esp_idf_sys::MALLOC_CAP_SPIRAM,
+            Capability::Default => esp_idf_sys::MALLOC_CAP_DEFAULT,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Dma => "DMA",
+            Capability::Byte => "8-bit",
+            Capability::Word => "32-bit",
+            Capability::Internal => "internal",
+            Capability::SpiRam => "SPIRAM",
+            Capability::Default => "default",
+        }
+    }
+}
+
+/// A snapshot of the heaps with some capabilities.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    pub free_bytes: usize,
+    pub allocated_bytes: usize,
+    /// The largest allocation that could currently succeed.
+    pub largest_free_block: usize,
+    /// The lowest `free_bytes` has been since boot.
+    pub minimum_free_bytes: usize,
+    pub allocated_blocks: usize,
+    pub free_blocks: usize,
+    pub total_blocks: usize,
+}
+
+impl HeapStats {
+    /// Get statistics for the heaps with all of `caps`, a set of
+    /// `MALLOC_CAP_*` flags.
+    pub fn for_caps(caps: u32) -> HeapStats {
+        let mut info: esp_idf_sys::multi_heap_info_t = unsafe { mem::zeroed() };
+        unsafe { esp_idf_sys::heap_caps_get_info(&mut info, caps) };
+        HeapStats {
+            free_bytes: info.total_free_bytes as usize,
+            allocated_bytes: info.total_allocated_bytes as usize,
+            largest_free_block: info.largest_free_block as usize,
+            minimum_free_bytes: info.minimum_free_bytes as usize,
+            allocated_blocks: info.allocated_blocks as usize,
+            free_blocks: info.free_blocks as usize,
+            total_blocks: info.total_blocks as usize,
+        }
+    }
+
+    pub fn get(capability: Capability) -> HeapStats {
+        Self::for_caps(capability.caps())
+    }
+
+    /// Statistics for each of `Capability::ALL`.
+    pub fn all() -> [(Capability, HeapStats); 5] {
+        let mut all = [(Capability::Default, HeapStats::default()); 5];
+        for (slot, &capability) in all.iter_mut().zip(Capability::ALL.iter()) {
+            *slot = (capability, Self::get(capability));
+        }
+        all
+    }
+
+    /// Free bytes that are too fragmented to be used by the largest
+    /// allocation, as a fraction of all free bytes.
+    pub fn fragmentation(&self) -> f32 {
+        if self.free_bytes == 0 {
+            0.0
+        } else {
+            1.0 - self.largest_free_block as f32 / self.free_bytes as f32
+        }
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} free (min {}, largest block {}), {} allocated in {} blocks",
+            self.free_bytes,
+            self.minimum_free_bytes,
+            self.largest_free_block,
+            self.allocated_bytes,
+            self.allocated_blocks
+        )
+    }
+}
+
+/// Check every heap for corruption, optionally printing what's wrong.
+/// Returns `false` if corruption was found.
+pub fn check_integrity(print_errors: bool) -> bool {
+    unsafe { esp_idf_sys::heap_caps_check_integrity_all(print_errors) }
+}
+
+/// Allocates from the heaps with all of `CAPS`, a set of `MALLOC_CAP_*`
+/// flags. Usable with the `Allocator` API, or as a `#[global_allocator]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CapsAllocator<const CAPS: u32>;
+
+/// Allocates DMA-capable memory.
+pub type DmaAllocator = CapsAllocator<{ esp_idf_sys::MALLOC_CAP_DMA }>;
+/// Allocates internal memory.
+pub type InternalAllocator = CapsAllocator<{ esp_idf_sys::MALLOC_CAP_INTERNAL }>;
+/// Allocates external SPI RAM.
+pub type SpiRamAllocator = CapsAllocator<{ esp_idf_sys::MALLOC_CAP_SPIRAM }>;
+
+impl<const CAPS: u32> CapsAllocator<CAPS> {
+    pub const fn new() -> Self {
+        CapsAllocator
+    }
+
+    /// Statistics for the heaps this allocates from.
+    pub fn stats(&self) -> HeapStats {
+        HeapStats::for_caps(CAPS)
+    }
+}
+
+unsafe impl<const CAPS: u32> GlobalAlloc for CapsAllocator<CAPS> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.align() <= MIN_ALIGN {
+            return esp_idf_sys::heap_caps_malloc(layout.size() as _, CAPS) as *mut u8;
+        }
+
+        // `heap_caps_malloc` can't align beyond `MIN_ALIGN`, so allocate
+        // extra and keep the real block's address just before the aligned
+        // one. There's always room, as the block is at least `MIN_ALIGN`
+        // aligned.
+        let raw = esp_idf_sys::heap_caps_malloc((layout.size() + layout.align()) as _, CAPS);
+        if raw.is_null() {
+            return ptr::null_mut();
+        }
+        let aligned = (raw as usize + layout.align()) & !(layout.align() - 1);
+        *((aligned - MIN_ALIGN) as *mut *mut c_void) = raw;
+        aligned as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let raw = if layout.align() <= MIN_ALIGN {
+            ptr as *mut c_void
+        } else {
+            *((ptr as usize - MIN_ALIGN) as *const *mut c_void)
+        };
+        esp_idf_sys::heap_caps_free(raw);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if layout.align() <= MIN_ALIGN {
+            return esp_idf_sys::heap_caps_realloc(ptr as *mut c_void, new_size as _, CAPS as _)
+                as *mut u8;
+        }
+
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new = self.alloc(new_layout);
+        if !new.is_null() {
+            ptr::copy_nonoverlapping(ptr, new, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new
+    }
+}
+
+unsafe impl<const CAPS: u32> Allocator for CapsAllocator<CAPS> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            // Zero-sized blocks only need to be aligned, not backed by memory.
+            let dangling = NonNull::new(layout.align() as *mut u8).ok_or(AllocError)?;
+            return Ok(NonNull::slice_from_raw_parts(dangling, 0));
+        }
+        let ptr = NonNull::new(unsafe { self.alloc(layout) }).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            self.dealloc(ptr.as_ptr(), layout);
+        }
+    }
+}
+
+static mut TRACKER_LOCK: esp_idf_sys::portMUX_TYPE = esp_idf_sys::portMUX_TYPE {
+    owner: esp_idf_sys::portMUX_FREE_VAL,
+    count: 0,
+};
+
+/// Runs the allocation tracker on FreeRTOS: tasks are identified by their
+/// handles, and the tracker's state is guarded by a critical section.
+pub struct FreeRtosPlatform;
+
+impl Platform for FreeRtosPlatform {
+    fn current_task() -> usize {
+        match unsafe { esp_idf_sys::xTaskGetCurrentTaskHandle() } as usize {
+            // Allocations before the scheduler starts.
+            0 => 1,
+            task => task,
+        }
+    }
+
+    fn locked<R>(f: impl FnOnce() -> R) -> R {
+        unsafe {
+            esp_idf_sys::vTaskEnterCritical(&mut TRACKER_LOCK);
+            let r = f();
+            esp_idf_sys::vTaskExitCritical(&mut TRACKER_LOCK);
+            r
+        }
+    }
+}
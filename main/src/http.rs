@@ -0,0 +1,223 @@
+//! Just enough HTTP/1.x to serve a form: request parsing, URL-encoded form
+//! decoding and response formatting.
+//!
+//! This module is plain Rust and doesn't touch `esp-idf`, so it can be
+//! exercised on the host.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write as _;
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Other,
+}
+
+/// Reasons a request couldn't be parsed.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// More data is needed: the headers haven't ended, or the body is shorter
+    /// than its `Content-Length`.
+    Incomplete,
+    Malformed,
+}
+
+/// A parsed request, borrowing from the receive buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub method: Method,
+    /// The path, without the query string.
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    headers: &'a str,
+    pub body: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    /// Parse a complete request from `buf`.
+    pub fn parse(buf: &'a [u8]) -> Result<Request<'a>, ParseError> {
+        Request::parse_within(buf, usize::max_value())
+    }
+
+    /// Parse a complete request from `buf`, the start of a receive buffer
+    /// that holds at most `capacity` bytes. A request that can't fit is
+    /// `Malformed` rather than `Incomplete`, since no more data can arrive.
+    pub fn parse_within(buf: &'a [u8], capacity: usize) -> Result<Request<'a>, ParseError> {
+        let head_end = match find(buf, b"\r\n\r\n") {
+            Some(i) => i,
+            None if buf.len() < capacity => return Err(ParseError::Incomplete),
+            None => return Err(ParseError::Malformed),
+        };
+        let head = core::str::from_utf8(&buf[..head_end]).map_err(|_| ParseError::Malformed)?;
+        let (request_line, headers) = match head.find("\r\n") {
+            Some(i) => (&head[..i], &head[i + 2..]),
+            None => (head, ""),
+        };
+
+        let mut parts = request_line.split(' ');
+        let method = match parts.next() {
+            Some("GET") => Method::Get,
+            Some("HEAD") => Method::Head,
+            Some("POST") => Method::Post,
+            Some(m) if !m.is_empty() => Method::Other,
+            _ => return Err(ParseError::Malformed),
+        };
+        let target = parts.next().ok_or(ParseError::Malformed)?;
+        match parts.next() {
+            Some(v) if v.starts_with("HTTP/1.") => (),
+            _ => return Err(ParseError::Malformed),
+        }
+        let (path, query) = match target.find('?') {
+            Some(i) => (&target[..i], Some(&target[i + 1..])),
+            None => (target, None),
+        };
+
+        let mut request = Request {
+            method,
+            path,
+            query,
+            headers,
+            body: &[],
+        };
+
+        let body_start = head_end + 4;
+        let body_len = match request.header("Content-Length") {
+            Some(len) => len.trim().parse().map_err(|_| ParseError::Malformed)?,
+            None => 0,
+        };
+        let body_end = body_start
+            .checked_add(body_len)
+            .filter(|&end| end <= capacity)
+            .ok_or(ParseError::Malformed)?;
+        request.body = buf
+            .get(body_start..body_end)
+            .ok_or(ParseError::Incomplete)?;
+        Ok(request)
+    }
+
+    /// Look up a header by case-insensitive name.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers.split("\r\n").find_map(|line| {
+            let i = line.find(':')?;
+            if line[..i].trim().eq_ignore_ascii_case(name) {
+                Some(line[i + 1..].trim())
+            } else {
+                None
+            }
+        })
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Decode a `application/x-www-form-urlencoded` component, returning `None`
+/// if it has invalid escapes or isn't UTF-8.
+pub fn url_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex = s.get(i + 1..i + 3)?;
+                if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return None;
+                }
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 2;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Iterate over the decoded `(key, value)` pairs of a URL-encoded form.
+/// Malformed pairs are skipped.
+pub fn form_fields(body: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    body.split('&').filter(|p| !p.is_empty()).filter_map(|pair| {
+        let (k, v) = match pair.find('=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, ""),
+        };
+        Some((url_decode(k)?, url_decode(v)?))
+    })
+}
+
+/// Find the first value for `key` in a URL-encoded form.
+pub fn form_value(body: &str, key: &str) -> Option<String> {
+    form_fields(body).find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+/// Append `s` to `out`, escaped for use in HTML text or attribute values.
+pub fn html_escape(s: &str, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// A response to be serialized with `write_to`.
+pub struct Response<'a> {
+    pub status: u16,
+    pub reason: &'a str,
+    pub content_type: Option<&'a str>,
+    pub location: Option<&'a str>,
+    pub body: &'a [u8],
+}
+
+impl<'a> Response<'a> {
+    /// A `200 OK` HTML page.
+    pub fn html(body: &'a str) -> Self {
+        Response {
+            status: 200,
+            reason: "OK",
+            content_type: Some("text/html; charset=utf-8"),
+            location: None,
+            body: body.as_bytes(),
+        }
+    }
+
+    /// A `302 Found` redirect to `location`.
+    pub fn redirect(location: &'a str) -> Self {
+        Response {
+            status: 302,
+            reason: "Found",
+            content_type: None,
+            location: Some(location),
+            body: &[],
+        }
+    }
+
+    /// Serialize the response. The connection is always marked as closing.
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        let mut head = String::new();
+        let _ = write!(head, "HTTP/1.1 {} {}\r\n", self.status, self.reason);
+        if let Some(content_type) = self.content_type {
+            let _ = write!(head, "Content-Type: {}\r\n", content_type);
+        }
+        if let Some(location) = self.location {
+            let _ = write!(head, "Location: {}\r\n", location);
+        }
+        let _ = write!(
+            head,
+            "Content-Length: {}\r\nConnection: close\r\nCache-Control: no-store\r\n\r\n",
+            self.body.len()
+        );
+        out.extend_from_slice(head.as_bytes());
+        out.extend_from_slice(self.body);
+    }
+}
@@ -0,0 +1,279 @@
+//! Parsing of IEEE 802.11 MAC headers.
+//!
+//! This module is plain Rust and doesn't touch `esp-idf`, so it can be
+//! exercised on the host; see `host-tests`.
+
+use crate::net::MacAddress;
+
+/// The frame's type, from the frame control field.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum FrameType {
+    Management,
+    Control,
+    Data,
+    Extension,
+}
+
+/// Frame subtypes that are commonly of interest. Anything else is reported as
+/// `Other` with the raw 4-bit subtype.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum FrameSubtype {
+    AssociationRequest,
+    AssociationResponse,
+    ReassociationRequest,
+    ReassociationResponse,
+    ProbeRequest,
+    ProbeResponse,
+    Beacon,
+    Disassociation,
+    Authentication,
+    Deauthentication,
+    Action,
+    BlockAckRequest,
+    BlockAck,
+    PsPoll,
+    Rts,
+    Cts,
+    Ack,
+    Data,
+    Null,
+    QosData,
+    QosNull,
+    Other(u8),
+}
+
+impl FrameSubtype {
+    fn new(ty: FrameType, subtype: u8) -> Self {
+        use FrameSubtype::*;
+        match (ty, subtype) {
+            (FrameType::Management, 0) => AssociationRequest,
+            (FrameType::Management, 1) => AssociationResponse,
+            (FrameType::Management, 2) => ReassociationRequest,
+            (FrameType::Management, 3) => ReassociationResponse,
+            (FrameType::Management, 4) => ProbeRequest,
+            (FrameType::Management, 5) => ProbeResponse,
+            (FrameType::Management, 8) => Beacon,
+            (FrameType::Management, 10) => Disassociation,
+            (FrameType::Management, 11) => Authentication,
+            (FrameType::Management, 12) => Deauthentication,
+            (FrameType::Management, 13) => Action,
+            (FrameType::Control, 8) => BlockAckRequest,
+            (FrameType::Control, 9) => BlockAck,
+            (FrameType::Control, 10) => PsPoll,
+            (FrameType::Control, 11) => Rts,
+            (FrameType::Control, 12) => Cts,
+            (FrameType::Control, 13) => Ack,
+            (FrameType::Data, 0) => Data,
+            (FrameType::Data, 4) => Null,
+            (FrameType::Data, 8) => QosData,
+            (FrameType::Data, 12) => QosNull,
+            (_, other) => Other(other),
+        }
+    }
+}
+
+/// The two-byte frame control field at the start of every frame.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub struct FrameControl(pub u16);
+
+impl FrameControl {
+    pub fn protocol_version(self) -> u8 {
+        (self.0 & 0b11) as u8
+    }
+
+    pub fn frame_type(self) -> FrameType {
+        match (self.0 >> 2) & 0b11 {
+            0 => FrameType::Management,
+            1 => FrameType::Control,
+            2 => FrameType::Data,
+            _ => FrameType::Extension,
+        }
+    }
+
+    /// The raw 4-bit subtype.
+    pub fn subtype_bits(self) -> u8 {
+        ((self.0 >> 4) & 0b1111) as u8
+    }
+
+    pub fn subtype(self) -> FrameSubtype {
+        FrameSubtype::new(self.frame_type(), self.subtype_bits())
+    }
+
+    pub fn to_ds(self) -> bool {
+        self.flag(0x01)
+    }
+
+    pub fn from_ds(self) -> bool {
+        self.flag(0x02)
+    }
+
+    pub fn more_fragments(self) -> bool {
+        self.flag(0x04)
+    }
+
+    pub fn retry(self) -> bool {
+        self.flag(0x08)
+    }
+
+    pub fn power_management(self) -> bool {
+        self.flag(0x10)
+    }
+
+    pub fn more_data(self) -> bool {
+        self.flag(0x20)
+    }
+
+    pub fn protected(self) -> bool {
+        self.flag(0x40)
+    }
+
+    pub fn order(self) -> bool {
+        self.flag(0x80)
+    }
+
+    fn flag(self, mask: u16) -> bool {
+        (self.0 >> 8) & mask != 0
+    }
+}
+
+/// Fragment and sequence numbers.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub struct SequenceControl {
+    pub fragment: u8,
+    pub sequence: u16,
+}
+
+/// A parsed MAC header. Which addresses are present depends on the frame type.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub frame_control: FrameControl,
+    pub duration: u16,
+    pub addr1: MacAddress,
+    pub addr2: Option<MacAddress>,
+    pub addr3: Option<MacAddress>,
+    pub sequence_control: Option<SequenceControl>,
+    pub addr4: Option<MacAddress>,
+    /// QoS control field, for QoS data subtypes.
+    pub qos_control: Option<u16>,
+    /// Length of the header in bytes; the frame body starts here.
+    pub len: usize,
+}
+
+/// Reasons a header couldn't be parsed.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The buffer ends before the header does.
+    Truncated,
+    /// The protocol version isn't 0.
+    UnsupportedVersion(u8),
+}
+
+impl FrameHeader {
+    /// Parse the MAC header at the start of `frame`.
+    pub fn parse(frame: &[u8]) -> Result<Self, ParseError> {
+        let fc = FrameControl(read_u16(frame, 0)?);
+        if fc.protocol_version() != 0 {
+            return Err(ParseError::UnsupportedVersion(fc.protocol_version()));
+        }
+        let duration = read_u16(frame, 2)?;
+        let addr1 = read_addr(frame, 4)?;
+
+        let mut header = FrameHeader {
+            frame_control: fc,
+            duration,
+            addr1,
+            addr2: None,
+            addr3: None,
+            sequence_control: None,
+            addr4: None,
+            qos_control: None,
+            len: 10,
+        };
+
+        match fc.frame_type() {
+            FrameType::Control => {
+                // CTS and ACK only carry the receiver address.
+                match fc.subtype() {
+                    FrameSubtype::Cts | FrameSubtype::Ack => (),
+                    _ => {
+                        header.addr2 = Some(read_addr(frame, 10)?);
+                        header.len = 16;
+                    }
+                }
+            }
+            FrameType::Management | FrameType::Data => {
+                header.addr2 = Some(read_addr(frame, 10)?);
+                header.addr3 = Some(read_addr(frame, 16)?);
+                let sc = read_u16(frame, 22)?;
+                header.sequence_control = Some(SequenceControl {
+                    fragment: (sc & 0xf) as u8,
+                    sequence: sc >> 4,
+                });
+                header.len = 24;
+
+                if fc.frame_type() == FrameType::Data {
+                    if fc.to_ds() && fc.from_ds() {
+                        header.addr4 = Some(read_addr(frame, header.len)?);
+                        header.len += 6;
+                    }
+                    // The QoS subtypes all have bit 3 of the subtype set.
+                    if fc.subtype_bits() & 0b1000 != 0 {
+                        header.qos_control = Some(read_u16(frame, header.len)?);
+                        header.len += 2;
+                    }
+                }
+            }
+            FrameType::Extension => (),
+        }
+
+        Ok(header)
+    }
+
+    /// The BSSID, if the frame identifies one.
+    pub fn bssid(&self) -> Option<MacAddress> {
+        let fc = self.frame_control;
+        match fc.frame_type() {
+            FrameType::Management => self.addr3,
+            FrameType::Data => match (fc.to_ds(), fc.from_ds()) {
+                (false, false) => self.addr3,
+                (false, true) => self.addr2,
+                (true, false) => Some(self.addr1),
+                (true, true) => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The address of the station that originated the frame, if present.
+    pub fn source(&self) -> Option<MacAddress> {
+        let fc = self.frame_control;
+        match (fc.frame_type(), fc.to_ds(), fc.from_ds()) {
+            (FrameType::Data, false, true) => self.addr3,
+            (FrameType::Data, true, true) => self.addr4,
+            _ => self.addr2,
+        }
+    }
+
+    /// The address of the final recipient of the frame.
+    pub fn destination(&self) -> MacAddress {
+        let fc = self.frame_control;
+        match (fc.frame_type(), fc.to_ds()) {
+            (FrameType::Data, true) => self.addr3.unwrap_or(self.addr1),
+            _ => self.addr1,
+        }
+    }
+}
+
+fn read_u16(frame: &[u8], offset: usize) -> Result<u16, ParseError> {
+    frame
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or(ParseError::Truncated)
+}
+
+fn read_addr(frame: &[u8], offset: usize) -> Result<MacAddress, ParseError> {
+    frame
+        .get(offset..)
+        .and_then(MacAddress::from_slice)
+        .ok_or(ParseError::Truncated)
+}
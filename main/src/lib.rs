@@ -1,5 +1,6 @@
 #![no_std]
 #![feature(alloc_error_handler)]
+#![feature(allocator_api)]
 #![feature(panic_info_message)]
 
 extern crate alloc;
@@ -7,70 +8,124 @@ extern crate alloc;
 use core::alloc::Layout;
 use core::panic::PanicInfo;
 
+pub mod alloc_tracker;
 mod app;
+pub mod binlog;
+pub mod binlog_wire;
+pub mod button;
+pub mod button_gesture;
+pub mod captive_portal;
+pub mod console;
+pub mod crash;
+pub mod csi;
+pub mod dispatcher;
+pub mod dns;
+pub mod freertos_queue;
 pub mod freertos_task;
 pub mod freertos_units;
+pub mod gpio_interrupt;
+pub mod heap;
+pub mod http;
+pub mod ieee80211;
+pub mod log_buffer;
+pub mod logger;
+pub mod modbus;
+pub mod modbus_rtu;
+pub mod net;
+pub mod netif;
+pub mod nvs;
+pub mod pcap;
+pub mod persistent_config;
 mod print;
-mod wifi;
+pub mod repl;
+pub mod rng;
+pub mod smartconfig;
+pub mod sniffer;
+pub mod socket;
+pub mod spsc;
+pub mod system;
+pub mod uart;
+pub mod wifi;
 
-pub use print::PrintF;
+#[doc(hidden)]
+pub use print::{_eprint, _print};
+pub use print::{lock, ConsoleLock, EPrintF, PrintF};
 
+/// Print to stdout. Output from concurrent tasks isn't interleaved.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::_print(format_args!($($arg)*))
+    };
+}
+
+/// Print to stdout, with a newline.
 #[macro_export]
 macro_rules! println {
-    ($fmt:expr) => {
-        #[allow(unused_import)]
-        {
-            use core::fmt::Write as _;
-            let _ = writeln!(&mut $crate::PrintF, $fmt);
-        }
+    () => {
+        $crate::print!("\n")
     };
-    ($fmt:expr, $($arg:tt)*) => {
-        #[allow(unused_import)]
-        {
-            use core::fmt::Write as _;
-            let _ = writeln!(&mut $crate::PrintF, $fmt, $($arg)*);
-        }
+    ($($arg:tt)*) => {
+        $crate::_print(format_args!("{}\n", format_args!($($arg)*)))
     };
 }
 
+/// Print to stderr.
 #[macro_export]
-macro_rules! print {
-    ($fmt:expr) => {
-        #[allow(unused_import)]
-        {
-            use core::fmt::Write as _;
-            let _ = write!(&mut PrintF, $fmt);
-        }
-     };
-    ($fmt:expr, $($arg:tt)*) => {
-        #[allow(unused_import)]
-        {
-            use core::fmt::Write as _;
-            let _ = write!(&mut PrintF, $fmt, $($arg)*);
+macro_rules! eprint {
+    ($($arg:tt)*) => {
+        $crate::_eprint(format_args!($($arg)*))
+    };
+}
+
+/// Print to stderr, with a newline.
+#[macro_export]
+macro_rules! eprintln {
+    () => {
+        $crate::eprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::_eprint(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Print an expression and its value to stderr, and return the value, like
+/// `std::dbg!`.
+#[macro_export]
+macro_rules! dbg {
+    () => {
+        $crate::eprintln!("[{}:{}]", file!(), line!())
+    };
+    ($val:expr $(,)?) => {
+        match $val {
+            tmp => {
+                $crate::eprintln!("[{}:{}] {} = {:#?}", file!(), line!(), stringify!($val), &tmp);
+                tmp
+            }
         }
     };
+    ($($val:expr),+ $(,)?) => {
+        ($($crate::dbg!($val)),+,)
+    };
 }
 
+#[cfg(not(feature = "alloc_tracking"))]
 #[global_allocator]
 static ALLOC: esp_idf_alloc::EspIdfAllocator = esp_idf_alloc::EspIdfAllocator;
 
+#[cfg(feature = "alloc_tracking")]
+#[global_allocator]
+pub static ALLOC: alloc_tracker::TrackingAllocator<
+    esp_idf_alloc::EspIdfAllocator,
+    heap::FreeRtosPlatform,
+> = alloc_tracker::TrackingAllocator::new(esp_idf_alloc::EspIdfAllocator);
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    if let Some(s) = info.payload().downcast_ref::<&str>() {
-        println!("panic msg: {}", s);
-    }
-    if let Some(args) = info.message() {
-        println!("{}", args);
-    }
-    if let Some(location) = info.location() {
-        println!("panic location: {}:{}", location.file(), location.line(),);
-    }
-    unsafe { esp_idf_sys::abort() }
-    unreachable!("post-abort")
+    crash::panic(info)
 }
 
 #[alloc_error_handler]
-fn alloc_error(_layout: Layout) -> ! {
-    unsafe { esp_idf_sys::abort() }
-    unreachable!("post-abort")
+fn alloc_error(layout: Layout) -> ! {
+    crash::alloc_error(layout)
 }
@@ -0,0 +1,193 @@
+//! A fixed-size ring buffer capturing console output, so the most recent
+//! logs can be retrieved after the fact.
+//!
+//! Once [`install()`] has been called, everything printed with `println!`
+//! and everything ESP-IDF logs (including the `log` backend in
+//! [`logger`]) is copied into the buffer, and still goes to the console.
+//!
+//! With the `rtc_log_buffer` feature, the buffer lives in RTC slow memory
+//! that isn't initialized on boot, so its contents survive software resets
+//! such as the one after a panic. It's then smaller, as RTC slow memory only
+//! has 8 KiB. Power-on resets lose it either way.
+//!
+//! [`install()`]: fn.install.html
+//! [`logger`]: ../logger/index.html
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Size of the buffer, in bytes.
+#[cfg(feature = "rtc_log_buffer")]
+pub const CAPACITY: usize = 4 * 1024;
+#[cfg(not(feature = "rtc_log_buffer"))]
+pub const CAPACITY: usize = 16 * 1024;
+
+const MAGIC: u32 = 0x4c4f_4752;
+
+/// The buffer and its bookkeeping. Laid out so that its validity can be
+/// checked after a reset.
+#[repr(C)]
+pub struct Ring {
+    magic: u32,
+    /// Offset where the next byte is written.
+    head: u32,
+    len: u32,
+    /// `MAGIC ^ head ^ len`, updated with every write.
+    check: u32,
+    data: [u8; CAPACITY],
+}
+
+impl Ring {
+    pub const fn new() -> Self {
+        Ring {
+            magic: MAGIC,
+            head: 0,
+            len: 0,
+            check: MAGIC,
+            data: [0; CAPACITY],
+        }
+    }
+
+    /// Whether the bookkeeping is consistent, which is how retained contents
+    /// are told apart from uninitialized memory.
+    pub fn is_valid(&self) -> bool {
+        self.magic == MAGIC
+            && (self.head as usize) < CAPACITY
+            && (self.len as usize) <= CAPACITY
+            && self.check == MAGIC ^ self.head ^ self.len
+    }
+
+    pub fn clear(&mut self) {
+        self.magic = MAGIC;
+        self.head = 0;
+        self.len = 0;
+        self.check = MAGIC;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append `bytes`, overwriting the oldest contents once full.
+    pub fn write(&mut self, mut bytes: &[u8]) {
+        if bytes.len() > CAPACITY {
+            bytes = &bytes[bytes.len() - CAPACITY..];
+        }
+        let head = self.head as usize;
+        let first = bytes.len().min(CAPACITY - head);
+        self.data[head..head + first].copy_from_slice(&bytes[..first]);
+        self.data[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+
+        self.head = ((head + bytes.len()) % CAPACITY) as u32;
+        self.len = (self.len as usize + bytes.len()).min(CAPACITY) as u32;
+        self.check = MAGIC ^ self.head ^ self.len;
+    }
+
+    /// The contents, oldest first, as up to two slices.
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        let head = self.head as usize;
+        let len = self.len as usize;
+        if len <= head {
+            (&self.data[head - len..head], &[])
+        } else {
+            (&self.data[CAPACITY - (len - head)..], &self.data[..head])
+        }
+    }
+}
+
+#[cfg_attr(feature = "rtc_log_buffer", link_section = ".rtc_noinit")]
+static mut RING: Ring = Ring::new();
+
+static mut LOCK: esp_idf_sys::portMUX_TYPE = esp_idf_sys::portMUX_TYPE {
+    owner: esp_idf_sys::portMUX_FREE_VAL,
+    count: 0,
+};
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+static mut ORIGINAL_VPRINTF: esp_idf_sys::vprintf_like_t = None;
+
+fn with_ring<R>(f: impl FnOnce(&mut Ring) -> R) -> R {
+    unsafe {
+        esp_idf_sys::vTaskEnterCritical(&mut LOCK);
+        let r = f(&mut RING);
+        esp_idf_sys::vTaskExitCritical(&mut LOCK);
+        r
+    }
+}
+
+/// Start capturing output, returning how many bytes were retained from
+/// before the last reset. Retained contents are kept, followed by a marker
+/// line. Calling this again has no effect.
+pub fn install() -> usize {
+    if INSTALLED.swap(true, Ordering::AcqRel) {
+        return 0;
+    }
+
+    let retained = with_ring(|ring| {
+        if !ring.is_valid() {
+            ring.clear();
+        }
+        let retained = ring.len();
+        if retained > 0 {
+            ring.write(b"\n--- reset ---\n");
+        }
+        retained
+    });
+
+    unsafe { ORIGINAL_VPRINTF = esp_idf_sys::esp_log_set_vprintf(Some(capture_vprintf)) };
+    retained
+}
+
+/// Copy `bytes` into the buffer, if capturing.
+pub(crate) fn capture(bytes: &[u8]) {
+    if INSTALLED.load(Ordering::Acquire) {
+        with_ring(|ring| ring.write(bytes));
+    }
+}
+
+unsafe extern "C" fn capture_vprintf(
+    format: *const esp_idf_sys::types::c_char,
+    args: esp_idf_sys::va_list,
+) -> esp_idf_sys::types::c_int {
+    // `va_list` is passed by value, so each call consumes its own copy.
+    let mut line = [0u8; 256];
+    let n = esp_idf_sys::vsnprintf(line.as_mut_ptr() as *mut _, line.len() as _, format, args);
+    if n > 0 {
+        // `vsnprintf` returns the untruncated length.
+        capture(&line[..(n as usize).min(line.len() - 1)]);
+    }
+
+    match ORIGINAL_VPRINTF {
+        Some(original) => original(format, args),
+        None => esp_idf_sys::vprintf(format, args),
+    }
+}
+
+/// Copy out the buffer's contents, oldest first.
+pub fn contents() -> Vec<u8> {
+    // Allocate up front, so interrupts are only held off for the copy.
+    let mut out = Vec::with_capacity(CAPACITY);
+    with_ring(|ring| {
+        let (a, b) = ring.as_slices();
+        out.extend_from_slice(a);
+        out.extend_from_slice(b);
+    });
+    out
+}
+
+/// Print the buffer's contents to the console, without capturing them again.
+pub fn dump() {
+    let contents = contents();
+    let _lock = crate::lock();
+    let _ = crate::print::write_all(1, &contents);
+}
+
+/// Discard the buffer's contents.
+pub fn clear() {
+    with_ring(|ring| ring.clear());
+}
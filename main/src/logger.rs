@@ -0,0 +1,181 @@
+//! A `log` backend that writes through ESP-IDF's `esp_log_write`, so Rust
+//! and C log lines share one format and one set of per-tag levels.
+//!
+//! The tag of a line is its `log` target, which is the module path unless
+//! the call site sets one. Levels map as `Error`..`Debug` to their ESP-IDF
+//! namesakes and `Trace` to `Verbose`; change them at runtime with
+//! [`set_level()`], or cap them at compile time with this crate's
+//! `max_level_*` and `release_max_level_*` features.
+//!
+//! Records below their tag's level are dropped before they're formatted.
+//! The logger keeps its own copy of each tag's level for this, so levels
+//! changed from C with `esp_log_level_set` don't apply to Rust tags.
+//!
+//! [`set_level()`]: fn.set_level.html
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+use cstr_core::{CStr, CString};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// The logger. Install it with [`init()`].
+///
+/// [`init()`]: fn.init.html
+pub struct EspLogger;
+
+static LOGGER: EspLogger = EspLogger;
+
+/// Install the logger. Records are filtered by tag, so `log`'s own filter
+/// is left fully open.
+pub fn init() -> Result<(), log::SetLoggerError> {
+    log::set_logger(&LOGGER)?;
+    log::set_max_level(LevelFilter::Trace);
+    Ok(())
+}
+
+/// Set the level for `tag`, as `esp_log_level_set` does. A tag of `"*"` sets
+/// the level of every tag.
+pub fn set_level(tag: &str, level: LevelFilter) {
+    let level = to_esp_level(level);
+    if tag == "*" {
+        DEFAULT_LEVEL.store(level, Ordering::Relaxed);
+        let mut node = TAGS.load(Ordering::Acquire);
+        while !node.is_null() {
+            let tag = unsafe { &*node };
+            tag.level.store(level, Ordering::Relaxed);
+            node = tag.next;
+        }
+        unsafe { esp_idf_sys::esp_log_level_set(b"*\0".as_ptr() as *const _, level) };
+    } else if let Some(tag) = intern(tag) {
+        tag.level.store(level, Ordering::Relaxed);
+        unsafe { esp_idf_sys::esp_log_level_set(tag.name.as_ptr(), level) };
+    }
+}
+
+fn to_esp_level(level: LevelFilter) -> esp_idf_sys::esp_log_level_t {
+    match level {
+        LevelFilter::Off => esp_idf_sys::esp_log_level_t_ESP_LOG_NONE,
+        LevelFilter::Error => esp_idf_sys::esp_log_level_t_ESP_LOG_ERROR,
+        LevelFilter::Warn => esp_idf_sys::esp_log_level_t_ESP_LOG_WARN,
+        LevelFilter::Info => esp_idf_sys::esp_log_level_t_ESP_LOG_INFO,
+        LevelFilter::Debug => esp_idf_sys::esp_log_level_t_ESP_LOG_DEBUG,
+        LevelFilter::Trace => esp_idf_sys::esp_log_level_t_ESP_LOG_VERBOSE,
+    }
+}
+
+/// The letter and colour ESP-IDF uses for `level`.
+fn style(level: Level) -> (char, Option<&'static str>) {
+    match level {
+        Level::Error => ('E', Some("\x1b[0;31m")),
+        Level::Warn => ('W', Some("\x1b[0;33m")),
+        Level::Info => ('I', Some("\x1b[0;32m")),
+        Level::Debug => ('D', None),
+        Level::Trace => ('V', None),
+    }
+}
+
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// Interned, NUL-terminated tags. ESP-IDF caches tag levels by pointer, so
+/// each tag needs a stable address.
+struct Tag {
+    name: CString,
+    /// The `esp_log_level_t` last set for this tag.
+    level: AtomicU32,
+    next: *mut Tag,
+}
+
+static TAGS: AtomicPtr<Tag> = AtomicPtr::new(core::ptr::null_mut());
+
+/// The level of tags that haven't had one set.
+static DEFAULT_LEVEL: AtomicU32 = AtomicU32::new(esp_idf_sys::CONFIG_LOG_DEFAULT_LEVEL);
+
+fn intern(name: &str) -> Option<&'static Tag> {
+    let mut head = TAGS.load(Ordering::Acquire);
+    let mut node = head;
+    while !node.is_null() {
+        let tag = unsafe { &*node };
+        if tag.name.as_bytes() == name.as_bytes() {
+            return Some(tag);
+        }
+        node = tag.next;
+    }
+
+    let tag = Box::into_raw(Box::new(Tag {
+        name: CString::new(name).ok()?,
+        level: AtomicU32::new(DEFAULT_LEVEL.load(Ordering::Relaxed)),
+        next: head,
+    }));
+    loop {
+        match TAGS.compare_exchange_weak(head, tag, Ordering::AcqRel, Ordering::Acquire) {
+            // Interned tags are never freed.
+            Ok(_) => return Some(unsafe { &*tag }),
+            Err(current) => {
+                // A racing insert may duplicate the tag, which only costs an
+                // entry in ESP-IDF's cache.
+                head = current;
+                unsafe { (*tag).next = head };
+            }
+        }
+    }
+}
+
+/// The interned tag for `metadata`, if its level lets the record through.
+fn enabled_tag(metadata: &Metadata) -> Option<&'static CStr> {
+    let tag = intern(metadata.target())?;
+    let level = to_esp_level(metadata.level().to_level_filter());
+    if level <= tag.level.load(Ordering::Relaxed) {
+        Some(&tag.name)
+    } else {
+        None
+    }
+}
+
+impl Log for EspLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        enabled_tag(metadata).is_some()
+    }
+
+    fn log(&self, record: &Record) {
+        let tag = match enabled_tag(record.metadata()) {
+            Some(tag) => tag,
+            None => return,
+        };
+
+        let (letter, color) = style(record.level());
+        let color = color.filter(|_| esp_idf_sys::CONFIG_LOG_COLORS != 0);
+        let mut line = String::new();
+        if let Some(color) = color {
+            line.push_str(color);
+        }
+        let _ = write!(
+            line,
+            "{} ({}) {}: {}",
+            letter,
+            unsafe { esp_idf_sys::esp_log_timestamp() },
+            record.target(),
+            record.args()
+        );
+        if color.is_some() {
+            line.push_str(RESET_COLOR);
+        }
+        line.push('\n');
+
+        let line = match CString::new(line) {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        unsafe {
+            esp_idf_sys::esp_log_write(
+                to_esp_level(record.level().to_level_filter()),
+                tag.as_ptr(),
+                b"%s\0".as_ptr() as *const _,
+                line.as_ptr(),
+            )
+        };
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,324 @@
+//! A Modbus RTU master, for polling sensors on an RS-485 bus.
+//!
+//! ```ignore
+//! let uart = Uart::new(Port::Uart1)
+//!     .baud_rate(9600)
+//!     .pins(17, 16)
+//!     .rts_pin(4)
+//!     .mode(Mode::Rs485HalfDuplex)
+//!     .start()?;
+//! let mut modbus = Master::new(uart)?;
+//! let mut registers = [0; 2];
+//! modbus.read_holding_registers(1, 0x0000, &mut registers)?;
+//! ```
+//!
+//! The framing is in [`modbus_rtu`].
+//!
+//! [`modbus_rtu`]: ../modbus_rtu/index.html
+
+use esp_idf_hal::errors::EspError;
+
+use crate::freertos_units::Duration;
+use crate::modbus_rtu::{
+    self, Exception, FrameError, Request, Response, BROADCAST, EXCEPTION_FRAME_LEN, MAX_FRAME,
+};
+use crate::uart::{Mode, Transmission, Uart};
+
+/// Slack for reads and writes whose length is known, on top of the time
+/// the bytes take on the wire.
+const MARGIN_MS: u32 = 10;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    Uart(EspError),
+    /// The slave didn't start responding within the response timeout.
+    Timeout,
+    /// Another node transmitted at the same time as the request.
+    Collision,
+    /// A read was sent to `BROADCAST`, which slaves never respond to.
+    BroadcastRead,
+    Frame(FrameError),
+    /// The slave refused the request.
+    Exception(Exception),
+}
+
+impl Error {
+    /// Whether resending the request might help.
+    fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::Timeout
+                | Error::Collision
+                | Error::Frame(FrameError::Truncated)
+                | Error::Frame(FrameError::BadCrc)
+                | Error::Frame(FrameError::Mismatch)
+        )
+    }
+}
+
+impl From<EspError> for Error {
+    fn from(e: EspError) -> Self {
+        Error::Uart(e)
+    }
+}
+
+impl From<FrameError> for Error {
+    fn from(e: FrameError) -> Self {
+        Error::Frame(e)
+    }
+}
+
+/// Sends requests to slaves over a UART, one at a time. Unit `BROADCAST`
+/// addresses every slave; writes to it don't wait for a response, and
+/// reads from it fail with `Error::BroadcastRead` without being sent.
+pub struct Master {
+    uart: Uart,
+    response_timeout: Duration,
+    retries: u8,
+    char_time_us: u32,
+    frame_gap_us: u32,
+    /// When the bus was last busy, from `esp_timer_get_time`.
+    last_activity_us: i64,
+}
+
+impl Master {
+    /// Talk over `uart`, at its current baud rate.
+    pub fn new(uart: Uart) -> Result<Master, EspError> {
+        let baud_rate = uart.baud_rate()?;
+        Ok(Master {
+            uart,
+            response_timeout: Duration::ms(1000),
+            retries: 2,
+            char_time_us: modbus_rtu::char_time_us(baud_rate),
+            frame_gap_us: modbus_rtu::frame_gap_us(baud_rate),
+            last_activity_us: 0,
+        })
+    }
+
+    /// How long to wait for a slave to start responding. Defaults to one
+    /// second.
+    pub fn set_response_timeout(&mut self, timeout: Duration) {
+        self.response_timeout = timeout;
+    }
+
+    /// How many times to resend a request that got no response, or a
+    /// corrupted one. Exceptions aren't retried. Defaults to two.
+    pub fn set_retries(&mut self, retries: u8) {
+        self.retries = retries;
+    }
+
+    pub fn into_inner(self) -> Uart {
+        self.uart
+    }
+
+    pub fn read_coils(&mut self, unit: u8, address: u16, out: &mut [bool]) -> Result<(), Error> {
+        let request = Request::ReadCoils {
+            address,
+            count: quantity(out.len())?,
+        };
+        let mut frame = [0; MAX_FRAME];
+        if let Response::Bits(bytes) = self.transaction(unit, &request, &mut frame)? {
+            modbus_rtu::unpack_bits(bytes, out);
+        }
+        Ok(())
+    }
+
+    pub fn read_discrete_inputs(
+        &mut self,
+        unit: u8,
+        address: u16,
+        out: &mut [bool],
+    ) -> Result<(), Error> {
+        let request = Request::ReadDiscreteInputs {
+            address,
+            count: quantity(out.len())?,
+        };
+        let mut frame = [0; MAX_FRAME];
+        if let Response::Bits(bytes) = self.transaction(unit, &request, &mut frame)? {
+            modbus_rtu::unpack_bits(bytes, out);
+        }
+        Ok(())
+    }
+
+    pub fn read_holding_registers(
+        &mut self,
+        unit: u8,
+        address: u16,
+        out: &mut [u16],
+    ) -> Result<(), Error> {
+        let request = Request::ReadHoldingRegisters {
+            address,
+            count: quantity(out.len())?,
+        };
+        let mut frame = [0; MAX_FRAME];
+        if let Response::Registers(bytes) = self.transaction(unit, &request, &mut frame)? {
+            modbus_rtu::unpack_registers(bytes, out);
+        }
+        Ok(())
+    }
+
+    pub fn read_input_registers(
+        &mut self,
+        unit: u8,
+        address: u16,
+        out: &mut [u16],
+    ) -> Result<(), Error> {
+        let request = Request::ReadInputRegisters {
+            address,
+            count: quantity(out.len())?,
+        };
+        let mut frame = [0; MAX_FRAME];
+        if let Response::Registers(bytes) = self.transaction(unit, &request, &mut frame)? {
+            modbus_rtu::unpack_registers(bytes, out);
+        }
+        Ok(())
+    }
+
+    pub fn write_single_coil(&mut self, unit: u8, address: u16, value: bool) -> Result<(), Error> {
+        let request = Request::WriteSingleCoil { address, value };
+        self.transaction(unit, &request, &mut [0; MAX_FRAME])?;
+        Ok(())
+    }
+
+    pub fn write_single_register(
+        &mut self,
+        unit: u8,
+        address: u16,
+        value: u16,
+    ) -> Result<(), Error> {
+        let request = Request::WriteSingleRegister { address, value };
+        self.transaction(unit, &request, &mut [0; MAX_FRAME])?;
+        Ok(())
+    }
+
+    pub fn write_multiple_coils(
+        &mut self,
+        unit: u8,
+        address: u16,
+        values: &[bool],
+    ) -> Result<(), Error> {
+        let request = Request::WriteMultipleCoils { address, values };
+        self.transaction(unit, &request, &mut [0; MAX_FRAME])?;
+        Ok(())
+    }
+
+    pub fn write_multiple_registers(
+        &mut self,
+        unit: u8,
+        address: u16,
+        values: &[u16],
+    ) -> Result<(), Error> {
+        let request = Request::WriteMultipleRegisters { address, values };
+        self.transaction(unit, &request, &mut [0; MAX_FRAME])?;
+        Ok(())
+    }
+
+    /// Send `request`, retrying as configured, and decode the response.
+    fn transaction<'f>(
+        &mut self,
+        unit: u8,
+        request: &Request<'_>,
+        frame: &'f mut [u8; MAX_FRAME],
+    ) -> Result<Response<'f>, Error> {
+        let mut attempts = 0;
+        let len = loop {
+            match self.exchange(unit, request, frame) {
+                Ok(len) => break len,
+                Err(e) if e.is_transient() && attempts < self.retries => attempts += 1,
+                Err(e) => return Err(e),
+            }
+        };
+        if len == 0 {
+            // A broadcast write.
+            return Ok(Response::Written);
+        }
+        match request.decode_response(unit, &frame[..len])? {
+            Response::Exception(e) => Err(Error::Exception(e)),
+            response => Ok(response),
+        }
+    }
+
+    /// Send `request` once and receive the response into `frame`. Returns
+    /// the response's length, once it's been checked.
+    fn exchange(
+        &mut self,
+        unit: u8,
+        request: &Request<'_>,
+        frame: &mut [u8; MAX_FRAME],
+    ) -> Result<usize, Error> {
+        if unit == BROADCAST && !request.is_write() {
+            return Err(Error::BroadcastRead);
+        }
+        let len = request.encode(unit, frame)?;
+        self.wait_for_silence();
+        self.uart.clear_input()?;
+        let sent = self
+            .uart
+            .transmit(&frame[..len], self.transfer_timeout(len))?;
+        if self.uart.mode() == Mode::Rs485CollisionDetect {
+            // The receiver was on, so the request was echoed.
+            self.uart
+                .read_timeout(&mut frame[..len], self.transfer_timeout(len))?;
+        }
+        self.mark_activity();
+        if sent == Transmission::Collided {
+            return Err(Error::Collision);
+        }
+        if unit == BROADCAST {
+            return Ok(0);
+        }
+
+        // Every response is at least as long as an exception, and its
+        // function code tells which it is.
+        let mut received = self
+            .uart
+            .read_timeout(&mut frame[..EXCEPTION_FRAME_LEN], self.response_timeout)?;
+        if received == EXCEPTION_FRAME_LEN && frame[1] == request.function() {
+            let expected = request.response_len();
+            let timeout = self.transfer_timeout(expected - received);
+            received += self
+                .uart
+                .read_timeout(&mut frame[received..expected], timeout)?;
+        }
+        self.mark_activity();
+
+        match received {
+            0 => Err(Error::Timeout),
+            _ => {
+                request.decode_response(unit, &frame[..received])?;
+                Ok(received)
+            }
+        }
+    }
+
+    /// How long `len` bytes take to send, plus a margin.
+    fn transfer_timeout(&self, len: usize) -> Duration {
+        Duration::ms(len as u32 * self.char_time_us / 1000 + MARGIN_MS)
+    }
+
+    /// Wait until the bus has been quiet for a frame gap, so the next
+    /// request isn't taken as part of the last frame. The gap is at most a
+    /// few milliseconds at usual baud rates, shorter than a tick, so this
+    /// busy-waits.
+    fn wait_for_silence(&self) {
+        let quiet = unsafe { esp_idf_sys::esp_timer_get_time() } - self.last_activity_us;
+        let gap = self.frame_gap_us as i64;
+        if quiet < gap {
+            unsafe { esp_idf_sys::ets_delay_us((gap - quiet) as u32) };
+        }
+    }
+
+    fn mark_activity(&mut self) {
+        self.last_activity_us = unsafe { esp_idf_sys::esp_timer_get_time() };
+    }
+}
+
+/// The number of coils or registers in `len` values, if it fits in a
+/// request's quantity field. `Request` checks the protocol's limits.
+fn quantity(len: usize) -> Result<u16, Error> {
+    if len > u16::max_value() as usize {
+        Err(Error::Frame(FrameError::InvalidQuantity))
+    } else {
+        Ok(len as u16)
+    }
+}
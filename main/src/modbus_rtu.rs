@@ -0,0 +1,406 @@
+//! Modbus RTU framing: building requests, checking and decoding responses,
+//! and the CRC and timings they depend on. [`modbus`] sends them over a
+//! UART.
+//!
+//! A frame is the unit (slave) address, a function code, the function's
+//! data, and a CRC-16, low byte first. Frames are separated by at least
+//! 3.5 character times of silence.
+//!
+//! This module is plain Rust and doesn't touch `esp-idf`, so it can be
+//! exercised on the host; see `host-tests`.
+//!
+//! [`modbus`]: ../modbus/index.html
+
+use core::fmt;
+
+/// Longest frame the protocol allows.
+pub const MAX_FRAME: usize = 256;
+
+/// An exception response: unit, function, exception code and CRC. Every
+/// other response is longer.
+pub const EXCEPTION_FRAME_LEN: usize = 5;
+
+/// Unit address that every slave accepts writes from without responding.
+pub const BROADCAST: u8 = 0;
+
+pub const READ_COILS: u8 = 0x01;
+pub const READ_DISCRETE_INPUTS: u8 = 0x02;
+pub const READ_HOLDING_REGISTERS: u8 = 0x03;
+pub const READ_INPUT_REGISTERS: u8 = 0x04;
+pub const WRITE_SINGLE_COIL: u8 = 0x05;
+pub const WRITE_SINGLE_REGISTER: u8 = 0x06;
+pub const WRITE_MULTIPLE_COILS: u8 = 0x0f;
+pub const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+/// Set in the function code of exception responses.
+const EXCEPTION_FLAG: u8 = 0x80;
+
+/// Bits per character: start, eight data, parity or a second stop, and
+/// stop.
+const BITS_PER_CHAR: u32 = 11;
+
+/// The Modbus CRC-16: polynomial 0xA001 (reflected 0x8005), starting from
+/// 0xFFFF.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffff;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// How long one character takes to send at `baud`, in microseconds,
+/// rounded up.
+pub fn char_time_us(baud: u32) -> u32 {
+    (BITS_PER_CHAR * 1_000_000 + baud - 1) / baud
+}
+
+/// The silence that ends a frame, 3.5 character times, in microseconds.
+/// Above 19200 baud it's fixed at 1750 µs, as the specification
+/// recommends.
+pub fn frame_gap_us(baud: u32) -> u32 {
+    if baud > 19200 {
+        1750
+    } else {
+        (BITS_PER_CHAR * 3_500_000 + baud - 1) / baud
+    }
+}
+
+/// Reasons a request couldn't be built, or a response couldn't be used.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// The quantity of coils or registers is zero, larger than fits in a
+    /// frame, or runs past address 0xFFFF.
+    InvalidQuantity,
+    /// The output buffer is too small for the frame.
+    BufferTooSmall,
+    /// The response is shorter than an exception response.
+    Truncated,
+    BadCrc,
+    /// The response is from another unit, for another function, or
+    /// doesn't have the length or contents the request calls for.
+    Mismatch,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FrameError::InvalidQuantity => "invalid quantity",
+            FrameError::BufferTooSmall => "buffer too small",
+            FrameError::Truncated => "truncated frame",
+            FrameError::BadCrc => "bad CRC",
+            FrameError::Mismatch => "response doesn't match request",
+        })
+    }
+}
+
+/// Why a slave refused a request.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum Exception {
+    IllegalFunction,
+    IllegalDataAddress,
+    IllegalDataValue,
+    ServerDeviceFailure,
+    /// The request was accepted but will take a while; poll for the result.
+    Acknowledge,
+    ServerDeviceBusy,
+    MemoryParityError,
+    GatewayPathUnavailable,
+    GatewayTargetFailedToRespond,
+    Other(u8),
+}
+
+impl Exception {
+    pub fn from_code(code: u8) -> Exception {
+        match code {
+            0x01 => Exception::IllegalFunction,
+            0x02 => Exception::IllegalDataAddress,
+            0x03 => Exception::IllegalDataValue,
+            0x04 => Exception::ServerDeviceFailure,
+            0x05 => Exception::Acknowledge,
+            0x06 => Exception::ServerDeviceBusy,
+            0x08 => Exception::MemoryParityError,
+            0x0a => Exception::GatewayPathUnavailable,
+            0x0b => Exception::GatewayTargetFailedToRespond,
+            other => Exception::Other(other),
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            Exception::IllegalFunction => 0x01,
+            Exception::IllegalDataAddress => 0x02,
+            Exception::IllegalDataValue => 0x03,
+            Exception::ServerDeviceFailure => 0x04,
+            Exception::Acknowledge => 0x05,
+            Exception::ServerDeviceBusy => 0x06,
+            Exception::MemoryParityError => 0x08,
+            Exception::GatewayPathUnavailable => 0x0a,
+            Exception::GatewayTargetFailedToRespond => 0x0b,
+            Exception::Other(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exception::IllegalFunction => f.write_str("illegal function"),
+            Exception::IllegalDataAddress => f.write_str("illegal data address"),
+            Exception::IllegalDataValue => f.write_str("illegal data value"),
+            Exception::ServerDeviceFailure => f.write_str("server device failure"),
+            Exception::Acknowledge => f.write_str("acknowledge"),
+            Exception::ServerDeviceBusy => f.write_str("server device busy"),
+            Exception::MemoryParityError => f.write_str("memory parity error"),
+            Exception::GatewayPathUnavailable => f.write_str("gateway path unavailable"),
+            Exception::GatewayTargetFailedToRespond => {
+                f.write_str("gateway target failed to respond")
+            }
+            Exception::Other(code) => write!(f, "exception {:#04x}", code),
+        }
+    }
+}
+
+/// A request from the master. Addresses are zero-based protocol addresses,
+/// not the one-based "40001"-style register numbers of data sheets.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum Request<'a> {
+    ReadCoils { address: u16, count: u16 },
+    ReadDiscreteInputs { address: u16, count: u16 },
+    ReadHoldingRegisters { address: u16, count: u16 },
+    ReadInputRegisters { address: u16, count: u16 },
+    WriteSingleCoil { address: u16, value: bool },
+    WriteSingleRegister { address: u16, value: u16 },
+    WriteMultipleCoils { address: u16, values: &'a [bool] },
+    WriteMultipleRegisters { address: u16, values: &'a [u16] },
+}
+
+impl<'a> Request<'a> {
+    pub fn function(&self) -> u8 {
+        match self {
+            Request::ReadCoils { .. } => READ_COILS,
+            Request::ReadDiscreteInputs { .. } => READ_DISCRETE_INPUTS,
+            Request::ReadHoldingRegisters { .. } => READ_HOLDING_REGISTERS,
+            Request::ReadInputRegisters { .. } => READ_INPUT_REGISTERS,
+            Request::WriteSingleCoil { .. } => WRITE_SINGLE_COIL,
+            Request::WriteSingleRegister { .. } => WRITE_SINGLE_REGISTER,
+            Request::WriteMultipleCoils { .. } => WRITE_MULTIPLE_COILS,
+            Request::WriteMultipleRegisters { .. } => WRITE_MULTIPLE_REGISTERS,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Request::WriteSingleCoil { .. }
+                | Request::WriteSingleRegister { .. }
+                | Request::WriteMultipleCoils { .. }
+                | Request::WriteMultipleRegisters { .. }
+        )
+    }
+
+    /// The address and quantity of a request that has them, with the
+    /// protocol's limit on the quantity.
+    fn range(&self) -> Option<(u16, usize, usize)> {
+        match *self {
+            Request::ReadCoils { address, count }
+            | Request::ReadDiscreteInputs { address, count } => {
+                Some((address, count as usize, 2000))
+            }
+            Request::ReadHoldingRegisters { address, count }
+            | Request::ReadInputRegisters { address, count } => {
+                Some((address, count as usize, 125))
+            }
+            Request::WriteMultipleCoils { address, values } => Some((address, values.len(), 1968)),
+            Request::WriteMultipleRegisters { address, values } => {
+                Some((address, values.len(), 123))
+            }
+            Request::WriteSingleCoil { .. } | Request::WriteSingleRegister { .. } => None,
+        }
+    }
+
+    fn validate(&self) -> Result<(), FrameError> {
+        match self.range() {
+            Some((address, count, max))
+                if count == 0 || count > max || address as usize + count > 0x1_0000 =>
+            {
+                Err(FrameError::InvalidQuantity)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Length of the request's frame.
+    pub fn frame_len(&self) -> usize {
+        match self {
+            Request::WriteMultipleCoils { values, .. } => 9 + (values.len() + 7) / 8,
+            Request::WriteMultipleRegisters { values, .. } => 9 + 2 * values.len(),
+            _ => 8,
+        }
+    }
+
+    /// Length of the frame of a successful response. Exception responses
+    /// are `EXCEPTION_FRAME_LEN` long.
+    pub fn response_len(&self) -> usize {
+        match *self {
+            Request::ReadCoils { count, .. } | Request::ReadDiscreteInputs { count, .. } => {
+                5 + (count as usize + 7) / 8
+            }
+            Request::ReadHoldingRegisters { count, .. }
+            | Request::ReadInputRegisters { count, .. } => 5 + 2 * count as usize,
+            _ => 8,
+        }
+    }
+
+    /// Write the frame for sending this to `unit` into `buf`, returning its
+    /// length.
+    pub fn encode(&self, unit: u8, buf: &mut [u8]) -> Result<usize, FrameError> {
+        self.validate()?;
+        let len = self.frame_len();
+        if buf.len() < len {
+            return Err(FrameError::BufferTooSmall);
+        }
+
+        buf[0] = unit;
+        buf[1] = self.function();
+        let (address, word) = match *self {
+            Request::ReadCoils { address, count }
+            | Request::ReadDiscreteInputs { address, count }
+            | Request::ReadHoldingRegisters { address, count }
+            | Request::ReadInputRegisters { address, count } => (address, count),
+            Request::WriteSingleCoil { address, value } => {
+                (address, if value { 0xff00 } else { 0x0000 })
+            }
+            Request::WriteSingleRegister { address, value } => (address, value),
+            Request::WriteMultipleCoils { address, values } => (address, values.len() as u16),
+            Request::WriteMultipleRegisters { address, values } => (address, values.len() as u16),
+        };
+        buf[2..4].copy_from_slice(&address.to_be_bytes());
+        buf[4..6].copy_from_slice(&word.to_be_bytes());
+
+        match *self {
+            Request::WriteMultipleCoils { values, .. } => {
+                buf[6] = (len - 9) as u8;
+                pack_bits(values, &mut buf[7..len - 2]);
+            }
+            Request::WriteMultipleRegisters { values, .. } => {
+                buf[6] = (2 * values.len()) as u8;
+                for (chunk, value) in buf[7..len - 2].chunks_mut(2).zip(values) {
+                    chunk.copy_from_slice(&value.to_be_bytes());
+                }
+            }
+            _ => {}
+        }
+
+        let crc = crc16(&buf[..len - 2]);
+        buf[len - 2..len].copy_from_slice(&crc.to_le_bytes());
+        Ok(len)
+    }
+
+    /// Check that `frame` is `unit`'s response to this request, and get
+    /// what it holds. `frame` must be exactly one frame.
+    pub fn decode_response<'f>(
+        &self,
+        unit: u8,
+        frame: &'f [u8],
+    ) -> Result<Response<'f>, FrameError> {
+        if frame.len() < EXCEPTION_FRAME_LEN {
+            return Err(FrameError::Truncated);
+        }
+        let (body, crc) = frame.split_at(frame.len() - 2);
+        if crc16(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+            return Err(FrameError::BadCrc);
+        }
+        if body[0] != unit {
+            return Err(FrameError::Mismatch);
+        }
+        if body[1] == self.function() | EXCEPTION_FLAG && frame.len() == EXCEPTION_FRAME_LEN {
+            return Ok(Response::Exception(Exception::from_code(body[2])));
+        }
+        if body[1] != self.function() || frame.len() != self.response_len() {
+            return Err(FrameError::Mismatch);
+        }
+
+        match *self {
+            Request::ReadCoils { .. } | Request::ReadDiscreteInputs { .. } => {
+                check_byte_count(body)?;
+                Ok(Response::Bits(&body[3..]))
+            }
+            Request::ReadHoldingRegisters { .. } | Request::ReadInputRegisters { .. } => {
+                check_byte_count(body)?;
+                Ok(Response::Registers(&body[3..]))
+            }
+            _ => {
+                // Writes are acknowledged by echoing the address and the
+                // value or quantity, which `encode` puts in the same place.
+                let mut request = [0; MAX_FRAME];
+                self.encode(unit, &mut request)?;
+                if body[2..6] == request[2..6] {
+                    Ok(Response::Written)
+                } else {
+                    Err(FrameError::Mismatch)
+                }
+            }
+        }
+    }
+}
+
+/// The byte count of a read response must cover the rest of the body.
+fn check_byte_count(body: &[u8]) -> Result<(), FrameError> {
+    if body[2] as usize == body.len() - 3 {
+        Ok(())
+    } else {
+        Err(FrameError::Mismatch)
+    }
+}
+
+/// What a successful, or refused, request returned.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum Response<'a> {
+    /// Coil or discrete input states, eight to a byte, least significant
+    /// bit first. See [`unpack_bits()`].
+    ///
+    /// [`unpack_bits()`]: fn.unpack_bits.html
+    Bits(&'a [u8]),
+    /// Register values, big-endian. See [`unpack_registers()`].
+    ///
+    /// [`unpack_registers()`]: fn.unpack_registers.html
+    Registers(&'a [u8]),
+    /// The write was acknowledged.
+    Written,
+    Exception(Exception),
+}
+
+/// Pack `bits` eight to a byte, least significant bit first, into
+/// `(bits.len() + 7) / 8` bytes of `out`.
+pub fn pack_bits(bits: &[bool], out: &mut [u8]) {
+    for byte in out.iter_mut().take((bits.len() + 7) / 8) {
+        *byte = 0;
+    }
+    for (i, &bit) in bits.iter().enumerate() {
+        if bit {
+            out[i / 8] |= 1 << (i % 8);
+        }
+    }
+}
+
+/// Fill `out` from the packed bits of a `Response::Bits`.
+pub fn unpack_bits(bytes: &[u8], out: &mut [bool]) {
+    for (i, bit) in out.iter_mut().enumerate() {
+        *bit = bytes[i / 8] & (1 << (i % 8)) != 0;
+    }
+}
+
+/// Fill `out` from the big-endian values of a `Response::Registers`.
+pub fn unpack_registers(bytes: &[u8], out: &mut [u16]) {
+    for (value, chunk) in out.iter_mut().zip(bytes.chunks(2)) {
+        *value = u16::from_be_bytes([chunk[0], chunk[1]]);
+    }
+}
@@ -0,0 +1,237 @@
+//! Minimal network address types, modelled after `core::net`.
+//!
+//! This module is plain Rust and doesn't touch `esp-idf`, so the parsers
+//! built on it can be exercised on the host. Conversions to lwIP's types
+//! live in `netif`.
+
+use core::fmt;
+
+/// An IPv4 address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Ipv4Addr {
+    octets: [u8; 4],
+}
+
+impl Ipv4Addr {
+    /// The unspecified address, `0.0.0.0`.
+    pub const UNSPECIFIED: Ipv4Addr = Ipv4Addr::new(0, 0, 0, 0);
+    /// The broadcast address, `255.255.255.255`.
+    pub const BROADCAST: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 255);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Ipv4Addr {
+            octets: [a, b, c, d],
+        }
+    }
+
+    pub const fn octets(&self) -> [u8; 4] {
+        self.octets
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    /// Parse a dotted-quad address, e.g. `192.168.4.1`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut octets = [0u8; 4];
+        let mut parts = s.split('.');
+        for octet in octets.iter_mut() {
+            let part = parts.next()?;
+            if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            *octet = part.parse().ok()?;
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Ipv4Addr { octets })
+    }
+}
+
+impl From<[u8; 4]> for Ipv4Addr {
+    fn from(octets: [u8; 4]) -> Self {
+        Ipv4Addr { octets }
+    }
+}
+
+impl From<Ipv4Addr> for [u8; 4] {
+    fn from(addr: Ipv4Addr) -> Self {
+        addr.octets
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d] = self.octets;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+/// An IPv4 socket address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SocketAddrV4 {
+    ip: Ipv4Addr,
+    port: u16,
+}
+
+impl SocketAddrV4 {
+    pub const fn new(ip: Ipv4Addr, port: u16) -> Self {
+        SocketAddrV4 { ip, port }
+    }
+
+    pub const fn ip(&self) -> Ipv4Addr {
+        self.ip
+    }
+
+    pub const fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl fmt::Display for SocketAddrV4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.ip, self.port)
+    }
+}
+
+/// An IPv6 address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Ipv6Addr {
+    octets: [u8; 16],
+}
+
+impl Ipv6Addr {
+    /// The unspecified address, `::`.
+    pub const UNSPECIFIED: Ipv6Addr = Ipv6Addr { octets: [0; 16] };
+
+    pub const fn from_octets(octets: [u8; 16]) -> Self {
+        Ipv6Addr { octets }
+    }
+
+    pub const fn octets(&self) -> [u8; 16] {
+        self.octets
+    }
+
+    /// The address as eight big-endian 16-bit segments.
+    pub fn segments(&self) -> [u16; 8] {
+        let mut segments = [0u16; 8];
+        for (i, segment) in segments.iter_mut().enumerate() {
+            *segment = u16::from_be_bytes([self.octets[2 * i], self.octets[2 * i + 1]]);
+        }
+        segments
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+}
+
+impl From<[u8; 16]> for Ipv6Addr {
+    fn from(octets: [u8; 16]) -> Self {
+        Ipv6Addr { octets }
+    }
+}
+
+impl fmt::Display for Ipv6Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let segments = self.segments();
+
+        // Find the longest run of zero segments to elide with `::`.
+        let (mut best_start, mut best_len) = (0, 0);
+        let (mut cur_start, mut cur_len) = (0, 0);
+        for (i, s) in segments.iter().enumerate() {
+            if *s == 0 {
+                if cur_len == 0 {
+                    cur_start = i;
+                }
+                cur_len += 1;
+                if cur_len > best_len {
+                    best_start = cur_start;
+                    best_len = cur_len;
+                }
+            } else {
+                cur_len = 0;
+            }
+        }
+
+        if best_len < 2 {
+            for (i, s) in segments.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(":")?;
+                }
+                write!(f, "{:x}", s)?;
+            }
+            return Ok(());
+        }
+
+        for (i, s) in segments[..best_start].iter().enumerate() {
+            if i != 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:x}", s)?;
+        }
+        f.write_str("::")?;
+        for (i, s) in segments[best_start + best_len..].iter().enumerate() {
+            if i != 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:x}", s)?;
+        }
+        Ok(())
+    }
+}
+
+/// A 48-bit IEEE 802 MAC address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xff; 6]);
+
+    pub const fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+
+    /// Read an address from the first six bytes of `bytes`.
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 6 {
+            return None;
+        }
+        let mut octets = [0u8; 6];
+        octets.copy_from_slice(&bytes[..6]);
+        Some(MacAddress(octets))
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    /// Whether the address is locally administered rather than assigned by
+    /// the manufacturer.
+    pub fn is_local(&self) -> bool {
+        self.0[0] & 0x02 != 0
+    }
+}
+
+impl From<[u8; 6]> for MacAddress {
+    fn from(octets: [u8; 6]) -> Self {
+        MacAddress(octets)
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
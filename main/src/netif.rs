@@ -0,0 +1,278 @@
+use cstr_core::{CStr, CString};
+use esp_idf_hal::errors::EspError;
+use esp_idf_sys::tcpip_adapter_if_t;
+
+use crate::net::{Ipv4Addr, Ipv6Addr};
+
+/// Convert from lwIP's representation, which stores the address in network
+/// byte order.
+fn ipv4_from_lwip(addr: esp_idf_sys::ip4_addr_t) -> Ipv4Addr {
+    Ipv4Addr::from(addr.addr.to_ne_bytes())
+}
+
+fn ipv4_to_lwip(addr: Ipv4Addr) -> esp_idf_sys::ip4_addr_t {
+    esp_idf_sys::ip4_addr_t {
+        addr: u32::from_ne_bytes(addr.octets()),
+    }
+}
+
+/// Convert from lwIP's representation, which stores each 32-bit word in
+/// network byte order. The zone index is discarded.
+fn ipv6_from_lwip(addr: &esp_idf_sys::ip6_addr_t) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    for (chunk, word) in octets.chunks_mut(4).zip(addr.addr.iter()) {
+        chunk.copy_from_slice(&word.to_ne_bytes());
+    }
+    Ipv6Addr::from(octets)
+}
+
+/// Network interfaces managed by the `tcpip_adapter`.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum NetworkInterface {
+    /// Wi-Fi station.
+    Sta,
+    /// Wi-Fi soft access point.
+    Ap,
+    /// Ethernet.
+    Eth,
+}
+
+/// IPv4 configuration of an interface.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub struct IpInfo {
+    pub ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gw: Ipv4Addr,
+}
+
+impl IpInfo {
+    fn from_raw(raw: &esp_idf_sys::tcpip_adapter_ip_info_t) -> Self {
+        IpInfo {
+            ip: ipv4_from_lwip(raw.ip),
+            netmask: ipv4_from_lwip(raw.netmask),
+            gw: ipv4_from_lwip(raw.gw),
+        }
+    }
+
+    fn to_raw(self) -> esp_idf_sys::tcpip_adapter_ip_info_t {
+        esp_idf_sys::tcpip_adapter_ip_info_t {
+            ip: ipv4_to_lwip(self.ip),
+            netmask: ipv4_to_lwip(self.netmask),
+            gw: ipv4_to_lwip(self.gw),
+        }
+    }
+}
+
+/// State of a DHCP client or server.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum DhcpStatus {
+    /// Not yet started.
+    Init,
+    Started,
+    Stopped,
+}
+
+/// Which of the interface's DNS servers to address.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum DnsServer {
+    Main,
+    Backup,
+    Fallback,
+}
+
+impl DnsServer {
+    fn to_raw(self) -> esp_idf_sys::tcpip_adapter_dns_type_t {
+        match self {
+            DnsServer::Main => esp_idf_sys::tcpip_adapter_dns_type_t_TCPIP_ADAPTER_DNS_MAIN,
+            DnsServer::Backup => esp_idf_sys::tcpip_adapter_dns_type_t_TCPIP_ADAPTER_DNS_BACKUP,
+            DnsServer::Fallback => esp_idf_sys::tcpip_adapter_dns_type_t_TCPIP_ADAPTER_DNS_FALLBACK,
+        }
+    }
+}
+
+impl NetworkInterface {
+    fn as_raw(self) -> tcpip_adapter_if_t {
+        match self {
+            NetworkInterface::Sta => esp_idf_sys::tcpip_adapter_if_t_TCPIP_ADAPTER_IF_STA,
+            NetworkInterface::Ap => esp_idf_sys::tcpip_adapter_if_t_TCPIP_ADAPTER_IF_AP,
+            NetworkInterface::Eth => esp_idf_sys::tcpip_adapter_if_t_TCPIP_ADAPTER_IF_ETH,
+        }
+    }
+
+    /// Whether the interface is currently up.
+    pub fn is_up(self) -> bool {
+        unsafe { esp_idf_sys::tcpip_adapter_is_netif_up(self.as_raw()) }
+    }
+
+    /// Get the interface's current IPv4 configuration.
+    pub fn ip_info(self) -> Result<IpInfo, EspError> {
+        let mut raw: esp_idf_sys::tcpip_adapter_ip_info_t = unsafe { core::mem::zeroed() };
+        EspError(unsafe { esp_idf_sys::tcpip_adapter_get_ip_info(self.as_raw(), &mut raw) })
+            .into_result()?;
+        Ok(IpInfo::from_raw(&raw))
+    }
+
+    /// Assign a static IPv4 configuration.
+    ///
+    /// The DHCP client (or server, for the soft AP) must be stopped for this to
+    /// take effect, so it is stopped first if it's running.
+    pub fn set_static(self, ip: Ipv4Addr, netmask: Ipv4Addr, gw: Ipv4Addr) -> Result<(), EspError> {
+        match self {
+            NetworkInterface::Ap => self.stop_dhcp_server()?,
+            NetworkInterface::Sta | NetworkInterface::Eth => self.stop_dhcp_client()?,
+        }
+        let raw = IpInfo { ip, netmask, gw }.to_raw();
+        EspError(unsafe { esp_idf_sys::tcpip_adapter_set_ip_info(self.as_raw(), &raw) })
+            .into_result()
+    }
+
+    /// Get the address of one of the interface's DNS servers.
+    pub fn dns(self, server: DnsServer) -> Result<Ipv4Addr, EspError> {
+        let mut raw: esp_idf_sys::tcpip_adapter_dns_info_t = unsafe { core::mem::zeroed() };
+        EspError(unsafe {
+            esp_idf_sys::tcpip_adapter_get_dns_info(self.as_raw(), server.to_raw(), &mut raw)
+        })
+        .into_result()?;
+        Ok(ipv4_from_lwip(unsafe { raw.ip.u_addr.ip4 }))
+    }
+
+    /// Set a single DNS server.
+    pub fn set_dns_server(self, server: DnsServer, addr: Ipv4Addr) -> Result<(), EspError> {
+        let mut raw: esp_idf_sys::tcpip_adapter_dns_info_t = unsafe { core::mem::zeroed() };
+        raw.ip.u_addr.ip4 = ipv4_to_lwip(addr);
+        raw.ip.type_ = esp_idf_sys::lwip_ip_addr_type_IPADDR_TYPE_V4 as esp_idf_sys::u8_t;
+        EspError(unsafe {
+            esp_idf_sys::tcpip_adapter_set_dns_info(self.as_raw(), server.to_raw(), &mut raw)
+        })
+        .into_result()
+    }
+
+    /// Set the main and, optionally, the backup DNS server.
+    ///
+    /// Note that a running DHCP client will overwrite these when it obtains a
+    /// lease.
+    pub fn set_dns(self, primary: Ipv4Addr, secondary: Option<Ipv4Addr>) -> Result<(), EspError> {
+        self.set_dns_server(DnsServer::Main, primary)?;
+        if let Some(secondary) = secondary {
+            self.set_dns_server(DnsServer::Backup, secondary)?;
+        }
+        Ok(())
+    }
+
+    /// Get the interface's hostname.
+    pub fn hostname(self) -> Result<CString, EspError> {
+        let mut name_ptr: *const esp_idf_sys::types::c_char = core::ptr::null();
+        EspError(unsafe { esp_idf_sys::tcpip_adapter_get_hostname(self.as_raw(), &mut name_ptr) })
+            .into_result()?;
+        if name_ptr.is_null() {
+            return Err(EspError(
+                esp_idf_sys::ESP_ERR_NOT_FOUND as esp_idf_sys::esp_err_t,
+            ));
+        }
+        // The name lives in a buffer owned by the adapter which is overwritten
+        // by `set_hostname`, so hand out a copy.
+        let name = unsafe { CStr::from_ptr(name_ptr) };
+        CString::new(name.to_bytes())
+            .map_err(|_| EspError(esp_idf_sys::ESP_ERR_INVALID_STATE as esp_idf_sys::esp_err_t))
+    }
+
+    /// Set the interface's hostname, which is sent with DHCP requests. Names
+    /// longer than 32 bytes are rejected.
+    pub fn set_hostname(self, hostname: &str) -> Result<(), EspError> {
+        let name = CString::new(hostname)
+            .map_err(|_| EspError(esp_idf_sys::ESP_ERR_INVALID_ARG as esp_idf_sys::esp_err_t))?;
+        EspError(unsafe { esp_idf_sys::tcpip_adapter_set_hostname(self.as_raw(), name.as_ptr()) })
+            .into_result()
+    }
+
+    /// Get the status of the interface's DHCP client.
+    pub fn dhcp_client_status(self) -> Result<DhcpStatus, EspError> {
+        let mut status: esp_idf_sys::tcpip_adapter_dhcp_status_t = 0;
+        EspError(unsafe {
+            esp_idf_sys::tcpip_adapter_dhcpc_get_status(self.as_raw(), &mut status)
+        })
+        .into_result()?;
+        Ok(dhcp_status_from_raw(status))
+    }
+
+    /// Start the DHCP client. Succeeds if it is already running.
+    pub fn start_dhcp_client(self) -> Result<(), EspError> {
+        ignore_err(
+            unsafe { esp_idf_sys::tcpip_adapter_dhcpc_start(self.as_raw()) },
+            esp_idf_sys::ESP_ERR_TCPIP_ADAPTER_DHCP_ALREADY_STARTED,
+        )
+    }
+
+    /// Stop the DHCP client. Succeeds if it is already stopped.
+    pub fn stop_dhcp_client(self) -> Result<(), EspError> {
+        ignore_err(
+            unsafe { esp_idf_sys::tcpip_adapter_dhcpc_stop(self.as_raw()) },
+            esp_idf_sys::ESP_ERR_TCPIP_ADAPTER_DHCP_ALREADY_STOPPED,
+        )
+    }
+
+    /// Get the status of the interface's DHCP server.
+    pub fn dhcp_server_status(self) -> Result<DhcpStatus, EspError> {
+        let mut status: esp_idf_sys::tcpip_adapter_dhcp_status_t = 0;
+        EspError(unsafe {
+            esp_idf_sys::tcpip_adapter_dhcps_get_status(self.as_raw(), &mut status)
+        })
+        .into_result()?;
+        Ok(dhcp_status_from_raw(status))
+    }
+
+    /// Start the DHCP server. Succeeds if it is already running.
+    pub fn start_dhcp_server(self) -> Result<(), EspError> {
+        ignore_err(
+            unsafe { esp_idf_sys::tcpip_adapter_dhcps_start(self.as_raw()) },
+            esp_idf_sys::ESP_ERR_TCPIP_ADAPTER_DHCP_ALREADY_STARTED,
+        )
+    }
+
+    /// Stop the DHCP server. Succeeds if it is already stopped.
+    pub fn stop_dhcp_server(self) -> Result<(), EspError> {
+        ignore_err(
+            unsafe { esp_idf_sys::tcpip_adapter_dhcps_stop(self.as_raw()) },
+            esp_idf_sys::ESP_ERR_TCPIP_ADAPTER_DHCP_ALREADY_STOPPED,
+        )
+    }
+
+    /// Create the interface's IPv6 link-local address. The address becomes
+    /// available once `IP_EVENT_GOT_IP6` has been posted.
+    pub fn create_ipv6_link_local(self) -> Result<(), EspError> {
+        EspError(unsafe { esp_idf_sys::tcpip_adapter_create_ip6_linklocal(self.as_raw()) })
+            .into_result()
+    }
+
+    /// Get the interface's IPv6 link-local address.
+    pub fn ipv6_link_local(self) -> Result<Ipv6Addr, EspError> {
+        let mut raw: esp_idf_sys::ip6_addr_t = unsafe { core::mem::zeroed() };
+        EspError(unsafe { esp_idf_sys::tcpip_adapter_get_ip6_linklocal(self.as_raw(), &mut raw) })
+            .into_result()?;
+        Ok(ipv6_from_lwip(&raw))
+    }
+
+    /// Get the interface's preferred IPv6 global address.
+    pub fn ipv6_global(self) -> Result<Ipv6Addr, EspError> {
+        let mut raw: esp_idf_sys::ip6_addr_t = unsafe { core::mem::zeroed() };
+        EspError(unsafe { esp_idf_sys::tcpip_adapter_get_ip6_global(self.as_raw(), &mut raw) })
+            .into_result()?;
+        Ok(ipv6_from_lwip(&raw))
+    }
+}
+
+fn dhcp_status_from_raw(status: esp_idf_sys::tcpip_adapter_dhcp_status_t) -> DhcpStatus {
+    match status {
+        esp_idf_sys::tcpip_adapter_dhcp_status_t_TCPIP_ADAPTER_DHCP_STARTED => DhcpStatus::Started,
+        esp_idf_sys::tcpip_adapter_dhcp_status_t_TCPIP_ADAPTER_DHCP_STOPPED => DhcpStatus::Stopped,
+        _ => DhcpStatus::Init,
+    }
+}
+
+fn ignore_err(ret: esp_idf_sys::esp_err_t, ignored: u32) -> Result<(), EspError> {
+    if ret == ignored as esp_idf_sys::esp_err_t {
+        Ok(())
+    } else {
+        EspError(ret).into_result()
+    }
+}
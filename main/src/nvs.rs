@@ -0,0 +1,597 @@
+//! Typed access to the non-volatile storage (NVS) key-value store.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use cstr_core::{CStr, CString};
+use esp_idf_hal::errors::EspError;
+use esp_idf_sys::types::c_void;
+
+fn invalid_arg() -> EspError {
+    EspError(esp_idf_sys::ESP_ERR_INVALID_ARG as esp_idf_sys::esp_err_t)
+}
+
+fn to_cstring(s: &str) -> Result<CString, EspError> {
+    CString::new(s).map_err(|_| invalid_arg())
+}
+
+/// Map `ESP_ERR_NVS_NOT_FOUND` to `Ok(None)`.
+fn found(ret: esp_idf_sys::esp_err_t) -> Result<Option<()>, EspError> {
+    if ret == esp_idf_sys::ESP_ERR_NVS_NOT_FOUND as esp_idf_sys::esp_err_t {
+        Ok(None)
+    } else {
+        EspError(ret).into_result().map(Some)
+    }
+}
+
+/// Make a label that lives for the rest of the program, as NVS keeps a
+/// pointer to the label of every initialized partition.
+fn static_label(label: &str) -> Result<&'static CStr, EspError> {
+    let label = to_cstring(label)?;
+    Ok(unsafe { CStr::from_ptr(label.into_raw()) })
+}
+
+/// An initialized NVS partition.
+#[derive(Debug)]
+pub struct NvsPartition {
+    /// `None` for the default `nvs` partition.
+    label: Option<&'static CStr>,
+}
+
+impl NvsPartition {
+    /// Initialize the default `nvs` partition.
+    ///
+    /// If the partition is full or was written by a newer NVS version, it is
+    /// erased and initialized again.
+    pub fn init_default() -> Result<NvsPartition, EspError> {
+        let partition = NvsPartition { label: None };
+        partition.init_with_recovery(None)?;
+        Ok(partition)
+    }
+
+    /// Initialize the partition with the given label, recovering like
+    /// `init_default`.
+    pub fn init(label: &str) -> Result<NvsPartition, EspError> {
+        let partition = NvsPartition {
+            label: Some(static_label(label)?),
+        };
+        partition.init_with_recovery(None)?;
+        Ok(partition)
+    }
+
+    /// Initialize the default `nvs` partition with encryption, recovering
+    /// like `init_default`.
+    ///
+    /// Namespaces opened on the returned partition are read and written
+    /// through the same interface as plain ones.
+    pub fn init_default_encrypted(keys: &NvsKeys) -> Result<NvsPartition, EspError> {
+        let partition = NvsPartition { label: None };
+        partition.init_with_recovery(Some(keys))?;
+        Ok(partition)
+    }
+
+    /// Initialize the partition with the given label with encryption,
+    /// recovering like `init_default`.
+    pub fn init_encrypted(label: &str, keys: &NvsKeys) -> Result<NvsPartition, EspError> {
+        let partition = NvsPartition {
+            label: Some(static_label(label)?),
+        };
+        partition.init_with_recovery(Some(keys))?;
+        Ok(partition)
+    }
+
+    /// Usage statistics for this partition.
+    pub fn stats(&self) -> Result<NvsStats, EspError> {
+        let mut stats: esp_idf_sys::nvs_stats_t = unsafe { core::mem::zeroed() };
+        let label = self.label.map_or(core::ptr::null(), |l| l.as_ptr());
+        EspError(unsafe { esp_idf_sys::nvs_get_stats(label, &mut stats) }).into_result()?;
+        Ok(NvsStats {
+            used_entries: stats.used_entries as usize,
+            free_entries: stats.free_entries as usize,
+            total_entries: stats.total_entries as usize,
+            namespace_count: stats.namespace_count as usize,
+        })
+    }
+
+    /// Iterate over the entries stored on this partition, optionally only
+    /// those in `namespace` and of type `ty`.
+    pub fn entries(
+        &self,
+        namespace: Option<&str>,
+        ty: Option<NvsType>,
+    ) -> Result<Entries, EspError> {
+        let namespace = namespace.map(to_cstring).transpose()?;
+        let label = match &self.label {
+            Some(label) => label.as_ptr(),
+            None => DEFAULT_LABEL.as_ptr() as *const _,
+        };
+        let iterator = unsafe {
+            esp_idf_sys::nvs_entry_find(
+                label,
+                namespace.as_ref().map_or(core::ptr::null(), |n| n.as_ptr()),
+                ty.map_or(esp_idf_sys::nvs_type_t_NVS_TYPE_ANY, NvsType::to_raw),
+            )
+        };
+        Ok(Entries { iterator })
+    }
+
+    fn init_with_recovery(&self, keys: Option<&NvsKeys>) -> Result<(), EspError> {
+        let ret = self.init_raw(keys);
+        if ret == esp_idf_sys::ESP_ERR_NVS_NO_FREE_PAGES as esp_idf_sys::esp_err_t
+            || ret == esp_idf_sys::ESP_ERR_NVS_NEW_VERSION_FOUND as esp_idf_sys::esp_err_t
+        {
+            self.erase()?;
+            return EspError(self.init_raw(keys)).into_result();
+        }
+        EspError(ret).into_result()
+    }
+
+    fn init_raw(&self, keys: Option<&NvsKeys>) -> esp_idf_sys::esp_err_t {
+        unsafe {
+            match (self.label, keys) {
+                (None, None) => esp_idf_sys::nvs_flash_init(),
+                (Some(label), None) => esp_idf_sys::nvs_flash_init_partition(label.as_ptr()),
+                // The configuration is only read, despite the `*mut`.
+                (None, Some(keys)) => esp_idf_sys::nvs_flash_secure_init(keys.as_raw()),
+                (Some(label), Some(keys)) => {
+                    esp_idf_sys::nvs_flash_secure_init_partition(label.as_ptr(), keys.as_raw())
+                }
+            }
+        }
+    }
+
+    fn erase(&self) -> Result<(), EspError> {
+        EspError(unsafe {
+            match &self.label {
+                None => esp_idf_sys::nvs_flash_erase(),
+                Some(label) => esp_idf_sys::nvs_flash_erase_partition(label.as_ptr()),
+            }
+        })
+        .into_result()
+    }
+
+    /// Open a namespace on this partition. Opening a namespace that doesn't
+    /// exist read-only fails with `ESP_ERR_NVS_NOT_FOUND`; read-write creates
+    /// it.
+    pub fn open(&self, namespace: &str, mode: OpenMode) -> Result<NvsNamespace, EspError> {
+        let namespace = to_cstring(namespace)?;
+        let mut handle: esp_idf_sys::nvs_handle_t = 0;
+        EspError(unsafe {
+            match &self.label {
+                None => esp_idf_sys::nvs_open(namespace.as_ptr(), mode.to_raw(), &mut handle),
+                Some(label) => esp_idf_sys::nvs_open_from_partition(
+                    label.as_ptr(),
+                    namespace.as_ptr(),
+                    mode.to_raw(),
+                    &mut handle,
+                ),
+            }
+        })
+        .into_result()?;
+        Ok(NvsNamespace { handle })
+    }
+}
+
+/// Keys for encrypting an NVS partition. They're zeroed when dropped.
+pub struct NvsKeys {
+    cfg: esp_idf_sys::nvs_sec_cfg_t,
+}
+
+impl NvsKeys {
+    /// Read the keys from the `nvs_keys` partition, or from the keys
+    /// partition with the given label, generating and storing new keys on
+    /// first boot.
+    ///
+    /// The keys partition has to be marked `encrypted` in the partition
+    /// table, which requires flash encryption to be enabled; otherwise the
+    /// keys would be stored in the clear.
+    pub fn load_or_generate(label: Option<&str>) -> Result<NvsKeys, EspError> {
+        let label = label.map(to_cstring).transpose()?;
+        let partition = unsafe {
+            esp_idf_sys::esp_partition_find_first(
+                esp_idf_sys::esp_partition_type_t_ESP_PARTITION_TYPE_DATA,
+                esp_idf_sys::esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_DATA_NVS_KEYS,
+                label.as_ref().map_or(core::ptr::null(), |l| l.as_ptr()),
+            )
+        };
+        if partition.is_null() {
+            return Err(EspError(
+                esp_idf_sys::ESP_ERR_NOT_FOUND as esp_idf_sys::esp_err_t,
+            ));
+        }
+
+        let mut keys = NvsKeys {
+            cfg: unsafe { core::mem::zeroed() },
+        };
+        let ret = unsafe { esp_idf_sys::nvs_flash_read_security_cfg(partition, &mut keys.cfg) };
+        if ret == esp_idf_sys::ESP_ERR_NVS_KEYS_NOT_INITIALIZED as esp_idf_sys::esp_err_t {
+            EspError(unsafe { esp_idf_sys::nvs_flash_generate_keys(partition, &mut keys.cfg) })
+                .into_result()?;
+        } else {
+            EspError(ret).into_result()?;
+        }
+        Ok(keys)
+    }
+
+    fn as_raw(&self) -> *mut esp_idf_sys::nvs_sec_cfg_t {
+        &self.cfg as *const _ as *mut _
+    }
+}
+
+impl Drop for NvsKeys {
+    fn drop(&mut self) {
+        let cfg = &mut self.cfg as *mut esp_idf_sys::nvs_sec_cfg_t;
+        unsafe { core::ptr::write_volatile(cfg, core::mem::zeroed()) };
+    }
+}
+
+// Hand-written so the keys don't end up in logs.
+impl core::fmt::Debug for NvsKeys {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("NvsKeys(..)")
+    }
+}
+
+/// Label of the default partition, `NVS_DEFAULT_PART_NAME`.
+const DEFAULT_LABEL: &[u8] = b"nvs\0";
+
+/// Entry counts for a partition. Every value takes at least one entry of 32
+/// bytes; strings and blobs take one more per 32 bytes of data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NvsStats {
+    pub used_entries: usize,
+    pub free_entries: usize,
+    pub total_entries: usize,
+    pub namespace_count: usize,
+}
+
+impl NvsStats {
+    /// Fraction of entries in use, from 0 to 1.
+    pub fn usage(&self) -> f32 {
+        if self.total_entries == 0 {
+            return 0.0;
+        }
+        self.used_entries as f32 / self.total_entries as f32
+    }
+}
+
+/// Types of stored values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NvsType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    Str,
+    Blob,
+}
+
+impl NvsType {
+    fn to_raw(self) -> esp_idf_sys::nvs_type_t {
+        match self {
+            NvsType::U8 => esp_idf_sys::nvs_type_t_NVS_TYPE_U8,
+            NvsType::I8 => esp_idf_sys::nvs_type_t_NVS_TYPE_I8,
+            NvsType::U16 => esp_idf_sys::nvs_type_t_NVS_TYPE_U16,
+            NvsType::I16 => esp_idf_sys::nvs_type_t_NVS_TYPE_I16,
+            NvsType::U32 => esp_idf_sys::nvs_type_t_NVS_TYPE_U32,
+            NvsType::I32 => esp_idf_sys::nvs_type_t_NVS_TYPE_I32,
+            NvsType::U64 => esp_idf_sys::nvs_type_t_NVS_TYPE_U64,
+            NvsType::I64 => esp_idf_sys::nvs_type_t_NVS_TYPE_I64,
+            NvsType::Str => esp_idf_sys::nvs_type_t_NVS_TYPE_STR,
+            NvsType::Blob => esp_idf_sys::nvs_type_t_NVS_TYPE_BLOB,
+        }
+    }
+
+    fn from_raw(ty: esp_idf_sys::nvs_type_t) -> Option<Self> {
+        Some(match ty {
+            esp_idf_sys::nvs_type_t_NVS_TYPE_U8 => NvsType::U8,
+            esp_idf_sys::nvs_type_t_NVS_TYPE_I8 => NvsType::I8,
+            esp_idf_sys::nvs_type_t_NVS_TYPE_U16 => NvsType::U16,
+            esp_idf_sys::nvs_type_t_NVS_TYPE_I16 => NvsType::I16,
+            esp_idf_sys::nvs_type_t_NVS_TYPE_U32 => NvsType::U32,
+            esp_idf_sys::nvs_type_t_NVS_TYPE_I32 => NvsType::I32,
+            esp_idf_sys::nvs_type_t_NVS_TYPE_U64 => NvsType::U64,
+            esp_idf_sys::nvs_type_t_NVS_TYPE_I64 => NvsType::I64,
+            esp_idf_sys::nvs_type_t_NVS_TYPE_STR => NvsType::Str,
+            esp_idf_sys::nvs_type_t_NVS_TYPE_BLOB => NvsType::Blob,
+            _ => return None,
+        })
+    }
+}
+
+/// A stored key, as listed by [`NvsPartition::entries()`].
+///
+/// [`NvsPartition::entries()`]: struct.NvsPartition.html#method.entries
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NvsEntry {
+    pub namespace: String,
+    pub key: String,
+    /// `None` for types this module doesn't know about.
+    pub ty: Option<NvsType>,
+}
+
+/// Iterator over stored entries.
+pub struct Entries {
+    /// Points at the next entry, or is null once all have been returned.
+    iterator: esp_idf_sys::nvs_iterator_t,
+}
+
+impl Iterator for Entries {
+    type Item = NvsEntry;
+
+    fn next(&mut self) -> Option<NvsEntry> {
+        if self.iterator.is_null() {
+            return None;
+        }
+        let mut info: esp_idf_sys::nvs_entry_info_t = unsafe { core::mem::zeroed() };
+        unsafe { esp_idf_sys::nvs_entry_info(self.iterator, &mut info) };
+        // Frees the iterator and returns null after the last entry.
+        self.iterator = unsafe { esp_idf_sys::nvs_entry_next(self.iterator) };
+        Some(NvsEntry {
+            namespace: from_c_chars(&info.namespace_name),
+            key: from_c_chars(&info.key),
+            ty: NvsType::from_raw(info.type_),
+        })
+    }
+}
+
+impl Drop for Entries {
+    fn drop(&mut self) {
+        if !self.iterator.is_null() {
+            unsafe { esp_idf_sys::nvs_release_iterator(self.iterator) };
+        }
+    }
+}
+
+fn from_c_chars(chars: &[esp_idf_sys::types::c_char]) -> String {
+    let bytes: Vec<u8> = chars
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).into()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenMode {
+    ReadOnly,
+    ReadWrite,
+}
+
+impl OpenMode {
+    fn to_raw(self) -> esp_idf_sys::nvs_open_mode_t {
+        match self {
+            OpenMode::ReadOnly => esp_idf_sys::nvs_open_mode_t_NVS_READONLY,
+            OpenMode::ReadWrite => esp_idf_sys::nvs_open_mode_t_NVS_READWRITE,
+        }
+    }
+}
+
+/// An open namespace. The handle is closed when this is dropped; changes
+/// aren't guaranteed to reach flash until [`commit()`] is called.
+///
+/// [`commit()`]: #method.commit
+#[derive(Debug)]
+pub struct NvsNamespace {
+    handle: esp_idf_sys::nvs_handle_t,
+}
+
+impl NvsNamespace {
+    /// Get the underlying NVS handle.
+    pub fn raw_handle(&self) -> esp_idf_sys::nvs_handle_t {
+        self.handle
+    }
+
+    /// Number of entries used by this namespace.
+    pub fn used_entry_count(&self) -> Result<usize, EspError> {
+        let mut count = 0;
+        EspError(unsafe { esp_idf_sys::nvs_get_used_entry_count(self.handle, &mut count) })
+            .into_result()?;
+        Ok(count as usize)
+    }
+
+    /// Read the value stored under `key`, or `None` if there is none.
+    ///
+    /// Reading a key that was stored with a different type fails with
+    /// `ESP_ERR_NVS_TYPE_MISMATCH`.
+    pub fn get<T: NvsValue>(&self, key: &str) -> Result<Option<T>, EspError> {
+        T::get_from(self, &to_cstring(key)?)
+    }
+
+    /// Store `value` under `key`.
+    pub fn set<T: NvsValue>(&mut self, key: &str, value: &T) -> Result<(), EspError> {
+        value.set_in(self, &to_cstring(key)?)
+    }
+
+    /// Store a string under `key`, as `set::<String>` would.
+    pub fn set_str(&mut self, key: &str, value: &str) -> Result<(), EspError> {
+        let key = to_cstring(key)?;
+        let value = to_cstring(value)?;
+        EspError(unsafe { esp_idf_sys::nvs_set_str(self.handle, key.as_ptr(), value.as_ptr()) })
+            .into_result()
+    }
+
+    /// Store a blob under `key`, as `set::<Vec<u8>>` would.
+    pub fn set_blob(&mut self, key: &str, value: &[u8]) -> Result<(), EspError> {
+        let key = to_cstring(key)?;
+        EspError(unsafe {
+            esp_idf_sys::nvs_set_blob(
+                self.handle,
+                key.as_ptr(),
+                value.as_ptr() as *const c_void,
+                value.len() as _,
+            )
+        })
+        .into_result()
+    }
+
+    /// Remove `key`, returning whether it existed.
+    pub fn remove(&mut self, key: &str) -> Result<bool, EspError> {
+        let key = to_cstring(key)?;
+        found(unsafe { esp_idf_sys::nvs_erase_key(self.handle, key.as_ptr()) }).map(|r| r.is_some())
+    }
+
+    /// Remove every key in the namespace.
+    pub fn erase_all(&mut self) -> Result<(), EspError> {
+        EspError(unsafe { esp_idf_sys::nvs_erase_all(self.handle) }).into_result()
+    }
+
+    /// Write pending changes to flash.
+    pub fn commit(&mut self) -> Result<(), EspError> {
+        EspError(unsafe { esp_idf_sys::nvs_commit(self.handle) }).into_result()
+    }
+}
+
+impl Drop for NvsNamespace {
+    fn drop(&mut self) {
+        unsafe { esp_idf_sys::nvs_close(self.handle) };
+    }
+}
+
+/// A type that can be stored in NVS.
+pub trait NvsValue: Sized {
+    fn get_from(namespace: &NvsNamespace, key: &CStr) -> Result<Option<Self>, EspError>;
+    fn set_in(&self, namespace: &mut NvsNamespace, key: &CStr) -> Result<(), EspError>;
+}
+
+macro_rules! impl_nvs_value {
+    ($($ty:ty => $get:ident, $set:ident;)*) => {
+        $(
+            impl NvsValue for $ty {
+                fn get_from(namespace: &NvsNamespace, key: &CStr) -> Result<Option<Self>, EspError> {
+                    let mut value: $ty = 0;
+                    Ok(found(unsafe {
+                        esp_idf_sys::$get(namespace.handle, key.as_ptr(), &mut value)
+                    })?
+                    .map(|()| value))
+                }
+
+                fn set_in(&self, namespace: &mut NvsNamespace, key: &CStr) -> Result<(), EspError> {
+                    EspError(unsafe { esp_idf_sys::$set(namespace.handle, key.as_ptr(), *self) })
+                        .into_result()
+                }
+            }
+        )*
+    };
+}
+
+impl_nvs_value! {
+    i8 => nvs_get_i8, nvs_set_i8;
+    u8 => nvs_get_u8, nvs_set_u8;
+    i16 => nvs_get_i16, nvs_set_i16;
+    u16 => nvs_get_u16, nvs_set_u16;
+    i32 => nvs_get_i32, nvs_set_i32;
+    u32 => nvs_get_u32, nvs_set_u32;
+    i64 => nvs_get_i64, nvs_set_i64;
+    u64 => nvs_get_u64, nvs_set_u64;
+}
+
+impl NvsValue for bool {
+    fn get_from(namespace: &NvsNamespace, key: &CStr) -> Result<Option<Self>, EspError> {
+        Ok(u8::get_from(namespace, key)?.map(|v| v != 0))
+    }
+
+    fn set_in(&self, namespace: &mut NvsNamespace, key: &CStr) -> Result<(), EspError> {
+        (*self as u8).set_in(namespace, key)
+    }
+}
+
+impl NvsValue for String {
+    fn get_from(namespace: &NvsNamespace, key: &CStr) -> Result<Option<Self>, EspError> {
+        let mut len = 0;
+        let ret = unsafe {
+            esp_idf_sys::nvs_get_str(
+                namespace.handle,
+                key.as_ptr(),
+                core::ptr::null_mut(),
+                &mut len,
+            )
+        };
+        if found(ret)?.is_none() {
+            return Ok(None);
+        }
+
+        let mut buf = vec![0u8; len as usize];
+        EspError(unsafe {
+            esp_idf_sys::nvs_get_str(
+                namespace.handle,
+                key.as_ptr(),
+                buf.as_mut_ptr() as *mut _,
+                &mut len,
+            )
+        })
+        .into_result()?;
+        // Drop the NUL terminator.
+        buf.truncate((len as usize).saturating_sub(1));
+        String::from_utf8(buf)
+            .map(Some)
+            .map_err(|_| EspError(esp_idf_sys::ESP_ERR_INVALID_RESPONSE as esp_idf_sys::esp_err_t))
+    }
+
+    fn set_in(&self, namespace: &mut NvsNamespace, key: &CStr) -> Result<(), EspError> {
+        let value = to_cstring(self)?;
+        EspError(unsafe {
+            esp_idf_sys::nvs_set_str(namespace.handle, key.as_ptr(), value.as_ptr())
+        })
+        .into_result()
+    }
+}
+
+impl NvsValue for Vec<u8> {
+    fn get_from(namespace: &NvsNamespace, key: &CStr) -> Result<Option<Self>, EspError> {
+        let mut len = 0;
+        let ret = unsafe {
+            esp_idf_sys::nvs_get_blob(
+                namespace.handle,
+                key.as_ptr(),
+                core::ptr::null_mut(),
+                &mut len,
+            )
+        };
+        if found(ret)?.is_none() {
+            return Ok(None);
+        }
+
+        let mut buf = vec![0u8; len as usize];
+        EspError(unsafe {
+            esp_idf_sys::nvs_get_blob(
+                namespace.handle,
+                key.as_ptr(),
+                buf.as_mut_ptr() as *mut c_void,
+                &mut len,
+            )
+        })
+        .into_result()?;
+        buf.truncate(len as usize);
+        Ok(Some(buf))
+    }
+
+    fn set_in(&self, namespace: &mut NvsNamespace, key: &CStr) -> Result<(), EspError> {
+        EspError(unsafe {
+            esp_idf_sys::nvs_set_blob(
+                namespace.handle,
+                key.as_ptr(),
+                self.as_ptr() as *const c_void,
+                self.len() as _,
+            )
+        })
+        .into_result()
+    }
+}
+
+impl crate::persistent_config::Storage for NvsNamespace {
+    type Error = EspError;
+
+    fn read_blob(&mut self, key: &str) -> Result<Option<Vec<u8>>, EspError> {
+        self.get(key)
+    }
+
+    fn write_blob(&mut self, key: &str, data: &[u8]) -> Result<(), EspError> {
+        self.set_blob(key, data)?;
+        self.commit()
+    }
+}
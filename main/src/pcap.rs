@@ -0,0 +1,186 @@
+//! Writer for the libpcap capture file format, with 802.11 frames wrapped in
+//! radiotap headers (`LINKTYPE_IEEE802_11_RADIOTAP`).
+//!
+//! This module is plain Rust and doesn't touch `esp-idf`, so it can be
+//! exercised on the host; see `host-tests`.
+
+use alloc::vec::Vec;
+
+pub const LINKTYPE_IEEE802_11_RADIOTAP: u32 = 127;
+
+/// Magic number for microsecond-resolution timestamps.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+
+/// Largest radiotap header produced by `radiotap_header`.
+pub const MAX_RADIOTAP_LEN: usize = 32;
+
+const RADIOTAP_TSFT: u32 = 1 << 0;
+const RADIOTAP_FLAGS: u32 = 1 << 1;
+const RADIOTAP_RATE: u32 = 1 << 2;
+const RADIOTAP_CHANNEL: u32 = 1 << 3;
+const RADIOTAP_DBM_ANTSIGNAL: u32 = 1 << 5;
+const RADIOTAP_DBM_ANTNOISE: u32 = 1 << 6;
+const RADIOTAP_MCS: u32 = 1 << 19;
+
+/// Radiotap flag: the frame includes the FCS at the end.
+const RADIOTAP_F_FCS: u8 = 0x10;
+/// Radiotap channel flag: 2 GHz spectrum channel.
+const RADIOTAP_CHAN_2GHZ: u16 = 0x0080;
+/// Radiotap MCS "known" bits: bandwidth, MCS index and guard interval.
+const RADIOTAP_MCS_KNOWN: u8 = 0x01 | 0x02 | 0x04;
+
+/// Somewhere the capture can be written to.
+pub trait Sink {
+    type Error;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error>;
+}
+
+impl Sink for Vec<u8> {
+    type Error = core::convert::Infallible;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// The modulation a frame was received with.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum PhyRate {
+    /// An 802.11b/g rate, in units of 500 kbps.
+    Legacy(u8),
+    /// An 802.11n MCS index.
+    Ht { mcs: u8, ht40: bool, short_gi: bool },
+}
+
+/// Receive metadata to encode in the radiotap header.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub struct RadioInfo {
+    /// Receive timestamp, in microseconds.
+    pub tsft: u64,
+    pub rate: Option<PhyRate>,
+    /// Primary 2.4 GHz channel number.
+    pub channel: u8,
+    /// Signal strength in dBm.
+    pub rssi: i8,
+    /// Noise floor in dBm.
+    pub noise_floor: i8,
+    /// Whether the frame data ends with its 4-byte FCS.
+    pub has_fcs: bool,
+}
+
+/// Center frequency in MHz of a 2.4 GHz channel.
+pub fn channel_frequency(channel: u8) -> u16 {
+    match channel {
+        14 => 2484,
+        c => 2407 + 5 * c as u16,
+    }
+}
+
+/// Encode a radiotap header for `radio` into `buf`, returning its length.
+pub fn radiotap_header(radio: &RadioInfo, buf: &mut [u8; MAX_RADIOTAP_LEN]) -> usize {
+    let mut present = RADIOTAP_TSFT
+        | RADIOTAP_FLAGS
+        | RADIOTAP_CHANNEL
+        | RADIOTAP_DBM_ANTSIGNAL
+        | RADIOTAP_DBM_ANTNOISE;
+    match radio.rate {
+        Some(PhyRate::Legacy(_)) => present |= RADIOTAP_RATE,
+        Some(PhyRate::Ht { .. }) => present |= RADIOTAP_MCS,
+        None => (),
+    }
+
+    // Fields are written in order of their presence bit, each aligned to its
+    // natural size relative to the start of the header.
+    let mut len = 8;
+    let mut put = |data: &[u8], align: usize| {
+        while len % align != 0 {
+            buf[len] = 0;
+            len += 1;
+        }
+        buf[len..len + data.len()].copy_from_slice(data);
+        len += data.len();
+    };
+
+    put(&radio.tsft.to_le_bytes(), 8);
+    put(&[if radio.has_fcs { RADIOTAP_F_FCS } else { 0 }], 1);
+    if let Some(PhyRate::Legacy(rate)) = radio.rate {
+        put(&[rate], 1);
+    }
+    put(&channel_frequency(radio.channel).to_le_bytes(), 2);
+    put(&RADIOTAP_CHAN_2GHZ.to_le_bytes(), 2);
+    put(&[radio.rssi as u8], 1);
+    put(&[radio.noise_floor as u8], 1);
+    if let Some(PhyRate::Ht {
+        mcs,
+        ht40,
+        short_gi,
+    }) = radio.rate
+    {
+        let flags = if ht40 { 0x01 } else { 0 } | if short_gi { 0x04 } else { 0 };
+        put(&[RADIOTAP_MCS_KNOWN, flags, mcs], 1);
+    }
+
+    buf[0] = 0; // version
+    buf[1] = 0; // padding
+    buf[2..4].copy_from_slice(&(len as u16).to_le_bytes());
+    buf[4..8].copy_from_slice(&present.to_le_bytes());
+    len
+}
+
+/// Writes a pcap stream to a `Sink`.
+pub struct PcapWriter<S> {
+    sink: S,
+    snaplen: u32,
+}
+
+impl<S: Sink> PcapWriter<S> {
+    /// Write the global header. Frames longer than `snaplen` bytes are
+    /// truncated.
+    pub fn new(mut sink: S, snaplen: u32) -> Result<Self, S::Error> {
+        let mut header = [0u8; 24];
+        header[0..4].copy_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header[4..6].copy_from_slice(&2u16.to_le_bytes());
+        header[6..8].copy_from_slice(&4u16.to_le_bytes());
+        // thiszone and sigfigs are left as zero.
+        header[16..20].copy_from_slice(&(snaplen + MAX_RADIOTAP_LEN as u32).to_le_bytes());
+        header[20..24].copy_from_slice(&LINKTYPE_IEEE802_11_RADIOTAP.to_le_bytes());
+        sink.write_all(&header)?;
+        Ok(PcapWriter { sink, snaplen })
+    }
+
+    /// Write one frame. `orig_len` is the length of the frame on air, which
+    /// may exceed `frame.len()` if it was already truncated during capture.
+    pub fn write_frame(
+        &mut self,
+        timestamp_us: u64,
+        radio: &RadioInfo,
+        frame: &[u8],
+        orig_len: usize,
+    ) -> Result<(), S::Error> {
+        let mut radiotap = [0u8; MAX_RADIOTAP_LEN];
+        let rt_len = radiotap_header(radio, &mut radiotap);
+
+        let incl_len = core::cmp::min(frame.len(), self.snaplen as usize);
+        let orig_len = core::cmp::max(orig_len, frame.len());
+
+        let mut record = [0u8; 16];
+        record[0..4].copy_from_slice(&((timestamp_us / 1_000_000) as u32).to_le_bytes());
+        record[4..8].copy_from_slice(&((timestamp_us % 1_000_000) as u32).to_le_bytes());
+        record[8..12].copy_from_slice(&((rt_len + incl_len) as u32).to_le_bytes());
+        record[12..16].copy_from_slice(&((rt_len + orig_len) as u32).to_le_bytes());
+
+        self.sink.write_all(&record)?;
+        self.sink.write_all(&radiotap[..rt_len])?;
+        self.sink.write_all(&frame[..incl_len])
+    }
+
+    pub fn sink(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
@@ -0,0 +1,541 @@
+//! A settings struct persisted as a single blob, with a schema version, a
+//! CRC and step-by-step migrations from older versions.
+//!
+//! Values are encoded with a compact binary format: unsigned integers are
+//! LEB128 varints, signed integers are zigzag-encoded varints, floats are
+//! little-endian, and strings and vectors are prefixed with their length.
+//! Structs are their fields in order; see [`impl_encode_decode!`].
+//!
+//! The blob starts with a header:
+//!
+//! | Offset | Size | Field                                 |
+//! |--------|------|---------------------------------------|
+//! | 0      | 2    | schema version, little-endian         |
+//! | 2      | 4    | CRC-32 of the payload, little-endian  |
+//! | 6      | ..   | payload                               |
+//!
+//! This module doesn't touch `esp-idf`; [`MemoryStorage`] stands in for NVS
+//! when exercising it on the host.
+//!
+//! [`impl_encode_decode!`]: ../macro.impl_encode_decode.html
+//! [`MemoryStorage`]: struct.MemoryStorage.html
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const HEADER_LEN: usize = 6;
+
+/// Reasons a value couldn't be decoded.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input ended in the middle of a value.
+    Truncated,
+    /// A varint doesn't fit the type being decoded.
+    Overflow,
+    /// A tag or string doesn't hold a valid value.
+    Invalid,
+    /// Bytes were left over after decoding.
+    TrailingBytes,
+}
+
+/// Writes values in the compact format.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder { buf: Vec::new() }
+    }
+
+    pub fn write_varint(&mut self, mut v: u64) {
+        loop {
+            let byte = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                self.buf.push(byte);
+                return;
+            }
+            self.buf.push(byte | 0x80);
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_varint(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn write_raw(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads values in the compact format.
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Decoder { buf }
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_raw(1)?[0];
+            let bits = (byte & 0x7f) as u64;
+            if shift == 63 && bits > 1 {
+                return Err(DecodeError::Overflow);
+            }
+            v |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(DecodeError::Overflow)
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.read_varint()?;
+        if len > self.buf.len() as u64 {
+            return Err(DecodeError::Truncated);
+        }
+        self.read_raw(len as usize)
+    }
+
+    pub fn read_raw(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if len > self.buf.len() {
+            return Err(DecodeError::Truncated);
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    /// Fail unless all input has been consumed.
+    pub fn finish(self) -> Result<(), DecodeError> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(DecodeError::TrailingBytes)
+        }
+    }
+}
+
+pub trait Encode {
+    fn encode(&self, e: &mut Encoder);
+}
+
+pub trait Decode: Sized {
+    fn decode(d: &mut Decoder<'_>) -> Result<Self, DecodeError>;
+}
+
+/// Encode `value` into a new buffer.
+pub fn to_bytes<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    let mut e = Encoder::new();
+    value.encode(&mut e);
+    e.into_inner()
+}
+
+/// Decode a value that must span all of `bytes`.
+pub fn from_bytes<T: Decode>(bytes: &[u8]) -> Result<T, DecodeError> {
+    let mut d = Decoder::new(bytes);
+    let value = T::decode(&mut d)?;
+    d.finish()?;
+    Ok(value)
+}
+
+macro_rules! impl_unsigned {
+    ($($ty:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, e: &mut Encoder) {
+                    e.write_varint(*self as u64);
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(d: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+                    let v = d.read_varint()?;
+                    if v > <$ty>::max_value() as u64 {
+                        return Err(DecodeError::Overflow);
+                    }
+                    Ok(v as $ty)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_signed {
+    ($($ty:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, e: &mut Encoder) {
+                    let v = *self as i64;
+                    e.write_varint(((v << 1) ^ (v >> 63)) as u64);
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(d: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+                    let v = d.read_varint()?;
+                    let v = ((v >> 1) as i64) ^ -((v & 1) as i64);
+                    if v < <$ty>::min_value() as i64 || v > <$ty>::max_value() as i64 {
+                        return Err(DecodeError::Overflow);
+                    }
+                    Ok(v as $ty)
+                }
+            }
+        )*
+    };
+}
+
+impl_unsigned!(u8, u16, u32, u64);
+impl_signed!(i8, i16, i32, i64);
+
+impl Encode for bool {
+    fn encode(&self, e: &mut Encoder) {
+        e.write_raw(&[*self as u8]);
+    }
+}
+
+impl Decode for bool {
+    fn decode(d: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        match d.read_raw(1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+impl Encode for f32 {
+    fn encode(&self, e: &mut Encoder) {
+        e.write_raw(&self.to_bits().to_le_bytes());
+    }
+}
+
+impl Decode for f32 {
+    fn decode(d: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let b = d.read_raw(4)?;
+        Ok(f32::from_bits(u32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, e: &mut Encoder) {
+        e.write_bytes(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, e: &mut Encoder) {
+        self.as_str().encode(e);
+    }
+}
+
+impl Decode for String {
+    fn decode(d: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let bytes = d.read_bytes()?;
+        core::str::from_utf8(bytes)
+            .map(String::from)
+            .map_err(|_| DecodeError::Invalid)
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, e: &mut Encoder) {
+        e.write_varint(self.len() as u64);
+        for item in self {
+            item.encode(e);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(d: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let len = d.read_varint()?;
+        // Every item takes at least one byte, which bounds the allocation.
+        if len > d.buf.len() as u64 {
+            return Err(DecodeError::Truncated);
+        }
+        let mut items = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            items.push(T::decode(d)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, e: &mut Encoder) {
+        match self {
+            None => e.write_raw(&[0]),
+            Some(v) => {
+                e.write_raw(&[1]);
+                v.encode(e);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(d: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        match d.read_raw(1)?[0] {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(d)?)),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+/// Implement `Encode` and `Decode` for a struct by encoding its fields in
+/// order.
+///
+/// ```ignore
+/// struct Settings {
+///     brightness: u8,
+///     interval_s: u32,
+/// }
+/// impl_encode_decode!(Settings { brightness, interval_s });
+/// ```
+#[macro_export]
+macro_rules! impl_encode_decode {
+    ($ty:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::persistent_config::Encode for $ty {
+            fn encode(&self, e: &mut $crate::persistent_config::Encoder) {
+                $( $crate::persistent_config::Encode::encode(&self.$field, e); )*
+            }
+        }
+
+        impl $crate::persistent_config::Decode for $ty {
+            fn decode(
+                d: &mut $crate::persistent_config::Decoder<'_>,
+            ) -> Result<Self, $crate::persistent_config::DecodeError> {
+                Ok($ty {
+                    $( $field: $crate::persistent_config::Decode::decode(d)?, )*
+                })
+            }
+        }
+    };
+}
+
+/// CRC-32 (IEEE 802.3), as used by zlib.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Wrap an encoded payload in the versioned, checksummed header.
+pub fn seal(version: u16, payload: &[u8]) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(HEADER_LEN + payload.len());
+    blob.extend_from_slice(&version.to_le_bytes());
+    blob.extend_from_slice(&crc32(payload).to_le_bytes());
+    blob.extend_from_slice(payload);
+    blob
+}
+
+/// Check a blob's header, returning its version and payload, or `None` if
+/// it's truncated or its CRC doesn't match.
+pub fn unseal(blob: &[u8]) -> Option<(u16, &[u8])> {
+    if blob.len() < HEADER_LEN {
+        return None;
+    }
+    let version = u16::from_le_bytes([blob[0], blob[1]]);
+    let crc = u32::from_le_bytes([blob[2], blob[3], blob[4], blob[5]]);
+    let payload = &blob[HEADER_LEN..];
+    if crc32(payload) == crc {
+        Some((version, payload))
+    } else {
+        None
+    }
+}
+
+/// Where blobs are kept.
+pub trait Storage {
+    type Error;
+
+    fn read_blob(&mut self, key: &str) -> Result<Option<Vec<u8>>, Self::Error>;
+    /// Write `data` under `key`, making sure it's durable.
+    fn write_blob(&mut self, key: &str, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// In-memory storage, for exercising configs on the host.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStorage {
+    pub blobs: BTreeMap<String, Vec<u8>>,
+}
+
+impl Storage for MemoryStorage {
+    type Error = core::convert::Infallible;
+
+    fn read_blob(&mut self, key: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self.blobs.get(key).cloned())
+    }
+
+    fn write_blob(&mut self, key: &str, data: &[u8]) -> Result<(), Self::Error> {
+        self.blobs.insert(String::from(key), Vec::from(data));
+        Ok(())
+    }
+}
+
+/// Converts a payload of schema version `n` into one of version `n + 1`.
+pub type Migration = fn(&[u8]) -> Result<Vec<u8>, DecodeError>;
+
+/// How the value of a [`PersistentConfig`] was obtained.
+///
+/// [`PersistentConfig`]: struct.PersistentConfig.html
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum LoadStatus {
+    /// The stored value had the current version.
+    Loaded,
+    /// The stored value was migrated from this older version, and the
+    /// result written back.
+    Migrated(u16),
+    /// Nothing was stored, so the defaults are used.
+    Missing,
+    /// The stored value failed its CRC, couldn't be decoded or migrated, or
+    /// has a newer version than this firmware knows. The defaults are used,
+    /// and the stored value is left alone until the next `save`.
+    Corrupt,
+}
+
+/// Helper for loading a [`PersistentConfig`]. Instantiate with
+/// [`PersistentConfig::new()`].
+///
+/// [`PersistentConfig`]: struct.PersistentConfig.html
+/// [`PersistentConfig::new()`]: struct.PersistentConfig.html#method.new
+pub struct PersistentConfigBuilder<S> {
+    storage: S,
+    key: String,
+    version: u16,
+    migrations: BTreeMap<u16, Migration>,
+}
+
+impl<S: Storage> PersistentConfigBuilder<S> {
+    /// Register the migration from schema version `from` to `from + 1`.
+    pub fn migration(mut self, from: u16, migrate: Migration) -> Self {
+        self.migrations.insert(from, migrate);
+        self
+    }
+
+    /// Load the stored value, migrating it if needed, or fall back to
+    /// `T::default()`. Only storage errors are reported.
+    pub fn load<T: Encode + Decode + Default>(
+        mut self,
+    ) -> Result<PersistentConfig<T, S>, S::Error> {
+        let (value, status) = match self.storage.read_blob(&self.key)? {
+            None => (T::default(), LoadStatus::Missing),
+            Some(blob) => match self.decode(&blob) {
+                Some((value, stored_version)) if stored_version == self.version => {
+                    (value, LoadStatus::Loaded)
+                }
+                Some((value, stored_version)) => (value, LoadStatus::Migrated(stored_version)),
+                None => (T::default(), LoadStatus::Corrupt),
+            },
+        };
+
+        let config = PersistentConfig {
+            storage: self.storage,
+            key: self.key,
+            version: self.version,
+            value,
+            status,
+        };
+        if let LoadStatus::Migrated(_) = status {
+            let mut config = config;
+            config.save()?;
+            return Ok(config);
+        }
+        Ok(config)
+    }
+
+    fn decode<T: Decode>(&self, blob: &[u8]) -> Option<(T, u16)> {
+        let (stored_version, payload) = unseal(blob)?;
+        if stored_version > self.version {
+            return None;
+        }
+
+        let mut payload = Vec::from(payload);
+        for from in stored_version..self.version {
+            let migrate = self.migrations.get(&from)?;
+            payload = migrate(&payload).ok()?;
+        }
+        let value = from_bytes(&payload).ok()?;
+        Some((value, stored_version))
+    }
+}
+
+/// A value of type `T` stored under one key.
+pub struct PersistentConfig<T, S> {
+    storage: S,
+    key: String,
+    version: u16,
+    value: T,
+    status: LoadStatus,
+}
+
+impl<S: Storage> PersistentConfig<(), S> {
+    /// Prepare to load the config stored under `key` in `storage`, whose
+    /// current schema version is `version`.
+    pub fn new(storage: S, key: &str, version: u16) -> PersistentConfigBuilder<S> {
+        PersistentConfigBuilder {
+            storage,
+            key: String::from(key),
+            version,
+            migrations: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Encode, S: Storage> PersistentConfig<T, S> {
+    /// How the current value was obtained.
+    pub fn status(&self) -> LoadStatus {
+        self.status
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    /// Change the value in memory. Call `save` to persist it.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+
+    /// Change the value and persist it.
+    pub fn update(&mut self, f: impl FnOnce(&mut T)) -> Result<(), S::Error> {
+        f(&mut self.value);
+        self.save()
+    }
+
+    /// Persist the current value.
+    pub fn save(&mut self) -> Result<(), S::Error> {
+        let blob = seal(self.version, &to_bytes(&self.value));
+        self.storage.write_blob(&self.key, &blob)
+    }
+
+    pub fn storage(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    pub fn into_inner(self) -> (T, S) {
+        (self.value, self.storage)
+    }
+}
@@ -1,18 +1,133 @@
-use core::fmt::Write;
+use core::fmt::{self, Write};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
 
+use esp_idf_sys::QueueHandle_t;
+
+use crate::freertos_units::{Duration, DurationTicks};
+
+/// Writes to stdout without taking the console lock. Use `print!` unless
+/// you need a `fmt::Write`.
 pub struct PrintF;
 
+/// Writes to stderr without taking the console lock. Use `eprint!` unless
+/// you need a `fmt::Write`.
+pub struct EPrintF;
+
+const STDOUT: i32 = 1;
+const STDERR: i32 = 2;
+
 impl Write for PrintF {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        let bytes = s.as_bytes();
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_captured(STDOUT, s.as_bytes())
+    }
+}
+
+impl Write for EPrintF {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_captured(STDERR, s.as_bytes())
+    }
+}
 
+fn write_captured(fd: i32, bytes: &[u8]) -> fmt::Result {
+    crate::log_buffer::capture(bytes);
+    write_all(fd, bytes)
+}
+
+/// Write all of `bytes`, retrying after short writes.
+pub(crate) fn write_all(fd: i32, mut bytes: &[u8]) -> fmt::Result {
+    while !bytes.is_empty() {
         // Call the `write` syscall directly to minimize the stack impact of `printf`.
-        let num_written =
-            unsafe { esp_idf_sys::write(1, bytes.as_ptr() as *const _, bytes.len() as u32) };
-        if num_written == bytes.len() as i32 {
-            Ok(())
-        } else {
-            Err(core::fmt::Error)
+        let n = unsafe { esp_idf_sys::write(fd, bytes.as_ptr() as *const _, bytes.len() as u32) };
+        if n < 0 {
+            let errno = unsafe { *esp_idf_sys::__errno() } as u32;
+            if errno == esp_idf_sys::EAGAIN || errno == esp_idf_sys::EINTR {
+                continue;
+            }
+            return Err(fmt::Error);
+        }
+        if n == 0 {
+            return Err(fmt::Error);
+        }
+        bytes = &bytes[n as usize..];
+    }
+    Ok(())
+}
+
+// `queueQUEUE_TYPE_RECURSIVE_MUTEX` and `taskSCHEDULER_RUNNING`, which are
+// macros.
+const QUEUE_TYPE_RECURSIVE_MUTEX: u8 = 4;
+const SCHEDULER_RUNNING: esp_idf_sys::BaseType_t = 2;
+
+static MUTEX: AtomicPtr<esp_idf_sys::types::c_void> = AtomicPtr::new(ptr::null_mut());
+
+fn mutex() -> QueueHandle_t {
+    let current = MUTEX.load(Ordering::Acquire);
+    if !current.is_null() {
+        return current;
+    }
+
+    let new = unsafe { esp_idf_sys::xQueueCreateMutex(QUEUE_TYPE_RECURSIVE_MUTEX) };
+    if new.is_null() {
+        return new;
+    }
+    match MUTEX.compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => new,
+        Err(existing) => {
+            unsafe { esp_idf_sys::vQueueDelete(new) };
+            existing
+        }
+    }
+}
+
+/// Holds the console lock; released on drop.
+pub struct ConsoleLock {
+    mutex: QueueHandle_t,
+}
+
+/// Take the console lock, so a group of writes isn't interleaved with
+/// output from other tasks. The lock is recursive, so the print macros can
+/// still be used while holding it.
+///
+/// Output must not stall forever behind a stuck task, so after a second of
+/// waiting, or from an interrupt or before the scheduler has started, this
+/// returns without actually locking.
+pub fn lock() -> ConsoleLock {
+    let unlocked = ConsoleLock {
+        mutex: ptr::null_mut(),
+    };
+    unsafe {
+        if esp_idf_sys::xPortInIsrContext() != 0
+            || esp_idf_sys::xTaskGetSchedulerState() != SCHEDULER_RUNNING
+        {
+            return unlocked;
+        }
+        let mutex = mutex();
+        if mutex.is_null()
+            || esp_idf_sys::xQueueTakeMutexRecursive(mutex, Duration::ms(1000).to_ticks()) != 1
+        {
+            return unlocked;
+        }
+        ConsoleLock { mutex }
+    }
+}
+
+impl Drop for ConsoleLock {
+    fn drop(&mut self) {
+        if !self.mutex.is_null() {
+            unsafe { esp_idf_sys::xQueueGiveMutexRecursive(self.mutex) };
         }
     }
 }
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let _lock = lock();
+    let _ = PrintF.write_fmt(args);
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    let _lock = lock();
+    let _ = EPrintF.write_fmt(args);
+}
@@ -0,0 +1,559 @@
+//! Line editing, argument splitting and command dispatch for the serial
+//! console in [`console`].
+//!
+//! Bytes from the terminal go to a [`Repl`], which echoes and edits the
+//! line, keeps a history, and runs the command when Enter is pressed. The
+//! terminal is expected to understand the usual VT100 escape sequences.
+//! Output has `\n` line endings, which raw terminals need as `\r\n`.
+//!
+//! This module is plain Rust and doesn't touch `esp-idf`, so it can be
+//! exercised on the host; see `host-tests`.
+//!
+//! [`console`]: ../console/index.html
+//! [`Repl`]: struct.Repl.html
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+/// Longest line the editor accepts; further input is ignored.
+pub const MAX_LINE: usize = 256;
+
+const CTRL_A: u8 = 0x01;
+const CTRL_C: u8 = 0x03;
+const CTRL_E: u8 = 0x05;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const ESC: u8 = 0x1b;
+const DEL: u8 = 0x7f;
+
+/// Reasons a line couldn't be split into arguments.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnterminatedQuote,
+    TrailingBackslash,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ParseError::UnterminatedQuote => "unterminated quote",
+            ParseError::TrailingBackslash => "trailing backslash",
+        })
+    }
+}
+
+/// Split a command line into words at whitespace. Single or double quotes
+/// make a word of what they enclose, and a backslash takes the next
+/// character literally.
+pub fn split_args(line: &str) -> Result<Vec<String>, ParseError> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => {
+                word.push(chars.next().ok_or(ParseError::TrailingBackslash)?);
+                in_word = true;
+            }
+            (c, Some(q)) if c == q => quote = None,
+            (c, Some(_)) => word.push(c),
+            ('"', None) | ('\'', None) => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (c, None) if c.is_whitespace() => {
+                if in_word {
+                    words.push(core::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (c, None) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(ParseError::UnterminatedQuote);
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Why a command failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    /// The arguments were wrong; the command's usage is shown.
+    Usage,
+    Failed(String),
+}
+
+impl CommandError {
+    /// A failure described by `e`'s `Debug` output, for use with `map_err`.
+    pub fn failed(e: impl fmt::Debug) -> CommandError {
+        CommandError::Failed(format!("{:?}", e))
+    }
+}
+
+/// Lets handlers use `?` on `write!`.
+impl From<fmt::Error> for CommandError {
+    fn from(_: fmt::Error) -> Self {
+        CommandError::Failed("couldn't write output".into())
+    }
+}
+
+/// The words of a command line, after the command's name.
+#[derive(Clone, Copy, Debug)]
+pub struct Args<'a> {
+    words: &'a [String],
+}
+
+impl<'a> Args<'a> {
+    pub fn new(words: &'a [String]) -> Self {
+        Args { words }
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&'a str> {
+        self.words.get(index).map(String::as_str)
+    }
+
+    /// The argument at `index`, or a usage error if there aren't that many.
+    pub fn required(&self, index: usize) -> Result<&'a str, CommandError> {
+        self.get(index).ok_or(CommandError::Usage)
+    }
+
+    /// Parse the argument at `index`.
+    pub fn parse<T: FromStr>(&self, index: usize) -> Result<T, CommandError> {
+        let arg = self.required(index)?;
+        arg.parse()
+            .map_err(|_| CommandError::Failed(format!("invalid argument {:?}", arg)))
+    }
+
+    /// The arguments from `index` on.
+    pub fn skip(&self, index: usize) -> Args<'a> {
+        Args {
+            words: self.words.get(index..).unwrap_or(&[]),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'a str> {
+        self.words.iter().map(String::as_str)
+    }
+}
+
+/// Runs a command, writing its output to the terminal.
+pub type Handler = Box<dyn FnMut(Args<'_>, &mut dyn fmt::Write) -> Result<(), CommandError> + Send>;
+
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    help: &'static str,
+    handler: Handler,
+}
+
+/// Why a line didn't run successfully.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExecuteError {
+    Parse(ParseError),
+    /// No command has this name.
+    Unknown(String),
+    Command(CommandError),
+}
+
+/// The registered commands, and a built-in `help` listing them.
+#[derive(Default)]
+pub struct Commands {
+    /// Sorted by name.
+    commands: Vec<Command>,
+}
+
+impl Commands {
+    pub fn new() -> Self {
+        Commands {
+            commands: Vec::new(),
+        }
+    }
+
+    /// Add a command, replacing any with the same name. `usage` describes
+    /// the arguments, e.g. `"get|set <key> [value]"`, and `help` is a
+    /// one-line description.
+    pub fn register<F>(
+        &mut self,
+        name: &'static str,
+        usage: &'static str,
+        help: &'static str,
+        handler: F,
+    ) where
+        F: FnMut(Args<'_>, &mut dyn fmt::Write) -> Result<(), CommandError> + Send + 'static,
+    {
+        let command = Command {
+            name,
+            usage,
+            help,
+            handler: Box::new(handler),
+        };
+        match self.commands.binary_search_by(|c| c.name.cmp(name)) {
+            Ok(i) => self.commands[i] = command,
+            Err(i) => self.commands.insert(i, command),
+        }
+    }
+
+    /// Run `line`, writing the command's output, or why it failed, to
+    /// `out`. Empty lines do nothing.
+    pub fn execute(&mut self, line: &str, out: &mut dyn fmt::Write) -> Result<(), ExecuteError> {
+        let result = self.dispatch(line, out);
+        let _ = match &result {
+            Ok(()) => Ok(()),
+            Err(ExecuteError::Parse(e)) => writeln!(out, "error: {}", e),
+            Err(ExecuteError::Unknown(name)) => {
+                writeln!(out, "unknown command {:?}; try \"help\"", name)
+            }
+            Err(ExecuteError::Command(CommandError::Usage)) => {
+                let words = split_args(line).unwrap_or_default();
+                self.write_usage(&words[0], out)
+            }
+            Err(ExecuteError::Command(CommandError::Failed(message))) => {
+                writeln!(out, "error: {}", message)
+            }
+        };
+        result
+    }
+
+    fn dispatch(&mut self, line: &str, out: &mut dyn fmt::Write) -> Result<(), ExecuteError> {
+        let words = split_args(line).map_err(ExecuteError::Parse)?;
+        let name = match words.first() {
+            Some(name) => name.as_str(),
+            None => return Ok(()),
+        };
+        let args = Args::new(&words[1..]);
+        if name == "help" {
+            return self.help(args, out).map_err(ExecuteError::Command);
+        }
+        let command = self
+            .commands
+            .iter_mut()
+            .find(|c| c.name == name)
+            .ok_or_else(|| ExecuteError::Unknown(name.into()))?;
+        (command.handler)(args, out).map_err(ExecuteError::Command)
+    }
+
+    fn help(&self, args: Args<'_>, out: &mut dyn fmt::Write) -> Result<(), CommandError> {
+        let _ = match args.get(0) {
+            Some(name) => match self.commands.iter().find(|c| c.name == name) {
+                Some(command) => self
+                    .write_usage(name, out)
+                    .and_then(|_| writeln!(out, "{}", command.help)),
+                None => return Err(CommandError::Failed(format!("no command {:?}", name))),
+            },
+            None => self.write_list(out),
+        };
+        Ok(())
+    }
+
+    fn write_usage(&self, name: &str, out: &mut dyn fmt::Write) -> fmt::Result {
+        match self.commands.iter().find(|c| c.name == name) {
+            Some(command) if !command.usage.is_empty() => {
+                writeln!(out, "usage: {} {}", name, command.usage)
+            }
+            _ if name == "help" => writeln!(out, "usage: help [command]"),
+            _ => writeln!(out, "usage: {}", name),
+        }
+    }
+
+    fn write_list(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        let synopses: Vec<(String, &str)> = self
+            .commands
+            .iter()
+            .map(|c| (format!("{} {}", c.name, c.usage), c.help))
+            .chain(core::iter::once((
+                String::from("help [command]"),
+                "Show the commands, or how to use one",
+            )))
+            .collect();
+        let width = synopses.iter().map(|(s, _)| s.len()).max().unwrap_or(0);
+        for (synopsis, help) in &synopses {
+            writeln!(
+                out,
+                "  {:width$}  {}",
+                synopsis.trim_end(),
+                help,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What a key press completed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Enter was pressed.
+    Line(String),
+    /// Ctrl-C was pressed, discarding the line.
+    Interrupt,
+}
+
+/// Where the editor is in an escape sequence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After ESC.
+    Start,
+    /// After `ESC [`, with the numeric parameter so far.
+    Csi(u8),
+    /// After `ESC O`.
+    Ss3,
+}
+
+/// Edits a line as it's typed: inserting at the cursor, Backspace and
+/// Delete, Left and Right, Home and End (or Ctrl-A and Ctrl-E), Ctrl-U to
+/// clear, and Up and Down through the history.
+pub struct LineEditor {
+    prompt: String,
+    line: Vec<u8>,
+    cursor: usize,
+    history: VecDeque<String>,
+    history_len: usize,
+    /// The history entry being shown; `history.len()` for the new line.
+    browsing: usize,
+    /// The new line, kept while browsing the history.
+    draft: Vec<u8>,
+    escape: Escape,
+    /// To treat CR LF as one Enter.
+    after_cr: bool,
+}
+
+impl LineEditor {
+    /// Keep up to `history_len` previous lines.
+    pub fn new(prompt: &str, history_len: usize) -> Self {
+        LineEditor {
+            prompt: prompt.into(),
+            line: Vec::new(),
+            cursor: 0,
+            history: VecDeque::new(),
+            history_len,
+            browsing: 0,
+            draft: Vec::new(),
+            escape: Escape::None,
+            after_cr: false,
+        }
+    }
+
+    /// Print the prompt, to start a line.
+    pub fn prompt(&self, out: &mut dyn fmt::Write) {
+        let _ = out.write_str(&self.prompt);
+    }
+
+    /// The previous lines, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(String::as_str)
+    }
+
+    /// Handle a byte from the terminal, echoing to `out`.
+    pub fn feed(&mut self, byte: u8, out: &mut dyn fmt::Write) -> Option<Event> {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match self.escape {
+            Escape::None => {}
+            Escape::Start => {
+                self.escape = match byte {
+                    b'[' => Escape::Csi(0),
+                    b'O' => Escape::Ss3,
+                    _ => Escape::None,
+                };
+                return None;
+            }
+            Escape::Csi(param) => {
+                if byte.is_ascii_digit() {
+                    self.escape = Escape::Csi(param.saturating_mul(10).saturating_add(byte - b'0'));
+                } else {
+                    self.escape = Escape::None;
+                    self.csi(param, byte, out);
+                }
+                return None;
+            }
+            Escape::Ss3 => {
+                self.escape = Escape::None;
+                self.csi(0, byte, out);
+                return None;
+            }
+        }
+
+        match byte {
+            b'\n' if after_cr => {}
+            b'\r' | b'\n' => {
+                let _ = out.write_str("\n");
+                return Some(Event::Line(self.finish_line()));
+            }
+            CTRL_C => {
+                let _ = out.write_str("^C\n");
+                self.discard_line();
+                return Some(Event::Interrupt);
+            }
+            BACKSPACE | DEL if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+                self.redraw(out);
+            }
+            CTRL_A => self.move_to(0, out),
+            CTRL_E => self.move_to(self.line.len(), out),
+            CTRL_U => {
+                self.line.clear();
+                self.cursor = 0;
+                self.redraw(out);
+            }
+            ESC => self.escape = Escape::Start,
+            b' '..=b'~' if self.line.len() < MAX_LINE => {
+                self.line.insert(self.cursor, byte);
+                self.cursor += 1;
+                if self.cursor == self.line.len() {
+                    let _ = out.write_char(byte as char);
+                } else {
+                    self.redraw(out);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Handle the final byte of an escape sequence.
+    fn csi(&mut self, param: u8, byte: u8, out: &mut dyn fmt::Write) {
+        match (byte, param) {
+            (b'A', _) => self.browse_back(out),
+            (b'B', _) => self.browse_forward(out),
+            (b'C', _) => self.move_to((self.cursor + 1).min(self.line.len()), out),
+            (b'D', _) => self.move_to(self.cursor.saturating_sub(1), out),
+            (b'H', _) | (b'~', 1) | (b'~', 7) => self.move_to(0, out),
+            (b'F', _) | (b'~', 4) | (b'~', 8) => self.move_to(self.line.len(), out),
+            (b'~', 3) if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+                self.redraw(out);
+            }
+            _ => {}
+        }
+    }
+
+    fn move_to(&mut self, cursor: usize, out: &mut dyn fmt::Write) {
+        if cursor != self.cursor {
+            self.cursor = cursor;
+            self.redraw(out);
+        }
+    }
+
+    fn browse_back(&mut self, out: &mut dyn fmt::Write) {
+        if self.browsing == 0 {
+            return;
+        }
+        if self.browsing == self.history.len() {
+            self.draft = core::mem::take(&mut self.line);
+        }
+        self.browsing -= 1;
+        self.show(self.history[self.browsing].as_bytes().to_vec(), out);
+    }
+
+    fn browse_forward(&mut self, out: &mut dyn fmt::Write) {
+        if self.browsing >= self.history.len() {
+            return;
+        }
+        self.browsing += 1;
+        let line = if self.browsing == self.history.len() {
+            core::mem::take(&mut self.draft)
+        } else {
+            self.history[self.browsing].as_bytes().to_vec()
+        };
+        self.show(line, out);
+    }
+
+    fn show(&mut self, line: Vec<u8>, out: &mut dyn fmt::Write) {
+        self.line = line;
+        self.cursor = self.line.len();
+        self.redraw(out);
+    }
+
+    /// Rewrite the whole line and put the cursor back.
+    fn redraw(&self, out: &mut dyn fmt::Write) {
+        let line = core::str::from_utf8(&self.line).unwrap_or_default();
+        let _ = write!(out, "\r{}{}\x1b[K", self.prompt, line);
+        if self.cursor < self.line.len() {
+            let _ = write!(out, "\x1b[{}D", self.line.len() - self.cursor);
+        }
+    }
+
+    /// Start a new line without keeping the current one.
+    fn discard_line(&mut self) {
+        self.line.clear();
+        self.cursor = 0;
+        self.draft.clear();
+        self.browsing = self.history.len();
+    }
+
+    /// Take the line, adding it to the history, and start a new one.
+    fn finish_line(&mut self) -> String {
+        let line = String::from_utf8(core::mem::take(&mut self.line)).unwrap_or_default();
+        self.cursor = 0;
+        self.draft.clear();
+        let repeated = self.history.back() == Some(&line);
+        if !line.trim().is_empty() && !repeated && self.history_len > 0 {
+            if self.history.len() == self.history_len {
+                self.history.pop_front();
+            }
+            self.history.push_back(line.clone());
+        }
+        self.browsing = self.history.len();
+        line
+    }
+}
+
+/// A line editor running commands: feed it the terminal's input, and it
+/// writes the terminal's output.
+pub struct Repl {
+    editor: LineEditor,
+    commands: Commands,
+}
+
+impl Repl {
+    pub fn new(editor: LineEditor, commands: Commands) -> Self {
+        Repl { editor, commands }
+    }
+
+    /// Print the first prompt.
+    pub fn start(&self, out: &mut dyn fmt::Write) {
+        self.editor.prompt(out);
+    }
+
+    /// Handle a byte from the terminal. If it completed a line, the line is
+    /// run and its result returned.
+    pub fn feed(&mut self, byte: u8, out: &mut dyn fmt::Write) -> Option<Result<(), ExecuteError>> {
+        let result = match self.editor.feed(byte, out)? {
+            Event::Line(line) => Some(self.commands.execute(&line, out)),
+            Event::Interrupt => None,
+        };
+        self.editor.prompt(out);
+        result
+    }
+
+    pub fn editor(&self) -> &LineEditor {
+        &self.editor
+    }
+
+    pub fn commands(&mut self) -> &mut Commands {
+        &mut self.commands
+    }
+}
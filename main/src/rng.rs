@@ -0,0 +1,52 @@
+//! The hardware random number generator, for use with `rand`.
+//!
+//! The ESP32's RNG only produces true random numbers while the RF subsystem
+//! is running, i.e. while WiFi or Bluetooth is enabled. Otherwise its output
+//! is pseudo-random: fine for jitter and IDs, but not for keys or nonces.
+//! See the "Random Number Generator" chapter of the ESP32 technical
+//! reference manual.
+
+use rand_core::{impls, CryptoRng, Error, RngCore, SeedableRng};
+
+/// Random numbers from `esp_random`. It's cheap to create and has no
+/// state, so there's no need to share one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EspRng;
+
+impl EspRng {
+    pub fn new() -> Self {
+        EspRng
+    }
+}
+
+impl RngCore for EspRng {
+    fn next_u32(&mut self) -> u32 {
+        unsafe { esp_idf_sys::esp_random() }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_u32(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        unsafe { esp_idf_sys::esp_fill_random(dest.as_mut_ptr() as *mut _, dest.len() as _) }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Only cryptographically secure while WiFi or Bluetooth is enabled; see
+/// the module documentation.
+impl CryptoRng for EspRng {}
+
+/// Seed a software PRNG, such as `rand_xoshiro::Xoshiro128PlusPlus`, from
+/// the hardware RNG. Software PRNGs are much faster when many numbers are
+/// needed.
+pub fn seeded<R: SeedableRng>() -> R {
+    let mut seed = R::Seed::default();
+    EspRng.fill_bytes(seed.as_mut());
+    R::from_seed(seed)
+}
@@ -0,0 +1,364 @@
+//! Wi-Fi provisioning with SmartConfig, where a phone app broadcasts the
+//! network's credentials in specially crafted packets.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use esp_idf_hal::errors::EspError;
+use esp_idf_sys::types::c_void;
+
+use crate::freertos_units::{Duration, DurationTicks};
+use crate::net::{Ipv4Addr, MacAddress};
+
+const CONNECTED_BIT: esp_idf_sys::EventBits_t = esp_idf_sys::BIT0;
+const ACK_DONE_BIT: esp_idf_sys::EventBits_t = esp_idf_sys::BIT1;
+
+/// The protocol the phone app uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    EspTouch,
+    AirKiss,
+    /// Listen for both ESPTouch and AirKiss.
+    EspTouchAirKiss,
+    /// ESPTouch V2. The SmartConfig library in this version of ESP-IDF
+    /// doesn't implement it, so starting with it fails with
+    /// `ESP_ERR_NOT_SUPPORTED`.
+    EspTouchV2,
+}
+
+impl Protocol {
+    fn to_raw(self) -> Option<esp_idf_sys::smartconfig_type_t> {
+        match self {
+            Protocol::EspTouch => Some(esp_idf_sys::smartconfig_type_t_SC_TYPE_ESPTOUCH),
+            Protocol::AirKiss => Some(esp_idf_sys::smartconfig_type_t_SC_TYPE_AIRKISS),
+            Protocol::EspTouchAirKiss => {
+                Some(esp_idf_sys::smartconfig_type_t_SC_TYPE_ESPTOUCH_AIRKISS)
+            }
+            Protocol::EspTouchV2 => None,
+        }
+    }
+
+    fn from_raw(ty: esp_idf_sys::smartconfig_type_t) -> Self {
+        match ty {
+            esp_idf_sys::smartconfig_type_t_SC_TYPE_AIRKISS => Protocol::AirKiss,
+            _ => Protocol::EspTouch,
+        }
+    }
+}
+
+/// Credentials received from the phone.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ProvisioningResult {
+    pub ssid: String,
+    pub password: String,
+    /// The access point the phone is connected to, if it sent it.
+    pub bssid: Option<MacAddress>,
+    /// The phone's address, which ESPTouch sends along so the device can
+    /// acknowledge it.
+    pub phone_ip: Option<Ipv4Addr>,
+    /// The protocol the credentials arrived with.
+    pub protocol: Protocol,
+}
+
+// Hand-written so the password doesn't end up in logs by accident.
+impl core::fmt::Debug for ProvisioningResult {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ProvisioningResult")
+            .field("ssid", &self.ssid)
+            .field("password", &"<hidden>")
+            .field("bssid", &self.bssid)
+            .field("phone_ip", &self.phone_ip)
+            .field("protocol", &self.protocol)
+            .finish()
+    }
+}
+
+/// Helper for starting SmartConfig. Instantiate with [`SmartConfig::new()`].
+///
+/// [`SmartConfig::new()`]: struct.SmartConfig.html#method.new
+pub struct SmartConfigBuilder {
+    protocol: Protocol,
+    timeout: Duration,
+    print_password: bool,
+    enable_log: bool,
+}
+
+impl SmartConfigBuilder {
+    /// Set the protocol to listen for. Defaults to ESPTouch.
+    pub fn protocol(self, protocol: Protocol) -> Self {
+        SmartConfigBuilder { protocol, ..self }
+    }
+
+    /// Set how long the whole process, from listening to the phone being
+    /// acknowledged, may take. Defaults to two minutes.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        SmartConfigBuilder { timeout, ..self }
+    }
+
+    /// Print the received password to the console. Off by default.
+    pub fn print_password(self, print_password: bool) -> Self {
+        SmartConfigBuilder {
+            print_password,
+            ..self
+        }
+    }
+
+    /// Enable the SmartConfig library's own logging.
+    pub fn enable_log(self, enable_log: bool) -> Self {
+        SmartConfigBuilder { enable_log, ..self }
+    }
+
+    /// Start listening for credentials. The Wi-Fi driver must have been
+    /// started in station mode.
+    ///
+    /// Once credentials arrive, the station connects with them. Use
+    /// [`SmartConfig::wait()`] to get the result.
+    ///
+    /// [`SmartConfig::wait()`]: struct.SmartConfig.html#method.wait
+    pub fn start(self) -> Result<SmartConfig, EspError> {
+        let ty = self.protocol.to_raw().ok_or(EspError(
+            esp_idf_sys::ESP_ERR_NOT_SUPPORTED as esp_idf_sys::esp_err_t,
+        ))?;
+
+        if ACTIVE.swap(true, Ordering::AcqRel) {
+            return Err(EspError(
+                esp_idf_sys::ESP_ERR_INVALID_STATE as esp_idf_sys::esp_err_t,
+            ));
+        }
+
+        let event_group = unsafe { esp_idf_sys::xEventGroupCreate() };
+        if event_group.is_null() {
+            ACTIVE.store(false, Ordering::Release);
+            return Err(EspError(
+                esp_idf_sys::ESP_ERR_NO_MEM as esp_idf_sys::esp_err_t,
+            ));
+        }
+
+        let mut handle = SmartConfig {
+            shared: Box::new(Shared {
+                event_group,
+                print_password: self.print_password,
+                got_credentials: AtomicBool::new(false),
+                result: UnsafeCell::new(None),
+            }),
+            deadline: unsafe { esp_idf_sys::xTaskGetTickCount() }
+                .wrapping_add(self.timeout.to_ticks()),
+            timeout: self.timeout,
+            registered: 0,
+        };
+        handle.register()?;
+
+        unsafe {
+            EspError(esp_idf_sys::esp_smartconfig_set_type(ty)).into_result()?;
+            let cfg = esp_idf_sys::smartconfig_start_config_t {
+                enable_log: self.enable_log,
+            };
+            EspError(esp_idf_sys::esp_smartconfig_start(&cfg)).into_result()?;
+        }
+        Ok(handle)
+    }
+}
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// State shared with the event handler.
+struct Shared {
+    event_group: esp_idf_sys::EventGroupHandle_t,
+    print_password: bool,
+    /// Set by the event handler once it has written `result`.
+    got_credentials: AtomicBool,
+    result: UnsafeCell<Option<ProvisioningResult>>,
+}
+
+/// Events the handler listens to, as `(base, id)` pairs.
+unsafe fn events() -> [(esp_idf_sys::esp_event_base_t, u32); 4] {
+    [
+        (
+            esp_idf_sys::SC_EVENT,
+            esp_idf_sys::smartconfig_event_t_SC_EVENT_GOT_SSID_PSWD,
+        ),
+        (
+            esp_idf_sys::SC_EVENT,
+            esp_idf_sys::smartconfig_event_t_SC_EVENT_SEND_ACK_DONE,
+        ),
+        (
+            esp_idf_sys::WIFI_EVENT,
+            esp_idf_sys::wifi_event_t_WIFI_EVENT_STA_DISCONNECTED,
+        ),
+        (
+            esp_idf_sys::IP_EVENT,
+            esp_idf_sys::ip_event_t_IP_EVENT_STA_GOT_IP,
+        ),
+    ]
+}
+
+/// Handle for a running SmartConfig session, returned by
+/// [`SmartConfigBuilder::start()`]. SmartConfig stops when this is dropped.
+///
+/// [`SmartConfigBuilder::start()`]: struct.SmartConfigBuilder.html#method.start
+pub struct SmartConfig {
+    shared: Box<Shared>,
+    deadline: esp_idf_sys::TickType_t,
+    timeout: Duration,
+    /// How many of `events()` have been registered.
+    registered: usize,
+}
+unsafe impl Send for SmartConfig {}
+
+impl SmartConfig {
+    /// Prepare a builder object for SmartConfig.
+    pub fn new() -> SmartConfigBuilder {
+        SmartConfigBuilder {
+            protocol: Protocol::EspTouch,
+            timeout: Duration::ms(120_000),
+            print_password: false,
+            enable_log: false,
+        }
+    }
+
+    fn register(&mut self) -> Result<(), EspError> {
+        for &(base, id) in unsafe { events() }.iter() {
+            EspError(unsafe {
+                esp_idf_sys::esp_event_handler_register(
+                    base,
+                    id as i32,
+                    Some(event_handler),
+                    &*self.shared as *const Shared as *mut c_void,
+                )
+            })
+            .into_result()?;
+            self.registered += 1;
+        }
+        Ok(())
+    }
+
+    /// Whether the station has connected with the received credentials.
+    pub fn is_connected(&self) -> bool {
+        let bits = unsafe { esp_idf_sys::xEventGroupClearBits(self.shared.event_group, 0) };
+        bits & CONNECTED_BIT != 0
+    }
+
+    /// Wait until the station has connected with the received credentials and
+    /// the phone has been acknowledged, or fail with `ESP_ERR_TIMEOUT` once
+    /// the timeout given to the builder has passed.
+    pub fn wait(self) -> Result<ProvisioningResult, EspError> {
+        let now = unsafe { esp_idf_sys::xTaskGetTickCount() };
+        // The deadline is in the past if more than the whole timeout has
+        // elapsed since it was computed.
+        let remaining = self.deadline.wrapping_sub(now);
+        let remaining = if remaining > self.timeout.to_ticks() {
+            0
+        } else {
+            remaining
+        };
+
+        let bits = unsafe {
+            esp_idf_sys::xEventGroupWaitBits(
+                self.shared.event_group,
+                CONNECTED_BIT | ACK_DONE_BIT,
+                0,
+                1,
+                remaining,
+            )
+        };
+        if bits & (CONNECTED_BIT | ACK_DONE_BIT) != CONNECTED_BIT | ACK_DONE_BIT {
+            return Err(EspError(
+                esp_idf_sys::ESP_ERR_TIMEOUT as esp_idf_sys::esp_err_t,
+            ));
+        }
+        // The handler doesn't touch the result again once it has set
+        // `got_credentials`.
+        let result = unsafe { (*self.shared.result.get()).take() };
+        result.ok_or(EspError(esp_idf_sys::ESP_FAIL))
+    }
+}
+
+impl Drop for SmartConfig {
+    fn drop(&mut self) {
+        unsafe {
+            esp_idf_sys::esp_smartconfig_stop();
+            for &(base, id) in events().iter().take(self.registered) {
+                esp_idf_sys::esp_event_handler_unregister(base, id as i32, Some(event_handler));
+            }
+            esp_idf_sys::vEventGroupDelete(self.shared.event_group);
+        }
+        ACTIVE.store(false, Ordering::Release);
+    }
+}
+
+fn nul_terminated(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into()
+}
+
+extern "C" fn event_handler(
+    arg: *mut c_void,
+    event_base: esp_idf_sys::esp_event_base_t,
+    event_id: i32,
+    event_data: *mut c_void,
+) {
+    let shared = unsafe { &*(arg as *const Shared) };
+    let event_id = event_id as u32;
+    unsafe {
+        if event_base == esp_idf_sys::SC_EVENT
+            && event_id == esp_idf_sys::smartconfig_event_t_SC_EVENT_GOT_SSID_PSWD
+        {
+            let evt = &*(event_data as *const esp_idf_sys::smartconfig_event_got_ssid_pswd_t);
+            let result = ProvisioningResult {
+                ssid: nul_terminated(&evt.ssid),
+                password: nul_terminated(&evt.password),
+                bssid: if evt.bssid_set {
+                    Some(MacAddress(evt.bssid))
+                } else {
+                    None
+                },
+                phone_ip: if evt.cellphone_ip == [0; 4] {
+                    None
+                } else {
+                    Some(Ipv4Addr::from(evt.cellphone_ip))
+                },
+                protocol: Protocol::from_raw(evt.type_),
+            };
+
+            crate::println!("SmartConfig got SSID {:?}", result.ssid);
+            if shared.print_password {
+                crate::println!("SmartConfig got password {:?}", result.password);
+            }
+
+            let mut wifi_config: esp_idf_sys::wifi_config_t = core::mem::zeroed();
+            wifi_config.sta.ssid = evt.ssid;
+            wifi_config.sta.password = evt.password;
+            wifi_config.sta.bssid_set = evt.bssid_set;
+            wifi_config.sta.bssid = evt.bssid;
+            if !shared.got_credentials.load(Ordering::Acquire) {
+                *shared.result.get() = Some(result);
+                shared.got_credentials.store(true, Ordering::Release);
+            }
+
+            esp_idf_sys::esp_wifi_disconnect();
+            esp_idf_sys::esp_wifi_set_config(
+                esp_idf_sys::esp_interface_t_ESP_IF_WIFI_STA,
+                &mut wifi_config,
+            );
+            esp_idf_sys::esp_wifi_connect();
+        } else if event_base == esp_idf_sys::SC_EVENT
+            && event_id == esp_idf_sys::smartconfig_event_t_SC_EVENT_SEND_ACK_DONE
+        {
+            esp_idf_sys::xEventGroupSetBits(shared.event_group, ACK_DONE_BIT);
+        } else if event_base == esp_idf_sys::WIFI_EVENT
+            && event_id == esp_idf_sys::wifi_event_t_WIFI_EVENT_STA_DISCONNECTED
+        {
+            // Keep retrying with the received credentials until the timeout.
+            if shared.got_credentials.load(Ordering::Acquire) {
+                esp_idf_sys::xEventGroupClearBits(shared.event_group, CONNECTED_BIT);
+                esp_idf_sys::esp_wifi_connect();
+            }
+        } else if event_base == esp_idf_sys::IP_EVENT
+            && event_id == esp_idf_sys::ip_event_t_IP_EVENT_STA_GOT_IP
+            && shared.got_credentials.load(Ordering::Acquire)
+        {
+            esp_idf_sys::xEventGroupSetBits(shared.event_group, CONNECTED_BIT);
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! Promiscuous-mode packet capture.
+//!
+//! Frames are copied out of the Wi-Fi driver's callback into a lock-free
+//! queue, and handed to a Rust callback on a dedicated dispatcher task, which
+//! also takes care of channel hopping.
+
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+use esp_idf_hal::errors::EspError;
+use esp_idf_sys::types::c_void;
+
+use crate::dispatcher::{Dispatcher, Slot};
+use crate::freertos_task::TaskPriority;
+use crate::freertos_units::Duration;
+use crate::ieee80211::{FrameHeader, ParseError};
+use crate::pcap::{PcapWriter, PhyRate, RadioInfo, Sink};
+use crate::uart::{Port, Uart};
+
+/// Number of bytes of each frame that are kept.
+pub const MAX_CAPTURE_LEN: usize = 256;
+
+/// Which kinds of frames to capture.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub struct FrameFilter(u32);
+
+impl FrameFilter {
+    pub const ALL: FrameFilter = FrameFilter(esp_idf_sys::WIFI_PROMIS_FILTER_MASK_ALL);
+    pub const MGMT: FrameFilter = FrameFilter(esp_idf_sys::WIFI_PROMIS_FILTER_MASK_MGMT);
+    pub const CTRL: FrameFilter = FrameFilter(esp_idf_sys::WIFI_PROMIS_FILTER_MASK_CTRL);
+    pub const DATA: FrameFilter = FrameFilter(esp_idf_sys::WIFI_PROMIS_FILTER_MASK_DATA);
+    pub const MISC: FrameFilter = FrameFilter(esp_idf_sys::WIFI_PROMIS_FILTER_MASK_MISC);
+}
+
+impl core::ops::BitOr for FrameFilter {
+    type Output = FrameFilter;
+
+    fn bitor(self, rhs: FrameFilter) -> FrameFilter {
+        FrameFilter(self.0 | rhs.0)
+    }
+}
+
+/// The type of buffer reported by the driver.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum PacketType {
+    Mgmt,
+    Ctrl,
+    Data,
+    /// E.g. MIMO frames, which carry no payload.
+    Misc,
+}
+
+/// A frame captured in promiscuous mode.
+pub struct Frame {
+    pub packet_type: PacketType,
+    /// Signal strength in dBm.
+    pub rssi: i8,
+    /// Noise floor in dBm.
+    pub noise_floor: i8,
+    pub channel: u8,
+    pub rate: Option<PhyRate>,
+    /// Time since boot at which the frame was captured, in microseconds.
+    pub timestamp_us: u64,
+    /// Length of the frame on air, including the FCS.
+    pub len: usize,
+    captured: usize,
+    data: [u8; MAX_CAPTURE_LEN],
+}
+
+impl Frame {
+    /// The captured bytes of the frame, starting with the MAC header. At most
+    /// `MAX_CAPTURE_LEN` bytes are kept.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.captured]
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.captured < self.len
+    }
+
+    /// Parse the frame's MAC header.
+    pub fn header(&self) -> Result<FrameHeader, ParseError> {
+        FrameHeader::parse(self.data())
+    }
+
+    /// Receive metadata in the form used by the pcap writer.
+    pub fn radio_info(&self) -> RadioInfo {
+        RadioInfo {
+            tsft: self.timestamp_us,
+            rate: self.rate,
+            channel: self.channel,
+            rssi: self.rssi,
+            noise_floor: self.noise_floor,
+            has_fcs: !self.is_truncated() && self.len >= 4,
+        }
+    }
+}
+
+/// Convert the driver's legacy rate code into units of 500 kbps.
+fn legacy_rate(code: u32) -> Option<u8> {
+    Some(match code {
+        0x00 | 0x04 => 2,
+        0x01 | 0x05 => 4,
+        0x02 | 0x06 => 11,
+        0x03 | 0x07 => 22,
+        0x08 => 96,
+        0x09 => 48,
+        0x0a => 24,
+        0x0b => 12,
+        0x0c => 108,
+        0x0d => 72,
+        0x0e => 36,
+        0x0f => 18,
+        _ => return None,
+    })
+}
+
+/// Where the promiscuous RX callback sends frames. There can only be one
+/// sniffer, since promiscuous mode is global to the driver.
+static SNIFFER: Slot<Frame> = Slot::new();
+
+/// Helper for starting a sniffer. Instantiate with [`Sniffer::new()`].
+///
+/// [`Sniffer::new()`]: struct.Sniffer.html#method.new
+pub struct SnifferBuilder {
+    filter: FrameFilter,
+    channel: u8,
+    hop_channels: Vec<u8>,
+    hop_interval: Duration,
+    queue_len: usize,
+    task_stack_size: u32,
+    task_priority: TaskPriority,
+}
+
+impl SnifferBuilder {
+    /// Set which kinds of frames to capture.
+    pub fn filter(self, filter: FrameFilter) -> Self {
+        SnifferBuilder { filter, ..self }
+    }
+
+    /// Capture on a single channel.
+    pub fn channel(self, channel: u8) -> Self {
+        SnifferBuilder { channel, ..self }
+    }
+
+    /// Cycle through `channels`, staying on each for `interval`.
+    pub fn hop(self, channels: &[u8], interval: Duration) -> Self {
+        SnifferBuilder {
+            channel: channels.first().copied().unwrap_or(self.channel),
+            hop_channels: channels.to_vec(),
+            hop_interval: interval,
+            ..self
+        }
+    }
+
+    /// Set how many frames can be buffered before new ones are dropped.
+    pub fn queue_len(self, queue_len: usize) -> Self {
+        SnifferBuilder { queue_len, ..self }
+    }
+
+    /// Set the dispatcher task's stack size, in words.
+    pub fn stack_size(self, stack_size: u32) -> Self {
+        SnifferBuilder {
+            task_stack_size: stack_size,
+            ..self
+        }
+    }
+
+    /// Set the dispatcher task's priority.
+    pub fn priority(self, priority: TaskPriority) -> Self {
+        SnifferBuilder {
+            task_priority: priority,
+            ..self
+        }
+    }
+
+    /// Enable promiscuous mode and call `callback` on the dispatcher task for
+    /// every captured frame.
+    ///
+    /// The Wi-Fi driver must already be initialized and started.
+    pub fn start(
+        self,
+        mut callback: impl FnMut(&Frame) + Send + 'static,
+    ) -> Result<Sniffer, EspError> {
+        let channels = self.hop_channels;
+        let interval = self.hop_interval;
+        let mut next_hop =
+            unsafe { esp_idf_sys::esp_timer_get_time() } as u64 + interval.to_ms() as u64 * 1000;
+        let mut hop_idx = 0;
+        let dispatcher = Dispatcher::new()
+            .name("sniffer_task")
+            .stack_size(self.task_stack_size)
+            .priority(self.task_priority)
+            .queue_len(self.queue_len)
+            .wake_interval(if channels.len() > 1 {
+                interval
+            } else {
+                Duration::infinite()
+            })
+            .start(&SNIFFER, move |frames| {
+                while let Some(frame) = frames.pop() {
+                    callback(&frame);
+                }
+
+                let now = unsafe { esp_idf_sys::esp_timer_get_time() } as u64;
+                if channels.len() > 1 && now >= next_hop {
+                    hop_idx = (hop_idx + 1) % channels.len();
+                    let _ = set_channel(channels[hop_idx]);
+                    next_hop = now + interval.to_ms() as u64 * 1000;
+                }
+            })?;
+
+        // Dropping `sniffer` on an error below turns promiscuous mode back
+        // off.
+        let sniffer = Sniffer { dispatcher };
+        let filter = esp_idf_sys::wifi_promiscuous_filter_t {
+            filter_mask: self.filter.0,
+        };
+        unsafe {
+            EspError(esp_idf_sys::esp_wifi_set_promiscuous_filter(&filter)).into_result()?;
+            EspError(esp_idf_sys::esp_wifi_set_promiscuous_rx_cb(Some(
+                rx_callback,
+            )))
+            .into_result()?;
+            EspError(esp_idf_sys::esp_wifi_set_promiscuous(true)).into_result()?;
+        }
+        set_channel(self.channel)?;
+
+        Ok(sniffer)
+    }
+
+    /// Enable promiscuous mode and stream all captured frames as pcap to the
+    /// console UART, e.g. to pipe into Wireshark on the host.
+    ///
+    /// Anything else printed to the console will corrupt the stream, so
+    /// logging should be quiet while this runs. Unless a [`Console`] already
+    /// has the port, this installs the UART driver until the sniffer is
+    /// dropped, so start the console first if there is one.
+    ///
+    /// [`Console`]: ../console/struct.Console.html
+    pub fn start_pcap(self) -> Result<Sniffer, EspError> {
+        let mut writer = PcapWriter::new(ConsoleSink::new()?, MAX_CAPTURE_LEN as u32)?;
+        self.start(move |frame| {
+            let _ = writer.write_frame(
+                frame.timestamp_us,
+                &frame.radio_info(),
+                frame.data(),
+                frame.len,
+            );
+        })
+    }
+}
+
+/// Handle for a running promiscuous-mode capture. Capturing stops when this is
+/// dropped.
+pub struct Sniffer {
+    dispatcher: Dispatcher<Frame>,
+}
+
+impl Sniffer {
+    /// Prepare a builder object for the sniffer.
+    pub fn new() -> SnifferBuilder {
+        SnifferBuilder {
+            filter: FrameFilter::ALL,
+            channel: 1,
+            hop_channels: Vec::new(),
+            hop_interval: Duration::ms(250),
+            queue_len: 32,
+            task_stack_size: 4096,
+            task_priority: TaskPriority(2),
+        }
+    }
+
+    /// Number of frames dropped because the dispatcher fell behind.
+    pub fn dropped(&self) -> u32 {
+        self.dispatcher.dropped()
+    }
+}
+
+impl Drop for Sniffer {
+    fn drop(&mut self) {
+        unsafe {
+            esp_idf_sys::esp_wifi_set_promiscuous(false);
+            esp_idf_sys::esp_wifi_set_promiscuous_rx_cb(None);
+        }
+        // `dispatcher` is dropped next, which waits for a callback that's
+        // still running.
+    }
+}
+
+fn set_channel(channel: u8) -> Result<(), EspError> {
+    EspError(unsafe {
+        esp_idf_sys::esp_wifi_set_channel(
+            channel,
+            esp_idf_sys::wifi_second_chan_t_WIFI_SECOND_CHAN_NONE,
+        )
+    })
+    .into_result()
+}
+
+extern "C" fn rx_callback(buf: *mut c_void, ty: esp_idf_sys::wifi_promiscuous_pkt_type_t) {
+    if buf.is_null() {
+        return;
+    }
+
+    let pkt = unsafe { &*(buf as *const esp_idf_sys::wifi_promiscuous_pkt_t) };
+    let rx = &pkt.rx_ctrl;
+    let packet_type = match ty {
+        esp_idf_sys::wifi_promiscuous_pkt_type_t_WIFI_PKT_MGMT => PacketType::Mgmt,
+        esp_idf_sys::wifi_promiscuous_pkt_type_t_WIFI_PKT_CTRL => PacketType::Ctrl,
+        esp_idf_sys::wifi_promiscuous_pkt_type_t_WIFI_PKT_DATA => PacketType::Data,
+        _ => PacketType::Misc,
+    };
+    let len = if packet_type == PacketType::Misc {
+        0
+    } else {
+        rx.sig_len() as usize
+    };
+    let captured = core::cmp::min(len, MAX_CAPTURE_LEN);
+    let rate = match rx.sig_mode() {
+        0 => legacy_rate(rx.rate()).map(PhyRate::Legacy),
+        _ => Some(PhyRate::Ht {
+            mcs: rx.mcs() as u8,
+            ht40: rx.cwb() != 0,
+            short_gi: rx.sgi() != 0,
+        }),
+    };
+
+    // Build the frame directly in the queue, since it's too large to
+    // comfortably put on the Wi-Fi task's stack.
+    unsafe {
+        SNIFFER.feed(|slot: &mut MaybeUninit<Frame>| {
+            let frame = slot.as_mut_ptr();
+            (*frame).packet_type = packet_type;
+            (*frame).rssi = rx.rssi() as i8;
+            (*frame).noise_floor = rx.noise_floor() as i8;
+            (*frame).channel = rx.channel() as u8;
+            (*frame).rate = rate;
+            (*frame).timestamp_us = esp_idf_sys::esp_timer_get_time() as u64;
+            (*frame).len = len;
+            (*frame).captured = captured;
+            let data = &mut (*frame).data;
+            data[..captured]
+                .copy_from_slice(core::slice::from_raw_parts(pkt.payload.as_ptr(), captured));
+        })
+    };
+}
+
+/// Writes raw bytes to the console UART, bypassing the line-ending translation
+/// done for `stdout`.
+struct ConsoleSink {
+    port: esp_idf_sys::uart_port_t,
+    /// The driver, if the sink installed it rather than sharing one that
+    /// was already installed, e.g. by the console.
+    uart: Option<Uart>,
+}
+
+impl ConsoleSink {
+    fn new() -> Result<Self, EspError> {
+        let port = match esp_idf_sys::CONFIG_ESP_CONSOLE_UART_NUM {
+            0 => Port::Uart0,
+            1 => Port::Uart1,
+            _ => Port::Uart2,
+        };
+        let mut sink = ConsoleSink {
+            port: port.to_raw(),
+            uart: None,
+        };
+        if !unsafe { esp_idf_sys::uart_is_driver_installed(sink.port) } {
+            sink.uart = Some(
+                Uart::new(port)
+                    .baud_rate(esp_idf_sys::CONFIG_ESP_CONSOLE_UART_BAUDRATE)
+                    .rx_buffer_size(256)
+                    .tx_buffer_size(4096)
+                    .start()?,
+            );
+            // Writing to the port's FIFO directly would race with the driver.
+            unsafe { esp_idf_sys::esp_vfs_dev_uart_use_driver(sink.port as _) };
+        }
+        Ok(sink)
+    }
+}
+
+impl Drop for ConsoleSink {
+    fn drop(&mut self) {
+        if self.uart.is_some() {
+            // Before `uart` deletes the driver.
+            unsafe { esp_idf_sys::esp_vfs_dev_uart_use_nonblocking(self.port as _) };
+        }
+    }
+}
+
+impl Sink for ConsoleSink {
+    type Error = EspError;
+
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<(), EspError> {
+        while !buf.is_empty() {
+            let written = unsafe {
+                esp_idf_sys::uart_write_bytes(self.port, buf.as_ptr() as *const _, buf.len() as _)
+            };
+            if written < 0 {
+                return Err(EspError(esp_idf_sys::ESP_FAIL));
+            }
+            buf = &buf[written as usize..];
+        }
+        Ok(())
+    }
+}
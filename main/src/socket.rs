@@ -0,0 +1,232 @@
+//! Thin wrappers around lwIP's BSD socket API.
+
+use esp_idf_sys::types::{c_int, c_void};
+use esp_idf_sys::{sockaddr, sockaddr_in, socklen_t};
+
+use crate::freertos_units::Duration;
+use crate::net::{Ipv4Addr, SocketAddrV4};
+
+/// An `errno` value reported by lwIP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Error(pub i32);
+
+impl Error {
+    fn last() -> Self {
+        Error(unsafe { *esp_idf_sys::__errno() })
+    }
+
+    /// Whether the operation failed because it timed out or would block.
+    pub fn is_timeout(self) -> bool {
+        self.0 == esp_idf_sys::EAGAIN as i32 || self.0 == esp_idf_sys::EWOULDBLOCK as i32
+    }
+}
+
+fn check(ret: c_int) -> Result<c_int, Error> {
+    if ret < 0 {
+        Err(Error::last())
+    } else {
+        Ok(ret)
+    }
+}
+
+fn to_raw_addr(addr: SocketAddrV4) -> sockaddr_in {
+    sockaddr_in {
+        sin_len: core::mem::size_of::<sockaddr_in>() as u8,
+        sin_family: esp_idf_sys::AF_INET as esp_idf_sys::sa_family_t,
+        sin_port: addr.port().to_be(),
+        sin_addr: esp_idf_sys::in_addr {
+            s_addr: u32::from_ne_bytes(addr.ip().octets()),
+        },
+        sin_zero: [0; 8],
+    }
+}
+
+fn from_raw_addr(addr: &sockaddr_in) -> SocketAddrV4 {
+    SocketAddrV4::new(
+        Ipv4Addr::from(addr.sin_addr.s_addr.to_ne_bytes()),
+        u16::from_be(addr.sin_port),
+    )
+}
+
+/// An owned socket descriptor, closed on drop.
+struct Socket(c_int);
+
+impl Socket {
+    fn new(ty: u32) -> Result<Socket, Error> {
+        let fd = check(unsafe {
+            esp_idf_sys::lwip_socket(esp_idf_sys::AF_INET as c_int, ty as c_int, 0)
+        })?;
+        Ok(Socket(fd))
+    }
+
+    fn setsockopt<T>(&self, level: u32, name: u32, value: &T) -> Result<(), Error> {
+        check(unsafe {
+            esp_idf_sys::lwip_setsockopt(
+                self.0,
+                level as c_int,
+                name as c_int,
+                value as *const T as *const c_void,
+                core::mem::size_of::<T>() as socklen_t,
+            )
+        })?;
+        Ok(())
+    }
+
+    fn bind(&self, addr: SocketAddrV4) -> Result<(), Error> {
+        let raw = to_raw_addr(addr);
+        check(unsafe {
+            esp_idf_sys::lwip_bind(
+                self.0,
+                &raw as *const sockaddr_in as *const sockaddr,
+                core::mem::size_of::<sockaddr_in>() as socklen_t,
+            )
+        })?;
+        Ok(())
+    }
+
+    fn set_timeout(&self, option: u32, timeout: Option<Duration>) -> Result<(), Error> {
+        let ms = timeout.map(|t| t.to_ms()).unwrap_or(0);
+        let tv = esp_idf_sys::timeval {
+            tv_sec: (ms / 1000) as _,
+            tv_usec: ((ms % 1000) * 1000) as _,
+        };
+        self.setsockopt(esp_idf_sys::SOL_SOCKET, option, &tv)
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { esp_idf_sys::lwip_close(self.0) };
+    }
+}
+
+/// A UDP socket.
+pub struct UdpSocket(Socket);
+
+impl UdpSocket {
+    /// Create a socket bound to `addr`.
+    pub fn bind(addr: SocketAddrV4) -> Result<UdpSocket, Error> {
+        let socket = Socket::new(esp_idf_sys::SOCK_DGRAM)?;
+        socket.bind(addr)?;
+        Ok(UdpSocket(socket))
+    }
+
+    /// Set how long `recv_from` blocks. `None` blocks indefinitely.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.0.set_timeout(esp_idf_sys::SO_RCVTIMEO, timeout)
+    }
+
+    /// Receive a datagram, returning its length and sender.
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddrV4), Error> {
+        let mut from: sockaddr_in = unsafe { core::mem::zeroed() };
+        let mut from_len = core::mem::size_of::<sockaddr_in>() as socklen_t;
+        let n = check(unsafe {
+            esp_idf_sys::lwip_recvfrom(
+                (self.0).0,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len() as _,
+                0,
+                &mut from as *mut sockaddr_in as *mut sockaddr,
+                &mut from_len,
+            )
+        })?;
+        Ok((n as usize, from_raw_addr(&from)))
+    }
+
+    /// Send a datagram to `addr`, returning the number of bytes sent.
+    pub fn send_to(&self, buf: &[u8], addr: SocketAddrV4) -> Result<usize, Error> {
+        let to = to_raw_addr(addr);
+        let n = check(unsafe {
+            esp_idf_sys::lwip_sendto(
+                (self.0).0,
+                buf.as_ptr() as *const c_void,
+                buf.len() as _,
+                0,
+                &to as *const sockaddr_in as *const sockaddr,
+                core::mem::size_of::<sockaddr_in>() as socklen_t,
+            )
+        })?;
+        Ok(n as usize)
+    }
+}
+
+/// A TCP socket listening for connections.
+pub struct TcpListener(Socket);
+
+impl TcpListener {
+    /// Create a socket bound to `addr` and start listening.
+    pub fn bind(addr: SocketAddrV4) -> Result<TcpListener, Error> {
+        let socket = Socket::new(esp_idf_sys::SOCK_STREAM)?;
+        let reuse: c_int = 1;
+        socket.setsockopt(esp_idf_sys::SOL_SOCKET, esp_idf_sys::SO_REUSEADDR, &reuse)?;
+        socket.bind(addr)?;
+        check(unsafe { esp_idf_sys::lwip_listen(socket.0, 4) })?;
+        Ok(TcpListener(socket))
+    }
+
+    /// Set how long `accept` blocks. `None` blocks indefinitely.
+    pub fn set_accept_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.0.set_timeout(esp_idf_sys::SO_RCVTIMEO, timeout)
+    }
+
+    /// Wait for a new connection.
+    pub fn accept(&self) -> Result<(TcpStream, SocketAddrV4), Error> {
+        let mut from: sockaddr_in = unsafe { core::mem::zeroed() };
+        let mut from_len = core::mem::size_of::<sockaddr_in>() as socklen_t;
+        let fd = check(unsafe {
+            esp_idf_sys::lwip_accept(
+                (self.0).0,
+                &mut from as *mut sockaddr_in as *mut sockaddr,
+                &mut from_len,
+            )
+        })?;
+        Ok((TcpStream(Socket(fd)), from_raw_addr(&from)))
+    }
+}
+
+/// A connected TCP socket.
+pub struct TcpStream(Socket);
+
+impl TcpStream {
+    /// Set how long `read` blocks. `None` blocks indefinitely.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.0.set_timeout(esp_idf_sys::SO_RCVTIMEO, timeout)
+    }
+
+    /// Set how long `write` blocks. `None` blocks indefinitely.
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.0.set_timeout(esp_idf_sys::SO_SNDTIMEO, timeout)
+    }
+
+    /// Read into `buf`, returning the number of bytes read. Returns 0 once the
+    /// peer has closed the connection.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let n = check(unsafe {
+            esp_idf_sys::lwip_recv((self.0).0, buf.as_mut_ptr() as *mut c_void, buf.len() as _, 0)
+        })?;
+        Ok(n as usize)
+    }
+
+    /// Write all of `buf`. Fails with `EIO` if the socket stops accepting
+    /// data.
+    pub fn write_all(&mut self, mut buf: &[u8]) -> Result<(), Error> {
+        while !buf.is_empty() {
+            let n = check(unsafe {
+                esp_idf_sys::lwip_send((self.0).0, buf.as_ptr() as *const c_void, buf.len() as _, 0)
+            })?;
+            // lwIP shouldn't accept nothing, but don't spin if it does.
+            if n == 0 {
+                return Err(Error(esp_idf_sys::EIO as i32));
+            }
+            buf = &buf[n as usize..];
+        }
+        Ok(())
+    }
+
+    /// Shut down the writing half, so the peer sees the end of the stream.
+    pub fn shutdown_write(&self) -> Result<(), Error> {
+        check(unsafe { esp_idf_sys::lwip_shutdown((self.0).0, esp_idf_sys::SHUT_WR as c_int) })?;
+        Ok(())
+    }
+}
+
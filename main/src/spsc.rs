@@ -0,0 +1,166 @@
+//! Lock-free single-producer, single-consumer queue.
+//!
+//! Suitable for handing data from driver callbacks (which must not block) to a
+//! regular task.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Create a queue that holds up to `capacity` items, returning its two ends.
+///
+/// Neither end can be cloned, so there is only ever one producer and one
+/// consumer; each can be sent to the context that uses it.
+pub fn channel<T: Send>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let queue = Arc::new(Queue::new(capacity));
+    (
+        Producer {
+            queue: queue.clone(),
+        },
+        Consumer { queue },
+    )
+}
+
+struct Queue<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// Index of the next slot to read, only written by the consumer.
+    head: AtomicUsize,
+    /// Index of the next slot to write, only written by the producer.
+    tail: AtomicUsize,
+}
+
+// The slots are only touched through a `Producer` or `Consumer`, and never
+// the same slot by both at once.
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Queue<T> {
+    fn new(capacity: usize) -> Self {
+        // One slot is kept empty to tell a full queue from an empty one.
+        let mut slots = Vec::with_capacity(capacity + 1);
+        slots.resize_with(capacity + 1, || UnsafeCell::new(MaybeUninit::uninit()));
+        Queue {
+            slots: slots.into_boxed_slice(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        if tail >= head {
+            tail - head
+        } else {
+            self.slots.len() - head + tail
+        }
+    }
+
+    fn next(&self, idx: usize) -> usize {
+        if idx + 1 == self.slots.len() {
+            0
+        } else {
+            idx + 1
+        }
+    }
+
+    /// The slot to write next and the tail index after it, or `None` if the
+    /// queue is full.
+    fn reserve(&self) -> Option<(usize, usize)> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = self.next(tail);
+        if next == self.head.load(Ordering::Acquire) {
+            None
+        } else {
+            Some((tail, next))
+        }
+    }
+
+    /// Dequeue the oldest item. Only called by the one consumer.
+    fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let item = unsafe { (*self.slots[head].get()).as_ptr().read() };
+        self.head.store(self.next(head), Ordering::Release);
+        Some(item)
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+/// The sending end of a queue.
+pub struct Producer<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T: Send> Producer<T> {
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+
+    /// Enqueue `item`, handing it back if the queue is full.
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        let (tail, next) = match self.queue.reserve() {
+            Some(r) => r,
+            None => return Err(item),
+        };
+        // Only the producer touches the slot at `tail` until it is published.
+        unsafe { *self.queue.slots[tail].get() = MaybeUninit::new(item) };
+        self.queue.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Initialize the next slot in place, which avoids building large items
+    /// on the stack of the calling context first. Returns `false` if the
+    /// queue is full.
+    ///
+    /// # Safety
+    ///
+    /// `init` must fully initialize the slot it is given.
+    pub unsafe fn push_with(&mut self, init: impl FnOnce(&mut MaybeUninit<T>)) -> bool {
+        let (tail, next) = match self.queue.reserve() {
+            Some(r) => r,
+            None => return false,
+        };
+        init(&mut *self.queue.slots[tail].get());
+        self.queue.tail.store(next, Ordering::Release);
+        true
+    }
+}
+
+/// The receiving end of a queue.
+pub struct Consumer<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T: Send> Consumer<T> {
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Dequeue the oldest item.
+    pub fn pop(&mut self) -> Option<T> {
+        self.queue.pop()
+    }
+}
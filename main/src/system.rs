@@ -0,0 +1,290 @@
+//! Restarting, reset reasons, chip and MAC information, and shutdown hooks.
+
+use core::fmt;
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use cstr_core::CStr;
+use esp_idf_hal::errors::EspError;
+
+use crate::net::MacAddress;
+
+/// Restart the chip, running the handlers registered with [`on_shutdown()`]
+/// first.
+///
+/// [`on_shutdown()`]: fn.on_shutdown.html
+pub fn restart() -> ! {
+    unsafe { esp_idf_sys::esp_restart() };
+    unreachable!("post-restart")
+}
+
+/// Why the chip last reset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetReason {
+    Unknown,
+    PowerOn,
+    /// The reset pin.
+    External,
+    /// `restart()` or `esp_restart`.
+    Software,
+    /// An exception or `abort`, including Rust panics.
+    Panic,
+    InterruptWatchdog,
+    TaskWatchdog,
+    OtherWatchdog,
+    /// Waking from deep sleep.
+    DeepSleep,
+    Brownout,
+    Sdio,
+}
+
+impl ResetReason {
+    /// The reason for the last reset.
+    pub fn get() -> ResetReason {
+        Self::from_raw(unsafe { esp_idf_sys::esp_reset_reason() })
+    }
+
+    pub fn from_raw(reason: esp_idf_sys::esp_reset_reason_t) -> ResetReason {
+        match reason {
+            esp_idf_sys::esp_reset_reason_t_ESP_RST_POWERON => ResetReason::PowerOn,
+            esp_idf_sys::esp_reset_reason_t_ESP_RST_EXT => ResetReason::External,
+            esp_idf_sys::esp_reset_reason_t_ESP_RST_SW => ResetReason::Software,
+            esp_idf_sys::esp_reset_reason_t_ESP_RST_PANIC => ResetReason::Panic,
+            esp_idf_sys::esp_reset_reason_t_ESP_RST_INT_WDT => ResetReason::InterruptWatchdog,
+            esp_idf_sys::esp_reset_reason_t_ESP_RST_TASK_WDT => ResetReason::TaskWatchdog,
+            esp_idf_sys::esp_reset_reason_t_ESP_RST_WDT => ResetReason::OtherWatchdog,
+            esp_idf_sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP => ResetReason::DeepSleep,
+            esp_idf_sys::esp_reset_reason_t_ESP_RST_BROWNOUT => ResetReason::Brownout,
+            esp_idf_sys::esp_reset_reason_t_ESP_RST_SDIO => ResetReason::Sdio,
+            _ => ResetReason::Unknown,
+        }
+    }
+
+    /// Whether the reset was caused by something going wrong, rather than
+    /// by power-on, a restart or waking up.
+    pub fn is_fault(self) -> bool {
+        matches!(
+            self,
+            ResetReason::Panic
+                | ResetReason::InterruptWatchdog
+                | ResetReason::TaskWatchdog
+                | ResetReason::OtherWatchdog
+                | ResetReason::Brownout
+        )
+    }
+}
+
+impl fmt::Display for ResetReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ResetReason::Unknown => "unknown",
+            ResetReason::PowerOn => "power-on",
+            ResetReason::External => "external pin",
+            ResetReason::Software => "software restart",
+            ResetReason::Panic => "panic",
+            ResetReason::InterruptWatchdog => "interrupt watchdog",
+            ResetReason::TaskWatchdog => "task watchdog",
+            ResetReason::OtherWatchdog => "other watchdog",
+            ResetReason::DeepSleep => "deep sleep wakeup",
+            ResetReason::Brownout => "brownout",
+            ResetReason::Sdio => "SDIO",
+        })
+    }
+}
+
+/// A network interface with its own MAC address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interface {
+    WifiStation,
+    WifiAccessPoint,
+    Bluetooth,
+    Ethernet,
+}
+
+impl Interface {
+    fn to_raw(self) -> esp_idf_sys::esp_mac_type_t {
+        match self {
+            Interface::WifiStation => esp_idf_sys::esp_mac_type_t_ESP_MAC_WIFI_STA,
+            Interface::WifiAccessPoint => esp_idf_sys::esp_mac_type_t_ESP_MAC_WIFI_SOFTAP,
+            Interface::Bluetooth => esp_idf_sys::esp_mac_type_t_ESP_MAC_BT,
+            Interface::Ethernet => esp_idf_sys::esp_mac_type_t_ESP_MAC_ETH,
+        }
+    }
+}
+
+/// The MAC address of `interface`, derived from the base MAC address as
+/// configured by `CONFIG_ESP32_UNIVERSAL_MAC_ADDRESSES`.
+pub fn mac_address(interface: Interface) -> Result<MacAddress, EspError> {
+    let mut mac = [0u8; 6];
+    EspError(unsafe { esp_idf_sys::esp_read_mac(mac.as_mut_ptr(), interface.to_raw()) })
+        .into_result()?;
+    Ok(MacAddress(mac))
+}
+
+/// The MAC address programmed into eFuse by Espressif.
+pub fn factory_mac_address() -> Result<MacAddress, EspError> {
+    let mut mac = [0u8; 6];
+    EspError(unsafe { esp_idf_sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) }).into_result()?;
+    Ok(MacAddress(mac))
+}
+
+/// The address that interface addresses are derived from: the one set with
+/// [`set_base_mac_address()`], or the factory address.
+///
+/// [`set_base_mac_address()`]: fn.set_base_mac_address.html
+pub fn base_mac_address() -> Result<MacAddress, EspError> {
+    let mut mac = [0u8; 6];
+    let ret = unsafe { esp_idf_sys::esp_base_mac_addr_get(mac.as_mut_ptr()) };
+    if ret == esp_idf_sys::ESP_ERR_INVALID_MAC as esp_idf_sys::esp_err_t {
+        return factory_mac_address();
+    }
+    EspError(ret).into_result()?;
+    Ok(MacAddress(mac))
+}
+
+/// Override the base MAC address. Must be called before the interfaces are
+/// initialized; multicast addresses are rejected.
+pub fn set_base_mac_address(mac: MacAddress) -> Result<(), EspError> {
+    let mut octets = mac.octets();
+    EspError(unsafe { esp_idf_sys::esp_base_mac_addr_set(octets.as_mut_ptr()) }).into_result()
+}
+
+/// The ESP-IDF version, e.g. `v4.0`.
+pub fn idf_version() -> &'static str {
+    unsafe { CStr::from_ptr(esp_idf_sys::esp_get_idf_version()) }
+        .to_str()
+        .unwrap_or("unknown")
+}
+
+/// The firmware's version, from `Cargo.toml`.
+pub fn firmware_version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+// `CHIP_FEATURE_*`, which are macros.
+const CHIP_FEATURE_EMB_FLASH: u32 = 1 << 0;
+const CHIP_FEATURE_WIFI_BGN: u32 = 1 << 1;
+const CHIP_FEATURE_BLE: u32 = 1 << 4;
+const CHIP_FEATURE_BT: u32 = 1 << 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChipInfo {
+    pub cores: u8,
+    pub revision: u8,
+    features: u32,
+}
+
+impl ChipInfo {
+    pub fn get() -> ChipInfo {
+        let mut info: esp_idf_sys::esp_chip_info_t = unsafe { mem::zeroed() };
+        unsafe { esp_idf_sys::esp_chip_info(&mut info) };
+        ChipInfo {
+            cores: info.cores,
+            revision: info.revision,
+            features: info.features,
+        }
+    }
+
+    pub fn has_wifi(&self) -> bool {
+        self.features & CHIP_FEATURE_WIFI_BGN != 0
+    }
+
+    pub fn has_bluetooth(&self) -> bool {
+        self.features & CHIP_FEATURE_BT != 0
+    }
+
+    pub fn has_ble(&self) -> bool {
+        self.features & CHIP_FEATURE_BLE != 0
+    }
+
+    pub fn has_embedded_flash(&self) -> bool {
+        self.features & CHIP_FEATURE_EMB_FLASH != 0
+    }
+}
+
+impl fmt::Display for ChipInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ESP32 rev {}, {} cores", self.revision, self.cores)?;
+        for (has, name) in &[
+            (self.has_wifi(), "WiFi"),
+            (self.has_bluetooth(), "BT"),
+            (self.has_ble(), "BLE"),
+            (self.has_embedded_flash(), "embedded flash"),
+        ] {
+            if *has {
+                write!(f, ", {}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Free heap in bytes.
+pub fn free_heap_size() -> u32 {
+    unsafe { esp_idf_sys::esp_get_free_heap_size() }
+}
+
+/// The lowest the free heap has been since boot.
+pub fn minimum_free_heap_size() -> u32 {
+    unsafe { esp_idf_sys::esp_get_minimum_free_heap_size() }
+}
+
+/// Most handlers that can be registered with [`on_shutdown()`].
+///
+/// [`on_shutdown()`]: fn.on_shutdown.html
+pub const MAX_SHUTDOWN_HANDLERS: usize = 8;
+
+static SHUTDOWN_HANDLERS: [AtomicUsize; MAX_SHUTDOWN_HANDLERS] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+static SHUTDOWN_REGISTERED: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "C" fn run_shutdown_handlers() {
+    // Most recently registered first, so later code can rely on what
+    // earlier code set up.
+    for slot in SHUTDOWN_HANDLERS.iter().rev() {
+        let handler = slot.load(Ordering::Acquire);
+        if handler != 0 {
+            let handler: fn() = mem::transmute(handler);
+            handler();
+        }
+    }
+}
+
+/// Run `handler` when the chip is restarted with [`restart()`], e.g. to
+/// flush state to flash. Handlers don't run after a panic or watchdog
+/// reset. Fails with `ESP_ERR_NO_MEM` once `MAX_SHUTDOWN_HANDLERS` are
+/// registered.
+///
+/// [`restart()`]: fn.restart.html
+pub fn on_shutdown(handler: fn()) -> Result<(), EspError> {
+    // ESP-IDF only has room for a few handlers, so register one that runs
+    // ours.
+    if !SHUTDOWN_REGISTERED.swap(true, Ordering::AcqRel) {
+        let ret =
+            unsafe { esp_idf_sys::esp_register_shutdown_handler(Some(run_shutdown_handlers)) };
+        if let Err(e) = EspError(ret).into_result() {
+            SHUTDOWN_REGISTERED.store(false, Ordering::Release);
+            return Err(e);
+        }
+    }
+
+    for slot in SHUTDOWN_HANDLERS.iter() {
+        if slot
+            .compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+    Err(EspError(
+        esp_idf_sys::ESP_ERR_NO_MEM as esp_idf_sys::esp_err_t,
+    ))
+}
@@ -0,0 +1,788 @@
+//! The UART driver.
+//!
+//! ```ignore
+//! let mut uart = Uart::new(Port::Uart2).baud_rate(9600).pins(17, 16).start()?;
+//! uart.write(b"AT\r\n")?;
+//! let mut buf = [0; 64];
+//! let n = uart.read_timeout(&mut buf, Duration::ms(100))?;
+//! ```
+//!
+//! UART0 is the console; installing a driver on it takes over `println!`'s
+//! output.
+//!
+//! For RS-485, connect the transceiver's driver enable to the RTS pin, start
+//! in one of the RS-485 [`Mode`]s, and send with [`Uart::transmit()`].
+//!
+//! [`Mode`]: enum.Mode.html
+//! [`Uart::transmit()`]: struct.Uart.html#method.transmit
+
+use core::fmt;
+use core::mem;
+use core::ptr;
+
+use esp_idf_hal::errors::EspError;
+use esp_idf_sys::types::c_void;
+
+use crate::freertos_units::{Duration, DurationTicks};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Port {
+    Uart0,
+    Uart1,
+    Uart2,
+}
+
+impl Port {
+    pub(crate) fn to_raw(self) -> esp_idf_sys::uart_port_t {
+        match self {
+            Port::Uart0 => esp_idf_sys::uart_port_t_UART_NUM_0,
+            Port::Uart1 => esp_idf_sys::uart_port_t_UART_NUM_1,
+            Port::Uart2 => esp_idf_sys::uart_port_t_UART_NUM_2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+impl DataBits {
+    fn to_raw(self) -> esp_idf_sys::uart_word_length_t {
+        match self {
+            DataBits::Five => esp_idf_sys::uart_word_length_t_UART_DATA_5_BITS,
+            DataBits::Six => esp_idf_sys::uart_word_length_t_UART_DATA_6_BITS,
+            DataBits::Seven => esp_idf_sys::uart_word_length_t_UART_DATA_7_BITS,
+            DataBits::Eight => esp_idf_sys::uart_word_length_t_UART_DATA_8_BITS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+impl Parity {
+    fn to_raw(self) -> esp_idf_sys::uart_parity_t {
+        match self {
+            Parity::None => esp_idf_sys::uart_parity_t_UART_PARITY_DISABLE,
+            Parity::Even => esp_idf_sys::uart_parity_t_UART_PARITY_EVEN,
+            Parity::Odd => esp_idf_sys::uart_parity_t_UART_PARITY_ODD,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    OneAndHalf,
+    Two,
+}
+
+impl StopBits {
+    fn to_raw(self) -> esp_idf_sys::uart_stop_bits_t {
+        match self {
+            StopBits::One => esp_idf_sys::uart_stop_bits_t_UART_STOP_BITS_1,
+            StopBits::OneAndHalf => esp_idf_sys::uart_stop_bits_t_UART_STOP_BITS_1_5,
+            StopBits::Two => esp_idf_sys::uart_stop_bits_t_UART_STOP_BITS_2,
+        }
+    }
+}
+
+/// Hardware flow control.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    /// Deassert RTS when the receive FIFO is nearly full.
+    Rts,
+    /// Only transmit while CTS is asserted.
+    Cts,
+    RtsCts,
+}
+
+impl FlowControl {
+    fn to_raw(self) -> esp_idf_sys::uart_hw_flowcontrol_t {
+        match self {
+            FlowControl::None => esp_idf_sys::uart_hw_flowcontrol_t_UART_HW_FLOWCTRL_DISABLE,
+            FlowControl::Rts => esp_idf_sys::uart_hw_flowcontrol_t_UART_HW_FLOWCTRL_RTS,
+            FlowControl::Cts => esp_idf_sys::uart_hw_flowcontrol_t_UART_HW_FLOWCTRL_CTS,
+            FlowControl::RtsCts => esp_idf_sys::uart_hw_flowcontrol_t_UART_HW_FLOWCTRL_CTS_RTS,
+        }
+    }
+}
+
+/// How the port drives the line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Uart,
+    /// Half-duplex RS-485: the driver asserts RTS, wired to the
+    /// transceiver's driver enable, while transmitting.
+    Rs485HalfDuplex,
+    /// Like `Rs485HalfDuplex`, but the receiver stays on while transmitting
+    /// so the echo can be compared with what was sent, to detect
+    /// collisions.
+    Rs485CollisionDetect,
+}
+
+impl Mode {
+    fn to_raw(self) -> esp_idf_sys::uart_mode_t {
+        match self {
+            Mode::Uart => esp_idf_sys::uart_mode_t_UART_MODE_UART,
+            Mode::Rs485HalfDuplex => esp_idf_sys::uart_mode_t_UART_MODE_RS485_HALF_DUPLEX,
+            Mode::Rs485CollisionDetect => esp_idf_sys::uart_mode_t_UART_MODE_RS485_COLLISION_DETECT,
+        }
+    }
+
+    pub fn is_rs485(self) -> bool {
+        self != Mode::Uart
+    }
+}
+
+/// The result of [`Uart::transmit()`].
+///
+/// [`Uart::transmit()`]: struct.Uart.html#method.transmit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transmission {
+    Sent,
+    /// Another node transmitted at the same time, so the data was
+    /// probably corrupted.
+    Collided,
+}
+
+/// Helper for configuring and installing the driver. Instantiate with
+/// [`Uart::new()`].
+///
+/// [`Uart::new()`]: struct.Uart.html#method.new
+#[derive(Clone, Copy, Debug)]
+pub struct UartBuilder {
+    port: Port,
+    baud_rate: u32,
+    data_bits: DataBits,
+    parity: Parity,
+    stop_bits: StopBits,
+    flow_control: FlowControl,
+    tx_pin: i32,
+    rx_pin: i32,
+    rts_pin: i32,
+    cts_pin: i32,
+    rx_buffer_size: usize,
+    tx_buffer_size: usize,
+    event_queue_size: usize,
+    mode: Mode,
+    rx_timeout: Option<u8>,
+}
+
+impl UartBuilder {
+    /// Defaults to 115200.
+    pub fn baud_rate(self, baud_rate: u32) -> Self {
+        UartBuilder { baud_rate, ..self }
+    }
+
+    /// Defaults to eight.
+    pub fn data_bits(self, data_bits: DataBits) -> Self {
+        UartBuilder { data_bits, ..self }
+    }
+
+    /// Defaults to none.
+    pub fn parity(self, parity: Parity) -> Self {
+        UartBuilder { parity, ..self }
+    }
+
+    /// Defaults to one.
+    pub fn stop_bits(self, stop_bits: StopBits) -> Self {
+        UartBuilder { stop_bits, ..self }
+    }
+
+    /// Defaults to none. The RTS and CTS pins must be set for the lines
+    /// used.
+    pub fn flow_control(self, flow_control: FlowControl) -> Self {
+        UartBuilder {
+            flow_control,
+            ..self
+        }
+    }
+
+    /// Route TX and RX to these GPIOs. Each port has default pins, used
+    /// if this isn't called.
+    pub fn pins(self, tx_pin: i32, rx_pin: i32) -> Self {
+        UartBuilder {
+            tx_pin,
+            rx_pin,
+            ..self
+        }
+    }
+
+    pub fn rts_pin(self, rts_pin: i32) -> Self {
+        UartBuilder { rts_pin, ..self }
+    }
+
+    pub fn cts_pin(self, cts_pin: i32) -> Self {
+        UartBuilder { cts_pin, ..self }
+    }
+
+    /// Bytes received but not yet read. Must be larger than the 128-byte
+    /// hardware FIFO; defaults to 1024.
+    pub fn rx_buffer_size(self, rx_buffer_size: usize) -> Self {
+        UartBuilder {
+            rx_buffer_size,
+            ..self
+        }
+    }
+
+    /// Bytes written but not yet sent. Either zero, in which case writes
+    /// block until everything is in the hardware FIFO, or larger than the
+    /// FIFO. Defaults to zero.
+    pub fn tx_buffer_size(self, tx_buffer_size: usize) -> Self {
+        UartBuilder {
+            tx_buffer_size,
+            ..self
+        }
+    }
+
+    /// Post [`UartEvent`]s to a queue of this many, to be received with
+    /// [`Uart::events()`]. Off by default.
+    ///
+    /// [`UartEvent`]: enum.UartEvent.html
+    /// [`Uart::events()`]: struct.Uart.html#method.events
+    pub fn event_queue_size(self, event_queue_size: usize) -> Self {
+        UartBuilder {
+            event_queue_size,
+            ..self
+        }
+    }
+
+    /// Defaults to `Mode::Uart`. The RS-485 modes need the RTS pin, and
+    /// no flow control.
+    pub fn mode(self, mode: Mode) -> Self {
+        UartBuilder { mode, ..self }
+    }
+
+    /// See [`Uart::set_rx_timeout()`]. Defaults to ESP-IDF's default.
+    ///
+    /// [`Uart::set_rx_timeout()`]: struct.Uart.html#method.set_rx_timeout
+    pub fn rx_timeout(self, symbols: u8) -> Self {
+        UartBuilder {
+            rx_timeout: Some(symbols),
+            ..self
+        }
+    }
+
+    /// Configure the port and install the driver. Fails if the port
+    /// already has a driver.
+    pub fn start(self) -> Result<Uart, EspError> {
+        let fifo_len = esp_idf_sys::UART_FIFO_LEN as usize;
+        if self.rx_buffer_size <= fifo_len
+            || (self.tx_buffer_size != 0 && self.tx_buffer_size <= fifo_len)
+            || (self.mode.is_rs485() && self.flow_control != FlowControl::None)
+        {
+            return Err(EspError(
+                esp_idf_sys::ESP_ERR_INVALID_ARG as esp_idf_sys::esp_err_t,
+            ));
+        }
+
+        let port = self.port.to_raw();
+        let config = esp_idf_sys::uart_config_t {
+            baud_rate: self.baud_rate as _,
+            data_bits: self.data_bits.to_raw(),
+            parity: self.parity.to_raw(),
+            stop_bits: self.stop_bits.to_raw(),
+            flow_ctrl: self.flow_control.to_raw(),
+            // Leave a little room in the FIFO for bytes already on the wire
+            // when RTS is deasserted.
+            rx_flow_ctrl_thresh: (fifo_len - 8) as u8,
+            use_ref_tick: false,
+        };
+        let mut events = ptr::null_mut();
+        unsafe {
+            EspError(esp_idf_sys::uart_param_config(port, &config)).into_result()?;
+            EspError(esp_idf_sys::uart_set_pin(
+                port,
+                self.tx_pin,
+                self.rx_pin,
+                self.rts_pin,
+                self.cts_pin,
+            ))
+            .into_result()?;
+            EspError(esp_idf_sys::uart_driver_install(
+                port,
+                self.rx_buffer_size as _,
+                self.tx_buffer_size as _,
+                self.event_queue_size as _,
+                if self.event_queue_size == 0 {
+                    ptr::null_mut()
+                } else {
+                    &mut events
+                },
+                0,
+            ))
+            .into_result()?;
+        }
+
+        // `uart` owns the driver now; returning early below deletes it.
+        let mut uart = Uart {
+            port: self.port,
+            events,
+            mode: self.mode,
+        };
+        EspError(unsafe { esp_idf_sys::uart_set_mode(port, self.mode.to_raw()) }).into_result()?;
+        if let Some(symbols) = self.rx_timeout {
+            uart.set_rx_timeout(symbols)?;
+        }
+        Ok(uart)
+    }
+}
+
+/// A UART port with the driver installed, returned by
+/// [`UartBuilder::start()`]. The driver is deleted when this is dropped.
+///
+/// [`UartBuilder::start()`]: struct.UartBuilder.html#method.start
+#[derive(Debug)]
+pub struct Uart {
+    port: Port,
+    /// Owned by the driver; null without an event queue.
+    events: esp_idf_sys::QueueHandle_t,
+    mode: Mode,
+}
+unsafe impl Send for Uart {}
+
+impl Uart {
+    pub fn new(port: Port) -> UartBuilder {
+        UartBuilder {
+            port,
+            baud_rate: 115_200,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            tx_pin: esp_idf_sys::UART_PIN_NO_CHANGE,
+            rx_pin: esp_idf_sys::UART_PIN_NO_CHANGE,
+            rts_pin: esp_idf_sys::UART_PIN_NO_CHANGE,
+            cts_pin: esp_idf_sys::UART_PIN_NO_CHANGE,
+            rx_buffer_size: 1024,
+            tx_buffer_size: 0,
+            event_queue_size: 0,
+            mode: Mode::Uart,
+            rx_timeout: None,
+        }
+    }
+
+    pub fn port(&self) -> Port {
+        self.port
+    }
+
+    /// Wait for at least one byte, then read as many as are buffered, up
+    /// to `buf.len()`. Returns how many were read.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, EspError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let n = self.read_timeout(&mut buf[..1], Duration::infinite())?;
+        let available = self.buffered_len()?.min(buf.len() - 1);
+        Ok(n + self.read_timeout(&mut buf[1..=available], Duration::zero())?)
+    }
+
+    /// Read until `buf` is full or `timeout` passes. Returns how many bytes
+    /// were read, which is zero on timeout.
+    pub fn read_timeout(
+        &mut self,
+        buf: &mut [u8],
+        timeout: impl DurationTicks,
+    ) -> Result<usize, EspError> {
+        let n = unsafe {
+            esp_idf_sys::uart_read_bytes(
+                self.port.to_raw(),
+                buf.as_mut_ptr(),
+                buf.len() as _,
+                timeout.to_ticks(),
+            )
+        };
+        if n < 0 {
+            return Err(EspError(esp_idf_sys::ESP_FAIL as esp_idf_sys::esp_err_t));
+        }
+        Ok(n as usize)
+    }
+
+    /// Bytes received and waiting to be read.
+    pub fn buffered_len(&self) -> Result<usize, EspError> {
+        let mut len = 0;
+        EspError(unsafe { esp_idf_sys::uart_get_buffered_data_len(self.port.to_raw(), &mut len) })
+            .into_result()?;
+        Ok(len as usize)
+    }
+
+    /// Queue all of `data` for sending. Blocks until it has been copied
+    /// into the transmit buffer, or into the FIFO if there isn't one.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), EspError> {
+        let n = unsafe {
+            esp_idf_sys::uart_write_bytes(
+                self.port.to_raw(),
+                data.as_ptr() as *const _,
+                data.len() as _,
+            )
+        };
+        if n < 0 {
+            return Err(EspError(esp_idf_sys::ESP_FAIL as esp_idf_sys::esp_err_t));
+        }
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for everything written to be sent. Fails with
+    /// `ESP_ERR_TIMEOUT` if it wasn't.
+    pub fn flush(&mut self, timeout: impl DurationTicks) -> Result<(), EspError> {
+        EspError(unsafe { esp_idf_sys::uart_wait_tx_done(self.port.to_raw(), timeout.to_ticks()) })
+            .into_result()
+    }
+
+    /// Write `data` and wait up to `timeout` for it to be sent. In
+    /// `Mode::Rs485CollisionDetect`, also check whether another node
+    /// transmitted at the same time.
+    pub fn transmit(
+        &mut self,
+        data: &[u8],
+        timeout: impl DurationTicks,
+    ) -> Result<Transmission, EspError> {
+        self.write(data)?;
+        self.flush(timeout)?;
+        if self.mode == Mode::Rs485CollisionDetect && self.collision_detected()? {
+            Ok(Transmission::Collided)
+        } else {
+            Ok(Transmission::Sent)
+        }
+    }
+
+    /// Whether a collision was detected during the last transmission, in
+    /// the RS-485 modes.
+    pub fn collision_detected(&self) -> Result<bool, EspError> {
+        let mut collided = false;
+        EspError(unsafe {
+            esp_idf_sys::uart_get_collision_flag(self.port.to_raw(), &mut collided)
+        })
+        .into_result()?;
+        Ok(collided)
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Set the RTS line, for driving an RS-485 transceiver by hand in
+    /// `Mode::Uart`. In the RS-485 modes the driver controls it.
+    pub fn set_rts(&mut self, high: bool) -> Result<(), EspError> {
+        EspError(unsafe { esp_idf_sys::uart_set_rts(self.port.to_raw(), high as _) }).into_result()
+    }
+
+    /// Consider a reception finished after this many symbol times, about
+    /// eleven bits each, of silence, at most 126. Buffered data is then
+    /// made available, and a `UartEvent::Data` posted, without waiting for
+    /// the FIFO to fill.
+    pub fn set_rx_timeout(&mut self, symbols: u8) -> Result<(), EspError> {
+        EspError(unsafe { esp_idf_sys::uart_set_rx_timeout(self.port.to_raw(), symbols) })
+            .into_result()
+    }
+
+    /// Discard everything received but not yet read.
+    pub fn clear_input(&mut self) -> Result<(), EspError> {
+        EspError(unsafe { esp_idf_sys::uart_flush_input(self.port.to_raw()) }).into_result()
+    }
+
+    pub fn baud_rate(&self) -> Result<u32, EspError> {
+        let mut baud_rate = 0;
+        EspError(unsafe { esp_idf_sys::uart_get_baudrate(self.port.to_raw(), &mut baud_rate) })
+            .into_result()?;
+        Ok(baud_rate)
+    }
+
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), EspError> {
+        EspError(unsafe { esp_idf_sys::uart_set_baudrate(self.port.to_raw(), baud_rate) })
+            .into_result()
+    }
+
+    /// The driver's events, if it was started with an event queue.
+    pub fn events(&self) -> Option<UartEvents<'_>> {
+        if self.events.is_null() {
+            None
+        } else {
+            Some(UartEvents { uart: self })
+        }
+    }
+
+    fn receive_event(&self, ticks: esp_idf_sys::TickType_t) -> Option<UartEvent> {
+        let mut event: esp_idf_sys::uart_event_t = unsafe { mem::zeroed() };
+        // `xQueueReceive` is a macro.
+        let received = unsafe {
+            esp_idf_sys::xQueueGenericReceive(
+                self.events,
+                &mut event as *mut _ as *mut c_void,
+                ticks,
+                0,
+            )
+        };
+        if received == 1 {
+            Some(UartEvent::from_raw(&event))
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for Uart {
+    fn drop(&mut self) {
+        unsafe { esp_idf_sys::uart_driver_delete(self.port.to_raw()) };
+    }
+}
+
+/// Something that happened on a UART, posted by the driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UartEvent {
+    /// This many bytes were received and can be read.
+    Data(usize),
+    /// A break was received.
+    Break,
+    /// The receive buffer is full, so incoming data is being lost. Read
+    /// faster, or clear the input.
+    BufferFull,
+    /// The hardware FIFO overflowed, so incoming data was lost.
+    FifoOverflow,
+    FrameError,
+    ParityError,
+    /// Data and a break were sent.
+    DataBreak,
+    /// The pattern enabled by a [`LineReader`] was received.
+    ///
+    /// [`LineReader`]: struct.LineReader.html
+    PatternDetected,
+    /// An event this wrapper doesn't know about.
+    Other(esp_idf_sys::uart_event_type_t),
+}
+
+impl UartEvent {
+    fn from_raw(event: &esp_idf_sys::uart_event_t) -> UartEvent {
+        match event.type_ {
+            esp_idf_sys::uart_event_type_t_UART_DATA => UartEvent::Data(event.size as usize),
+            esp_idf_sys::uart_event_type_t_UART_BREAK => UartEvent::Break,
+            esp_idf_sys::uart_event_type_t_UART_BUFFER_FULL => UartEvent::BufferFull,
+            esp_idf_sys::uart_event_type_t_UART_FIFO_OVF => UartEvent::FifoOverflow,
+            esp_idf_sys::uart_event_type_t_UART_FRAME_ERR => UartEvent::FrameError,
+            esp_idf_sys::uart_event_type_t_UART_PARITY_ERR => UartEvent::ParityError,
+            esp_idf_sys::uart_event_type_t_UART_DATA_BREAK => UartEvent::DataBreak,
+            esp_idf_sys::uart_event_type_t_UART_PATTERN_DET => UartEvent::PatternDetected,
+            other => UartEvent::Other(other),
+        }
+    }
+
+    /// Whether received data was, or is being, lost.
+    pub fn is_overflow(&self) -> bool {
+        matches!(self, UartEvent::BufferFull | UartEvent::FifoOverflow)
+    }
+}
+
+/// The events posted for a [`Uart`], returned by [`Uart::events()`].
+/// Iterating blocks until the next event.
+///
+/// [`Uart`]: struct.Uart.html
+/// [`Uart::events()`]: struct.Uart.html#method.events
+#[derive(Debug)]
+pub struct UartEvents<'a> {
+    uart: &'a Uart,
+}
+
+impl UartEvents<'_> {
+    /// Wait up to `timeout` for the next event.
+    pub fn recv(&mut self, timeout: impl DurationTicks) -> Option<UartEvent> {
+        self.uart.receive_event(timeout.to_ticks())
+    }
+}
+
+impl Iterator for UartEvents<'_> {
+    type Item = UartEvent;
+
+    fn next(&mut self) -> Option<UartEvent> {
+        loop {
+            if let Some(event) = self.recv(Duration::infinite()) {
+                return Some(event);
+            }
+        }
+    }
+}
+
+/// Pattern positions remembered by the driver; lines beyond this many
+/// unread ones are lost.
+const PATTERN_QUEUE_LEN: i32 = 16;
+
+/// Reads delimited lines, such as NMEA sentences or modem responses, using
+/// the UART's pattern detection, so waiting for a line doesn't poll.
+///
+/// ```ignore
+/// let uart = Uart::new(Port::Uart2).pins(17, 16).event_queue_size(20).start()?;
+/// let mut modem = LineReader::new(uart, b'\n')?;
+/// modem.uart().write(b"AT\r\n")?;
+/// let mut line = [0; 128];
+/// if let Some(n) = modem.read_line(&mut line, Duration::ms(1000))? { .. }
+/// ```
+#[derive(Debug)]
+pub struct LineReader {
+    uart: Uart,
+    delimiter: u8,
+}
+
+impl LineReader {
+    /// Split what `uart` receives on `delimiter`. The UART must have been
+    /// started with an event queue, or this fails with
+    /// `ESP_ERR_INVALID_STATE`.
+    pub fn new(uart: Uart, delimiter: u8) -> Result<LineReader, EspError> {
+        if uart.events.is_null() {
+            return Err(EspError(
+                esp_idf_sys::ESP_ERR_INVALID_STATE as esp_idf_sys::esp_err_t,
+            ));
+        }
+        let port = uart.port.to_raw();
+        unsafe {
+            // A single delimiter character, with the timings from ESP-IDF's
+            // `uart_events` example.
+            EspError(esp_idf_sys::uart_enable_pattern_det_intr(
+                port,
+                delimiter as _,
+                1,
+                9,
+                0,
+                0,
+            ))
+            .into_result()?;
+            EspError(esp_idf_sys::uart_pattern_queue_reset(
+                port,
+                PATTERN_QUEUE_LEN,
+            ))
+            .into_result()?;
+        }
+        Ok(LineReader { uart, delimiter })
+    }
+
+    /// The UART, e.g. for sending commands.
+    pub fn uart(&mut self) -> &mut Uart {
+        &mut self.uart
+    }
+
+    /// Stop detecting lines and give back the UART.
+    pub fn into_inner(self) -> Uart {
+        unsafe { esp_idf_sys::uart_disable_pattern_det_intr(self.uart.port.to_raw()) };
+        self.uart
+    }
+
+    /// Wait up to `timeout` for a line and copy it, without the delimiter,
+    /// into `buf`. With a `\n` delimiter, a trailing `\r` is removed too.
+    /// Returns the line's length, or `None` on timeout.
+    ///
+    /// Fails with `ESP_ERR_INVALID_SIZE` if the line didn't fit in `buf`;
+    /// the rest of it is discarded. Fails with `ESP_FAIL` if received data
+    /// was lost because it wasn't read quickly enough; the input is then
+    /// cleared, so the next line may be partial.
+    pub fn read_line(
+        &mut self,
+        buf: &mut [u8],
+        timeout: impl DurationTicks,
+    ) -> Result<Option<usize>, EspError> {
+        let port = self.uart.port.to_raw();
+        let timeout = timeout.to_ticks();
+        let infinite = Duration::infinite().to_ticks();
+        let start = unsafe { esp_idf_sys::xTaskGetTickCount() };
+        loop {
+            // Check for a line before waiting, as events may have been
+            // dropped while the queue was full.
+            let pos = unsafe { esp_idf_sys::uart_pattern_pop_pos(port) };
+            if pos >= 0 {
+                return self.take_line(pos as usize, buf).map(Some);
+            }
+
+            let elapsed = unsafe { esp_idf_sys::xTaskGetTickCount() }.wrapping_sub(start);
+            let remaining = if timeout == infinite {
+                infinite
+            } else {
+                timeout.saturating_sub(elapsed)
+            };
+            match self.uart.receive_event(remaining) {
+                None if remaining == 0 => return Ok(None),
+                None => {}
+                Some(event) if event.is_overflow() => {
+                    self.uart.clear_input()?;
+                    EspError(unsafe {
+                        esp_idf_sys::uart_pattern_queue_reset(port, PATTERN_QUEUE_LEN)
+                    })
+                    .into_result()?;
+                    return Err(EspError(esp_idf_sys::ESP_FAIL as esp_idf_sys::esp_err_t));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Read a `len`-byte line and its delimiter from the receive buffer.
+    fn take_line(&mut self, len: usize, buf: &mut [u8]) -> Result<usize, EspError> {
+        // The bytes are already buffered, so this shouldn't need to wait.
+        let wait = Duration::ms(100);
+        let kept = len.min(buf.len());
+        let mut n = self.uart.read_timeout(&mut buf[..kept], wait)?;
+
+        let mut rest = len - kept + 1;
+        let mut scratch = [0; 32];
+        while rest > 0 {
+            let chunk = rest.min(scratch.len());
+            let read = self.uart.read_timeout(&mut scratch[..chunk], wait)?;
+            if read == 0 {
+                break;
+            }
+            rest -= read;
+        }
+
+        if kept < len {
+            return Err(EspError(
+                esp_idf_sys::ESP_ERR_INVALID_SIZE as esp_idf_sys::esp_err_t,
+            ));
+        }
+        if self.delimiter == b'\n' && n > 0 && buf[n - 1] == b'\r' {
+            n -= 1;
+        }
+        Ok(n)
+    }
+}
+
+impl embedded_hal::serial::Read<u8> for Uart {
+    type Error = EspError;
+
+    fn read(&mut self) -> nb::Result<u8, EspError> {
+        let mut byte = 0;
+        match self.read_timeout(core::slice::from_mut(&mut byte), Duration::zero())? {
+            0 => Err(nb::Error::WouldBlock),
+            _ => Ok(byte),
+        }
+    }
+}
+
+impl embedded_hal::serial::Write<u8> for Uart {
+    type Error = EspError;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), EspError> {
+        Uart::write(self, &[word])?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), EspError> {
+        match Uart::flush(self, Duration::zero()) {
+            Err(e) if e.0 == esp_idf_sys::ESP_ERR_TIMEOUT as esp_idf_sys::esp_err_t => {
+                Err(nb::Error::WouldBlock)
+            }
+            r => Ok(r?),
+        }
+    }
+}
+
+impl fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        Uart::write(self, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}